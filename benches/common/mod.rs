@@ -0,0 +1,24 @@
+use simple_csv_tx_engine::exporter::ClientStateExporter;
+use simple_csv_tx_engine::importer::TransactionImporter;
+use simple_csv_tx_engine::model::{ClientState, Transaction};
+
+// use a hardcoded seed for deterministic benches
+pub const SEED: u64 = 0xbeef00666;
+
+pub struct PredefinedTransactionImporter {
+    pub transactions: Vec<Transaction>,
+}
+
+impl TransactionImporter for &PredefinedTransactionImporter {
+    fn deserialize(&mut self) -> Box<dyn Iterator<Item = anyhow::Result<Transaction>> + '_> {
+        Box::new(self.transactions.iter().map(|tx| Ok(*tx)))
+    }
+}
+
+pub struct NullClientStateExporter;
+
+impl ClientStateExporter for NullClientStateExporter {
+    fn serialize(&mut self, _client_state: &ClientState) -> anyhow::Result<()> {
+        Ok(())
+    }
+}
@@ -0,0 +1,204 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use simple_csv_tx_engine::exporter::ClientStateCsvExporter;
+use simple_csv_tx_engine::generator::{
+    write_rows, write_transactions, TransactionGenerator, TransactionMix,
+};
+use simple_csv_tx_engine::importer::{TransactionCsvImporter, TransactionImporter};
+use simple_csv_tx_engine::model::Transaction;
+use simple_csv_tx_engine::service::{ProcessingContext, TransactionProcessor};
+use std::io::sink;
+
+use common::{NullClientStateExporter, PredefinedTransactionImporter, SEED};
+
+mod common;
+
+// full `u16` client ID range, regardless of the client ID width
+const MANY_CLIENTS: u64 = 1 << 16;
+
+fn generate(generator: TransactionGenerator, size: u64) -> Vec<Transaction> {
+    generator.take(size as usize).collect()
+}
+
+fn process(importer: &PredefinedTransactionImporter) {
+    TransactionProcessor::new(importer, NullClientStateExporter)
+        .process_transactions()
+        .expect("Unexpected processing error!");
+}
+
+// parses CSV on its own, and along with processing
+fn csv_parsing(c: &mut Criterion) {
+    let mut group = c.benchmark_group("csv_parsing");
+
+    for size in [1000, 10000, 100000].iter().copied() {
+        let mut csv = Vec::new();
+        write_transactions(
+            &mut csv,
+            TransactionGenerator::new(SEED).take(size as usize),
+        )
+        .expect("Unexpected generator error!");
+
+        group.throughput(Throughput::Elements(size));
+        group.bench_with_input(BenchmarkId::new("import", size), &csv, |b, csv| {
+            b.iter(|| {
+                let mut importer = TransactionCsvImporter::from_reader(csv.as_slice());
+                for transaction in importer.deserialize() {
+                    transaction.expect("Unexpected import error!");
+                }
+            });
+        });
+        group.bench_with_input(BenchmarkId::new("process", size), &csv, |b, csv| {
+            b.iter(|| {
+                TransactionProcessor::new(
+                    TransactionCsvImporter::from_reader(csv.as_slice()),
+                    NullClientStateExporter,
+                )
+                .process_transactions()
+                .expect("Unexpected processing error!");
+            });
+        });
+    }
+
+    group.finish();
+}
+
+// exports a context with a few deposits per client, so nearly all of them have a state
+fn csv_export(c: &mut Criterion) {
+    let mut group = c.benchmark_group("csv_export");
+
+    for clients in [1000, 10000, MANY_CLIENTS].iter().copied() {
+        let mix = TransactionMix {
+            deposit: 1,
+            withdrawal: 0,
+            dispute: 0,
+            resolve: 0,
+            chargeback: 0,
+        };
+        let generator = TransactionGenerator::new(SEED)
+            .with_clients(clients)
+            .with_mix(mix);
+
+        let mut context = ProcessingContext::default();
+        let limits = Default::default();
+        for transaction in generate(generator, clients * 4) {
+            context
+                .apply(&transaction, &limits)
+                .expect("Unexpected processing error!");
+        }
+
+        group.throughput(Throughput::Elements(context.client_states().count() as u64));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(clients),
+            &context,
+            |b, context| {
+                b.iter(|| {
+                    let mut exporter = ClientStateCsvExporter::new(sink(), Default::default());
+                    context
+                        .export_client_states(&mut exporter)
+                        .expect("Unexpected export error!");
+                });
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// most transactions are disputes, resolves and chargebacks of earlier deposits
+fn disputes(c: &mut Criterion) {
+    let mut group = c.benchmark_group("disputes");
+    let mix = TransactionMix {
+        deposit: 3,
+        withdrawal: 1,
+        dispute: 3,
+        resolve: 2,
+        chargeback: 1,
+    };
+
+    for size in [1000, 10000, 100000].iter().copied() {
+        let importer = PredefinedTransactionImporter {
+            transactions: generate(TransactionGenerator::new(SEED).with_mix(mix), size),
+        };
+
+        group.throughput(Throughput::Elements(size));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &importer,
+            |b, importer| {
+                b.iter(|| process(importer));
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// spreads transactions over the whole client ID range
+fn client_cardinality(c: &mut Criterion) {
+    let mut group = c.benchmark_group("client_cardinality");
+    group.sample_size(10);
+
+    for size in [100000, 1000000].iter().copied() {
+        let importer = PredefinedTransactionImporter {
+            transactions: generate(
+                TransactionGenerator::new(SEED).with_clients(MANY_CLIENTS),
+                size,
+            ),
+        };
+
+        group.throughput(Throughput::Elements(size));
+        group.bench_with_input(
+            BenchmarkId::from_parameter(size),
+            &importer,
+            |b, importer| {
+                b.iter(|| process(importer));
+            },
+        );
+    }
+
+    group.finish();
+}
+
+// applied transactions compared to ones rejected by the processor, and to malformed rows rejected
+// by the importer, including the report of rejections
+fn error_path(c: &mut Criterion) {
+    let mut group = c.benchmark_group("error_path");
+    let size = 10000;
+
+    for (name, invalid_ratio, malformed_ratio) in [
+        ("applied", 0.0, 0.0),
+        ("rejected", 1.0, 0.0),
+        ("malformed", 0.0, 1.0),
+    ] {
+        let generator = TransactionGenerator::new(SEED)
+            .with_invalid_ratio(invalid_ratio)
+            .with_malformed_ratio(malformed_ratio);
+        let mut csv = Vec::new();
+        write_rows(&mut csv, generator.into_rows().take(size as usize))
+            .expect("Unexpected generator error!");
+
+        group.throughput(Throughput::Elements(size));
+        group.bench_with_input(BenchmarkId::from_parameter(name), &csv, |b, csv| {
+            b.iter(|| {
+                TransactionProcessor::new(
+                    TransactionCsvImporter::from_reader(csv.as_slice()),
+                    NullClientStateExporter,
+                )
+                .with_error_report(Box::new(sink()))
+                .process_transactions()
+                .expect("Unexpected processing error!");
+            });
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(
+    benches,
+    csv_parsing,
+    csv_export,
+    disputes,
+    client_cardinality,
+    error_path
+);
+criterion_main!(benches);
@@ -0,0 +1,13 @@
+fn main() {
+    // protobuf code is generated only for the gRPC interface, so default builds don't need protoc
+    #[cfg(feature = "grpc")]
+    compile_protos().expect("Error compiling protobuf schema");
+}
+
+#[cfg(feature = "grpc")]
+fn compile_protos() -> Result<(), Box<dyn std::error::Error>> {
+    // a vendored protoc keeps builds independent of locally installed tooling
+    std::env::set_var("PROTOC", protoc_bin_vendored::protoc_bin_path()?);
+    tonic_build::compile_protos("proto/engine.proto")?;
+    Ok(())
+}
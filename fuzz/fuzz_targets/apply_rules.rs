@@ -0,0 +1,170 @@
+#![no_main]
+
+use arbitrary::Arbitrary;
+use libfuzzer_sys::fuzz_target;
+use rust_decimal::Decimal;
+use std::io::sink;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+
+use simple_csv_tx_engine::exporter::ClientStateCsvExporter;
+use simple_csv_tx_engine::fraud::{
+    FraudAction, FraudConfig, FraudDetector, PatternCheck, VelocityCheck, Window,
+};
+use simple_csv_tx_engine::importer::TransactionImporter;
+use simple_csv_tx_engine::model::Transaction;
+use simple_csv_tx_engine::rules::{
+    Clock, DailyWithdrawalLimit, DuplicateTransactions, MaxAmount, RuleSet,
+};
+use simple_csv_tx_engine::service::{ProcessingError, TransactionProcessor};
+
+use common::{FuzzAmount, FuzzTransaction};
+
+mod common;
+
+#[derive(Arbitrary, Debug, Copy, Clone)]
+enum FuzzAction {
+    Flag,
+    Hold,
+    Lock,
+}
+
+#[derive(Arbitrary, Debug, Copy, Clone)]
+enum FuzzWindow {
+    Transactions(u8),
+    Seconds(u16),
+}
+
+#[derive(Arbitrary, Debug, Copy, Clone)]
+struct FuzzVelocityCheck {
+    window: FuzzWindow,
+    max_count: Option<u8>,
+    max_amount: Option<FuzzAmount>,
+    action: FuzzAction,
+}
+
+#[derive(Arbitrary, Debug, Copy, Clone)]
+struct FuzzPatternCheck {
+    window: FuzzWindow,
+    action: FuzzAction,
+}
+
+#[derive(Arbitrary, Debug)]
+struct FuzzRules {
+    max_amount: Option<FuzzAmount>,
+    daily_withdrawal_limit: Option<FuzzAmount>,
+    reject_duplicates: bool,
+    withdrawal_velocity: Option<FuzzVelocityCheck>,
+    deposit_withdraw_dispute: Option<FuzzPatternCheck>,
+}
+
+// transaction along with seconds elapsed since the previous one, so time windows and days roll over
+#[derive(Arbitrary, Debug)]
+struct FuzzInput {
+    rules: FuzzRules,
+    transactions: Vec<(u32, FuzzTransaction)>,
+}
+
+impl From<FuzzAction> for FraudAction {
+    fn from(action: FuzzAction) -> Self {
+        match action {
+            FuzzAction::Flag => FraudAction::Flag,
+            FuzzAction::Hold => FraudAction::Hold,
+            FuzzAction::Lock => FraudAction::Lock,
+        }
+    }
+}
+
+impl From<FuzzWindow> for Window {
+    fn from(window: FuzzWindow) -> Self {
+        match window {
+            FuzzWindow::Transactions(n) => Window::Transactions(n.into()),
+            FuzzWindow::Seconds(n) => Window::Seconds(n.into()),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct FuzzClock(Arc<AtomicU64>);
+
+impl Clock for FuzzClock {
+    fn now(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+// advances the clock before yielding each transaction
+struct FuzzImporter {
+    clock: FuzzClock,
+    transactions: Vec<(u32, FuzzTransaction)>,
+}
+
+impl TransactionImporter for FuzzImporter {
+    fn deserialize(&mut self) -> Box<dyn Iterator<Item = anyhow::Result<Transaction>> + '_> {
+        let clock = &self.clock;
+        Box::new(self.transactions.iter().map(move |(elapsed, transaction)| {
+            clock.0.fetch_add((*elapsed).into(), Ordering::Relaxed);
+            Ok(Transaction::from(*transaction))
+        }))
+    }
+}
+
+fn build_rules(rules: &FuzzRules, clock: &FuzzClock) -> RuleSet {
+    let mut rule_set = RuleSet::default();
+
+    if let Some(max_amount) = rules.max_amount {
+        rule_set.push(Box::new(MaxAmount(max_amount.into())));
+    }
+
+    if rules.reject_duplicates {
+        rule_set.push(Box::new(DuplicateTransactions::default()));
+    }
+
+    if let Some(limit) = rules.daily_withdrawal_limit {
+        rule_set.push(Box::new(
+            DailyWithdrawalLimit::new(limit.into()).with_clock(Box::new(clock.clone())),
+        ));
+    }
+
+    rule_set
+}
+
+fn build_fraud_detector(rules: &FuzzRules, clock: &FuzzClock) -> FraudDetector {
+    let config = FraudConfig {
+        withdrawal_velocity: rules.withdrawal_velocity.map(|check| VelocityCheck {
+            window: check.window.into(),
+            max_count: check.max_count.map(usize::from),
+            max_amount: check.max_amount.map(Decimal::from),
+            action: check.action.into(),
+        }),
+        deposit_withdraw_dispute: rules.deposit_withdraw_dispute.map(|check| PatternCheck {
+            window: check.window.into(),
+            action: check.action.into(),
+        }),
+    };
+
+    FraudDetector::new(config).with_clock(Box::new(clock.clone()))
+}
+
+// rules and fraud heuristics see untrusted amounts too, so they may reject them but never panic
+fuzz_target!(|input: FuzzInput| {
+    let clock = FuzzClock::default();
+    let rules = build_rules(&input.rules, &clock);
+    let detector = build_fraud_detector(&input.rules, &clock);
+
+    let result = TransactionProcessor::new(
+        FuzzImporter {
+            clock,
+            transactions: input.transactions,
+        },
+        ClientStateCsvExporter::new(sink(), Default::default()),
+    )
+    .with_rules(rules)
+    .with_fraud_detector(detector)
+    .with_invariant_checks()
+    .process_transactions();
+
+    if let Err(ProcessingError::InvariantViolation(violation)) = result {
+        panic!("{}", violation);
+    }
+});
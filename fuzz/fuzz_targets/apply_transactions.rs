@@ -0,0 +1,32 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::sink;
+
+use simple_csv_tx_engine::exporter::ClientStateCsvExporter;
+use simple_csv_tx_engine::invariants::check_client;
+use simple_csv_tx_engine::model::Transaction;
+use simple_csv_tx_engine::service::ProcessingContext;
+
+use common::FuzzTransaction;
+
+mod common;
+
+// every transaction is either applied, ignored or rejected, without panics or corrupting balances
+fuzz_target!(|transactions: Vec<FuzzTransaction>| {
+    let mut context = ProcessingContext::default();
+    let limits = Default::default();
+
+    for transaction in transactions {
+        let transaction = Transaction::from(transaction);
+        let _ = context.apply(&transaction, &limits);
+
+        if let Err(violation) = check_client(&context, transaction.client_id) {
+            panic!("{} after {:?}", violation, transaction);
+        }
+    }
+
+    context
+        .export_client_states(&mut ClientStateCsvExporter::new(sink(), Default::default()))
+        .expect("Export to a sink should succeed");
+});
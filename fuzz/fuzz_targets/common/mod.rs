@@ -0,0 +1,62 @@
+use arbitrary::Arbitrary;
+use rust_decimal::Decimal;
+
+use simple_csv_tx_engine::model::{ClientId, Transaction, TransactionId, TransactionType};
+
+#[derive(Arbitrary, Debug, Copy, Clone)]
+pub enum FuzzTransactionType {
+    Deposit,
+    Withdrawal,
+    Dispute,
+    Resolve,
+    Chargeback,
+}
+
+// any decimal, up to the extremes of its range, so overflows are exercised
+#[derive(Arbitrary, Debug, Copy, Clone)]
+pub struct FuzzAmount {
+    pub mantissa: i128,
+    pub scale: u8,
+}
+
+// narrow ID spaces, so transactions collide and refer to each other
+#[derive(Arbitrary, Debug, Copy, Clone)]
+pub struct FuzzTransaction {
+    pub r#type: FuzzTransactionType,
+    pub client: u8,
+    pub tx: u8,
+    pub amount: Option<FuzzAmount>,
+}
+
+impl From<FuzzAmount> for Decimal {
+    fn from(FuzzAmount { mantissa, scale }: FuzzAmount) -> Self {
+        Decimal::try_from_i128_with_scale(mantissa, u32::from(scale % 29)).unwrap_or(
+            if mantissa.is_negative() {
+                Decimal::MIN
+            } else {
+                Decimal::MAX
+            },
+        )
+    }
+}
+
+impl From<FuzzTransaction> for Transaction {
+    fn from(transaction: FuzzTransaction) -> Self {
+        let r#type = match transaction.r#type {
+            FuzzTransactionType::Deposit => TransactionType::Deposit,
+            FuzzTransactionType::Withdrawal => TransactionType::Withdrawal,
+            FuzzTransactionType::Dispute => TransactionType::Dispute,
+            FuzzTransactionType::Resolve => TransactionType::Resolve,
+            FuzzTransactionType::Chargeback => TransactionType::Chargeback,
+        };
+
+        let amount = transaction.amount.map(Decimal::from);
+
+        Transaction {
+            r#type,
+            client_id: ClientId::new(transaction.client.into()),
+            transaction_id: TransactionId::new(transaction.tx.into()),
+            amount,
+        }
+    }
+}
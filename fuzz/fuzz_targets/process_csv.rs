@@ -0,0 +1,22 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use std::io::sink;
+
+use simple_csv_tx_engine::exporter::ClientStateCsvExporter;
+use simple_csv_tx_engine::importer::TransactionCsvImporter;
+use simple_csv_tx_engine::service::{ProcessingError, TransactionProcessor};
+
+// untrusted input can be rejected in any way, as long as it doesn't panic or corrupt the ledger
+fuzz_target!(|data: &[u8]| {
+    let result = TransactionProcessor::new(
+        TransactionCsvImporter::from_reader(data),
+        ClientStateCsvExporter::new(sink(), Default::default()),
+    )
+    .with_invariant_checks()
+    .process_transactions();
+
+    if let Err(ProcessingError::InvariantViolation(violation)) = result {
+        panic!("{}", violation);
+    }
+});
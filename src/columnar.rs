@@ -0,0 +1,679 @@
+use anyhow::{anyhow, bail, Context, Result};
+use arrow::array::{
+    Array, ArrayRef, AsArray, BooleanArray, Decimal128Array, PrimitiveArray, RecordBatch,
+};
+use arrow::compute::cast;
+use arrow::datatypes::{
+    ArrowPrimitiveType, DataType, Field, Int32Type, Schema, SchemaRef, UInt32Type,
+};
+use arrow::error::ArrowError;
+use arrow::ipc::reader::FileReader;
+use arrow::ipc::writer::FileWriter;
+use parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use parquet::arrow::ArrowWriter;
+use rust_decimal::Decimal;
+use std::fs::File;
+use std::io::{Read, Write};
+use std::iter;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use tracing::info;
+
+use crate::exporter::{ClientStateExporter, ExporterConfig};
+use crate::importer::{ImporterConfig, PrecisionPolicy, RecordError, TransactionImporter};
+use crate::model::{ClientId, ClientIdRepr, ClientState, RoundingMode, Transaction, TransactionId};
+
+const PARQUET_MAGIC: &[u8] = b"PAR1";
+const ARROW_IPC_MAGIC: &[u8] = b"ARROW1";
+
+// maximum precision of 128-bit decimals
+const DECIMAL128_PRECISION: u8 = 38;
+
+// maximum scale of amounts, which are rescaled before export
+const MAX_AMOUNT_SCALE: u32 = 28;
+
+// number of client states buffered before writing a record batch
+const EXPORT_BATCH_SIZE: usize = 8192;
+
+#[cfg(feature = "client-id-u64")]
+type ClientIdArrowType = arrow::datatypes::UInt64Type;
+
+#[cfg(all(feature = "client-id-u32", not(feature = "client-id-u64")))]
+type ClientIdArrowType = arrow::datatypes::UInt32Type;
+
+#[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
+type ClientIdArrowType = arrow::datatypes::UInt16Type;
+
+/// Supported columnar file formats.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum ColumnarFormat {
+    Parquet,
+    ArrowIpc,
+}
+
+impl ColumnarFormat {
+    /// Detects columnar format based on file extension.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Option<Self> {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("parquet") | Some("pq") => Some(ColumnarFormat::Parquet),
+            Some("arrow") | Some("ipc") | Some("feather") => Some(ColumnarFormat::ArrowIpc),
+            _ => None,
+        }
+    }
+
+    /// Detects columnar format based on leading magic bytes of given data.
+    pub fn from_magic_bytes(data: &[u8]) -> Option<Self> {
+        if data.starts_with(PARQUET_MAGIC) {
+            Some(ColumnarFormat::Parquet)
+        } else if data.starts_with(ARROW_IPC_MAGIC) {
+            Some(ColumnarFormat::ArrowIpc)
+        } else {
+            None
+        }
+    }
+}
+
+/// Transaction importer from Arrow record batches, e.g. read from Parquet or Arrow IPC files.
+/// Expects `type` (string or dictionary of strings), `client` and `tx` (integers) and `amount`
+/// (nullable decimal128) columns; other columns are ignored. Amounts are checked against the
+/// scale and precision policy of importer configuration, like CSV ones.
+pub struct ArrowTransactionImporter {
+    batches: Box<dyn Iterator<Item = Result<RecordBatch>>>,
+    amount_scale: u32,
+    precision_policy: PrecisionPolicy,
+}
+
+impl ArrowTransactionImporter {
+    /// Creates a new importer from given record batches.
+    pub fn from_batches<I>(batches: I) -> Self
+    where
+        I: IntoIterator<Item = Result<RecordBatch, ArrowError>>,
+        I::IntoIter: 'static,
+    {
+        Self::from_results(batches.into_iter().map(|batch| Ok(batch?)))
+    }
+
+    /// Creates a new importer from given Parquet or Arrow IPC files, processed in order as a
+    /// single logical stream. Files are opened lazily, one at a time.
+    pub fn from_paths(paths: Vec<PathBuf>) -> Self {
+        let batches = paths.into_iter().flat_map(|path| {
+            let batches = match read_batches(&path) {
+                Ok(batches) => batches,
+                Err(error) => Box::new(iter::once(Err(error))),
+            };
+
+            batches.map(move |batch| {
+                batch.with_context(|| format!("Error reading {}", path.display()))
+            })
+        });
+
+        Self::from_results(batches)
+    }
+
+    /// Sets importer configuration; only amount scale and precision policy apply to columnar
+    /// inputs.
+    pub fn with_config(mut self, config: ImporterConfig) -> Self {
+        self.amount_scale = config.amount_scale;
+        self.precision_policy = config.precision_policy;
+        self
+    }
+
+    fn from_results<I>(batches: I) -> Self
+    where
+        I: Iterator<Item = Result<RecordBatch>> + 'static,
+    {
+        let config = ImporterConfig::default();
+
+        Self {
+            batches: Box::new(batches),
+            amount_scale: config.amount_scale,
+            precision_policy: config.precision_policy,
+        }
+    }
+}
+
+impl TransactionImporter for ArrowTransactionImporter {
+    fn deserialize(&mut self) -> Box<dyn Iterator<Item = Result<Transaction>> + '_> {
+        let amount_scale = self.amount_scale;
+        let precision_policy = self.precision_policy;
+
+        Box::new(self.batches.by_ref().flat_map(move |batch| {
+            let transactions: Box<dyn Iterator<Item = Result<Transaction>>> = match batch
+                .and_then(|batch| decode_transactions(&batch, amount_scale, precision_policy))
+            {
+                Ok(transactions) => Box::new(transactions.into_iter()),
+                Err(error) => Box::new(iter::once(Err(error))),
+            };
+
+            transactions
+        }))
+    }
+}
+
+/// Client state exporter to Parquet or Arrow IPC files. States are written in record batches of
+/// `client` (unsigned integer), `available`, `held` and `total` (decimal128) and `locked`
+/// (boolean) columns. Amount scale and rounding follow exporter configuration, with totals
+/// computed from rounded parts.
+pub struct ArrowClientStateExporter<W: Write + Send> {
+    writer: BatchWriter<W>,
+    schema: SchemaRef,
+    amount_scale: u32,
+    rounding: RoundingMode,
+    pending: Vec<ClientState>,
+}
+
+impl ArrowClientStateExporter<File> {
+    /// Creates a new exporter writing to given file, in format detected by its extension.
+    pub fn from_path<P: AsRef<Path>>(path: P, config: ExporterConfig) -> Result<Self> {
+        let path = path.as_ref();
+        let format = ColumnarFormat::from_extension(path)
+            .ok_or_else(|| anyhow!("Unknown columnar format of: {}", path.display()))?;
+
+        let file =
+            File::create(path).with_context(|| format!("Error creating {}", path.display()))?;
+        Self::new(file, format, config)
+    }
+}
+
+impl<W: Write + Send> ArrowClientStateExporter<W> {
+    /// Creates a new exporter writing given format to given `Writer`.
+    pub fn new(writer: W, format: ColumnarFormat, config: ExporterConfig) -> Result<Self> {
+        if config.amount_scale > MAX_AMOUNT_SCALE {
+            bail!("Amount scale too large: {}", config.amount_scale);
+        }
+
+        let amount_type = DataType::Decimal128(DECIMAL128_PRECISION, config.amount_scale as i8);
+        let schema = Arc::new(Schema::new(vec![
+            Field::new("client", ClientIdArrowType::DATA_TYPE, false),
+            Field::new("available", amount_type.clone(), false),
+            Field::new("held", amount_type.clone(), false),
+            Field::new("total", amount_type, false),
+            Field::new("locked", DataType::Boolean, false),
+        ]));
+
+        let writer = match format {
+            ColumnarFormat::Parquet => {
+                BatchWriter::Parquet(ArrowWriter::try_new(writer, schema.clone(), None)?)
+            }
+            ColumnarFormat::ArrowIpc => {
+                BatchWriter::ArrowIpc(FileWriter::try_new(writer, &schema)?)
+            }
+        };
+
+        Ok(Self {
+            writer,
+            schema,
+            amount_scale: config.amount_scale,
+            rounding: config.rounding,
+            pending: Vec::with_capacity(EXPORT_BATCH_SIZE),
+        })
+    }
+
+    fn write_pending(&mut self) -> Result<()> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+
+        let scale = self.amount_scale;
+        let round = |value: Decimal| {
+            to_decimal128(self.rounding.round(value, scale), scale)
+                .ok_or_else(|| anyhow!("Amount too large for scale {}: {}", scale, value))
+        };
+
+        let mut available = Vec::with_capacity(self.pending.len());
+        let mut held = Vec::with_capacity(self.pending.len());
+        let mut total = Vec::with_capacity(self.pending.len());
+        for state in &self.pending {
+            let state_available = round(state.available())?;
+            let state_held = round(state.held())?;
+            let state_total = state_available
+                .checked_add(state_held)
+                .filter(|total| fits_decimal128(*total))
+                .ok_or_else(|| {
+                    anyhow!("Amount too large for scale {}: {}", scale, state.total())
+                })?;
+
+            available.push(state_available);
+            held.push(state_held);
+            total.push(state_total);
+        }
+
+        let decimals = |values: Vec<i128>| -> Result<ArrayRef> {
+            Ok(Arc::new(
+                Decimal128Array::from(values)
+                    .with_precision_and_scale(DECIMAL128_PRECISION, scale as i8)?,
+            ))
+        };
+
+        let batch = RecordBatch::try_new(
+            self.schema.clone(),
+            vec![
+                Arc::new(PrimitiveArray::<ClientIdArrowType>::from_iter_values(
+                    self.pending
+                        .iter()
+                        .map(|state| ClientIdRepr::from(state.client_id())),
+                )),
+                decimals(available)?,
+                decimals(held)?,
+                decimals(total)?,
+                Arc::new(BooleanArray::from_iter(
+                    self.pending.iter().map(|state| Some(state.locked())),
+                )),
+            ],
+        )?;
+
+        self.pending.clear();
+        self.writer.write(&batch)
+    }
+}
+
+impl<W: Write + Send> ClientStateExporter for ArrowClientStateExporter<W> {
+    fn serialize(&mut self, client_state: &ClientState) -> Result<()> {
+        self.pending.push(*client_state);
+        if self.pending.len() >= EXPORT_BATCH_SIZE {
+            self.write_pending()?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.write_pending()?;
+        self.writer.finish()
+    }
+}
+
+enum BatchWriter<W: Write + Send> {
+    Parquet(ArrowWriter<W>),
+    ArrowIpc(FileWriter<W>),
+}
+
+impl<W: Write + Send> BatchWriter<W> {
+    fn write(&mut self, batch: &RecordBatch) -> Result<()> {
+        match self {
+            BatchWriter::Parquet(writer) => writer.write(batch)?,
+            BatchWriter::ArrowIpc(writer) => writer.write(batch)?,
+        };
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        match self {
+            BatchWriter::Parquet(writer) => {
+                writer.finish()?;
+            }
+            BatchWriter::ArrowIpc(writer) => writer.finish()?,
+        };
+
+        Ok(())
+    }
+}
+
+fn read_batches(path: &Path) -> Result<Box<dyn Iterator<Item = Result<RecordBatch>>>> {
+    info!(input = %path.display(), "Importing transactions");
+    let mut file = File::open(path)?;
+
+    let mut magic = [0; ARROW_IPC_MAGIC.len()];
+    let read = file.read(&mut magic)?;
+    let format = ColumnarFormat::from_magic_bytes(&magic[..read])
+        .or_else(|| ColumnarFormat::from_extension(path))
+        .ok_or_else(|| anyhow!("Unknown columnar format"))?;
+
+    // both readers seek on their own, so there's no need to rewind
+    Ok(match format {
+        ColumnarFormat::Parquet => Box::new(
+            ParquetRecordBatchReaderBuilder::try_new(file)?
+                .build()?
+                .map(|batch| Ok(batch?)),
+        ),
+        ColumnarFormat::ArrowIpc => {
+            Box::new(FileReader::try_new(file, None)?.map(|batch| Ok(batch?)))
+        }
+    })
+}
+
+// malformed columns fail the whole batch, while invalid values only reject their own rows
+fn decode_transactions(
+    batch: &RecordBatch,
+    amount_scale: u32,
+    precision_policy: PrecisionPolicy,
+) -> Result<Vec<Result<Transaction>>> {
+    let column = |name: &str| {
+        batch
+            .column_by_name(name)
+            .ok_or_else(|| anyhow!("Missing column in input schema: {}", name))
+    };
+
+    let types = decode_types(column("type")?)?;
+    let client_ids = decode_integers::<ClientIdArrowType>(column("client")?, "client")?;
+    let transaction_ids = decode_integers::<UInt32Type>(column("tx")?, "tx")?;
+    let amounts = decode_amounts(column("amount")?)?;
+
+    Ok(types
+        .into_iter()
+        .zip(client_ids.values())
+        .zip(transaction_ids.values())
+        .zip(amounts)
+        .map(|(((r#type, client_id), transaction_id), amount)| {
+            let transaction = Transaction {
+                r#type: r#type?,
+                client_id: ClientId::new(*client_id),
+                transaction_id: TransactionId::new(*transaction_id),
+                amount: amount?,
+            };
+
+            Ok(precision_policy.apply(transaction, amount_scale)?)
+        })
+        .map(|transaction: Result<Transaction>| {
+            transaction.map_err(|error| RecordError { line: None, error }.into())
+        })
+        .collect())
+}
+
+fn decode_types(column: &ArrayRef) -> Result<Vec<Result<crate::model::TransactionType>>> {
+    let parse = |value: Option<&str>| {
+        value
+            .ok_or_else(|| anyhow!("Missing transaction type"))?
+            .parse()
+            .map_err(|error: String| anyhow!(error))
+    };
+
+    // dictionary values are parsed only once and then looked up by keys
+    if let DataType::Dictionary(_, _) = column.data_type() {
+        let dictionary = cast(
+            column,
+            &DataType::Dictionary(Box::new(DataType::Int32), Box::new(DataType::Utf8)),
+        )?;
+        let dictionary = dictionary.as_dictionary::<Int32Type>();
+        let values: Vec<_> = dictionary
+            .values()
+            .as_string::<i32>()
+            .iter()
+            .map(parse)
+            .collect();
+
+        return Ok(dictionary
+            .keys()
+            .iter()
+            .map(|key| match key {
+                Some(key) => values[key as usize]
+                    .as_ref()
+                    .map(|r#type| *r#type)
+                    .map_err(|error| anyhow!("{}", error)),
+                None => Err(anyhow!("Missing transaction type")),
+            })
+            .collect());
+    }
+
+    let column = cast(column, &DataType::Utf8)?;
+    Ok(column.as_string::<i32>().iter().map(parse).collect())
+}
+
+fn decode_integers<T: ArrowPrimitiveType>(
+    column: &ArrayRef,
+    name: &str,
+) -> Result<PrimitiveArray<T>> {
+    // out of range values are cast to nulls, which are rejected below
+    let values = cast(column, &T::DATA_TYPE)?;
+    if values.null_count() > column.null_count() {
+        bail!("Value out of range in column: {}", name);
+    }
+
+    if values.null_count() > 0 {
+        bail!("Missing value in column: {}", name);
+    }
+
+    Ok(values.as_primitive::<T>().clone())
+}
+
+fn decode_amounts(column: &ArrayRef) -> Result<Vec<Result<Option<Decimal>>>> {
+    let scale = match column.data_type() {
+        DataType::Decimal128(_, scale) if *scale >= 0 => *scale as u32,
+        data_type => bail!("Unsupported amount type: {}", data_type),
+    };
+
+    Ok(column
+        .as_primitive::<arrow::datatypes::Decimal128Type>()
+        .iter()
+        .map(|value| {
+            value
+                .map(|value| {
+                    Decimal::try_from_i128_with_scale(value, scale)
+                        .map_err(|_| anyhow!("Amount out of range: {}", value))
+                })
+                .transpose()
+        })
+        .collect())
+}
+
+// unscaled value of given amount at given scale, if it fits a 128-bit decimal; `Decimal::rescale`
+// keeps fewer decimal places when the mantissa would overflow, so it cannot be used here
+fn to_decimal128(value: Decimal, scale: u32) -> Option<i128> {
+    let factor = 10i128.checked_pow(scale.checked_sub(value.scale())?)?;
+    value
+        .mantissa()
+        .checked_mul(factor)
+        .filter(|value| fits_decimal128(*value))
+}
+
+#[inline]
+fn fits_decimal128(value: i128) -> bool {
+    value.unsigned_abs() < 10u128.pow(DECIMAL128_PRECISION as u32)
+}
+
+#[cfg(test)]
+mod tests {
+    use arrow::array::{
+        ArrayRef, Decimal128Array, DictionaryArray, RecordBatch, StringArray, UInt32Array,
+    };
+    use arrow::datatypes::Int32Type;
+    use rust_decimal::Decimal;
+    use std::sync::Arc;
+    use tempfile::Builder;
+
+    use crate::columnar::{ArrowClientStateExporter, ArrowTransactionImporter, ColumnarFormat};
+    use crate::exporter::{ClientStateExporter, ExporterConfig};
+    use crate::importer::{
+        ImporterConfig, InputFormatError, PrecisionPolicy, RecordError, TransactionImporter,
+    };
+    use crate::model::{ClientId, ClientState, Transaction, TransactionId, TransactionType};
+
+    fn create_test_batch() -> RecordBatch {
+        let types: DictionaryArray<Int32Type> = vec!["deposit", "withdrawal", "dispute"]
+            .into_iter()
+            .collect();
+
+        RecordBatch::try_from_iter([
+            ("type", Arc::new(types) as ArrayRef),
+            ("client", Arc::new(UInt32Array::from(vec![1, 1, 2]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 4, 1]))),
+            (
+                "amount",
+                Arc::new(
+                    Decimal128Array::from(vec![Some(10000), Some(15000), None])
+                        .with_precision_and_scale(38, 4)
+                        .unwrap(),
+                ),
+            ),
+            ("note", Arc::new(StringArray::from(vec!["a", "b", "c"]))),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn should_decode_record_batches() {
+        let mut importer = ArrowTransactionImporter::from_batches([Ok(create_test_batch())]);
+        let transactions: Vec<_> = importer.deserialize().map(Result::unwrap).collect();
+
+        assert_eq!(
+            transactions,
+            vec![
+                Transaction {
+                    r#type: TransactionType::Deposit,
+                    client_id: ClientId::new(1),
+                    transaction_id: TransactionId::new(1),
+                    amount: Some(Decimal::from(1)),
+                },
+                Transaction {
+                    r#type: TransactionType::Withdrawal,
+                    client_id: ClientId::new(1),
+                    transaction_id: TransactionId::new(4),
+                    amount: Some(Decimal::new(15, 1)),
+                },
+                Transaction {
+                    r#type: TransactionType::Dispute,
+                    client_id: ClientId::new(2),
+                    transaction_id: TransactionId::new(1),
+                    amount: None,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn should_reject_missing_columns() {
+        let batch = create_test_batch().project(&[0, 1, 3]).unwrap();
+        let mut importer = ArrowTransactionImporter::from_batches([Ok(batch)]);
+
+        assert!(importer.deserialize().next().unwrap().is_err());
+    }
+
+    #[test]
+    fn should_apply_precision_policy() {
+        let batch = RecordBatch::try_from_iter([
+            (
+                "type",
+                Arc::new(StringArray::from(vec!["deposit", "deposit"])) as ArrayRef,
+            ),
+            ("client", Arc::new(UInt32Array::from(vec![1, 1]))),
+            ("tx", Arc::new(UInt32Array::from(vec![1, 2]))),
+            (
+                "amount",
+                Arc::new(
+                    Decimal128Array::from(vec![Some(1_000_050), Some(2_000_000)])
+                        .with_precision_and_scale(38, 6)
+                        .unwrap(),
+                ),
+            ),
+        ])
+        .unwrap();
+
+        let mut importer = ArrowTransactionImporter::from_batches([Ok(batch.clone())]);
+        let results: Vec<_> = importer.deserialize().collect();
+
+        let error = results[0].as_ref().unwrap_err();
+        let error = error.downcast_ref::<RecordError>().unwrap();
+        assert!(matches!(
+            error.error.downcast_ref::<InputFormatError>(),
+            Some(InputFormatError::ExcessivePrecision { .. })
+        ));
+        assert_eq!(results[1].as_ref().unwrap().amount, Some(Decimal::from(2)));
+
+        let mut importer =
+            ArrowTransactionImporter::from_batches([Ok(batch)]).with_config(ImporterConfig {
+                precision_policy: PrecisionPolicy::RoundHalfEven,
+                ..Default::default()
+            });
+        let amounts: Vec<_> = importer
+            .deserialize()
+            .map(|transaction| transaction.unwrap().amount)
+            .collect();
+
+        assert_eq!(
+            amounts,
+            vec![Some(Decimal::from(1)), Some(Decimal::from(2))]
+        );
+    }
+
+    #[test]
+    fn should_reject_unsupported_amount_scale() {
+        let config = ExporterConfig {
+            amount_scale: 29,
+            ..Default::default()
+        };
+
+        assert!(ArrowClientStateExporter::new(vec![], ColumnarFormat::ArrowIpc, config).is_err());
+    }
+
+    #[test]
+    fn should_round_trip_client_states() {
+        for extension in [".parquet", ".arrow"] {
+            let file = Builder::new().suffix(extension).tempfile().unwrap();
+
+            let mut state = ClientState::new(ClientId::new(7));
+            state.deposit(Decimal::new(12345, 4)).unwrap();
+
+            let mut exporter =
+                ArrowClientStateExporter::from_path(file.path(), Default::default()).unwrap();
+            exporter.serialize(&state).unwrap();
+            exporter.finish().unwrap();
+
+            let batch = super::read_batches(file.path())
+                .unwrap()
+                .next()
+                .unwrap()
+                .unwrap();
+            assert_eq!(batch.num_rows(), 1);
+
+            let total = batch
+                .column_by_name("total")
+                .unwrap()
+                .as_any()
+                .downcast_ref::<Decimal128Array>()
+                .unwrap();
+            assert_eq!(total.value_as_string(0), "1.2345");
+        }
+    }
+
+    #[test]
+    fn should_round_trip_large_client_states() {
+        let file = Builder::new().suffix(".parquet").tempfile().unwrap();
+
+        let mut state = ClientState::new(ClientId::new(7));
+        state.deposit(Decimal::from(10u128.pow(25))).unwrap();
+
+        let mut exporter =
+            ArrowClientStateExporter::from_path(file.path(), Default::default()).unwrap();
+        exporter.serialize(&state).unwrap();
+        exporter.finish().unwrap();
+
+        let batch = super::read_batches(file.path())
+            .unwrap()
+            .next()
+            .unwrap()
+            .unwrap();
+        let available = batch
+            .column_by_name("available")
+            .unwrap()
+            .as_any()
+            .downcast_ref::<Decimal128Array>()
+            .unwrap();
+        assert_eq!(
+            available.value_as_string(0),
+            "10000000000000000000000000.0000"
+        );
+    }
+
+    #[test]
+    fn should_reject_amounts_too_large_for_scale() {
+        let mut state = ClientState::new(ClientId::new(7));
+        state.deposit(Decimal::MAX).unwrap();
+
+        let mut exporter = ArrowClientStateExporter::new(
+            vec![],
+            ColumnarFormat::ArrowIpc,
+            ExporterConfig {
+                amount_scale: 28,
+                ..Default::default()
+            },
+        )
+        .unwrap();
+        exporter.serialize(&state).unwrap();
+        assert!(exporter.finish().is_err());
+    }
+}
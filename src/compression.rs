@@ -0,0 +1,282 @@
+use std::fs::File;
+use std::io::{BufRead, BufReader, Error, ErrorKind, Read, Result, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const ZSTD_MAGIC: &[u8] = &[0x28, 0xb5, 0x2f, 0xfd];
+
+/// Supported data compression formats. Actual (de)compression support depends on enabled cargo
+/// features: `gzip` and `zstd`.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Compression {
+    None,
+    Gzip,
+    Zstd,
+}
+
+impl Compression {
+    /// Detects compression based on leading magic bytes of given data.
+    pub fn from_magic_bytes(data: &[u8]) -> Self {
+        if data.starts_with(GZIP_MAGIC) {
+            Compression::Gzip
+        } else if data.starts_with(ZSTD_MAGIC) {
+            Compression::Zstd
+        } else {
+            Compression::None
+        }
+    }
+
+    /// Detects compression based on file extension.
+    pub fn from_extension<P: AsRef<Path>>(path: P) -> Self {
+        match path
+            .as_ref()
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("gz") | Some("gzip") => Compression::Gzip,
+            Some("zst") | Some("zstd") => Compression::Zstd,
+            _ => Compression::None,
+        }
+    }
+}
+
+impl FromStr for Compression {
+    type Err = Error;
+
+    fn from_str(value: &str) -> Result<Self> {
+        match value {
+            "none" => Ok(Compression::None),
+            "gzip" | "gz" => Ok(Compression::Gzip),
+            "zstd" | "zst" => Ok(Compression::Zstd),
+            _ => Err(Error::new(
+                ErrorKind::InvalidInput,
+                format!("Unknown compression format: {}", value),
+            )),
+        }
+    }
+}
+
+/// Opens given file for reading, transparently decompressing its contents. Compression is
+/// detected by magic bytes, falling back to file extension for data too short to contain them.
+pub fn open_decompressed<P: AsRef<Path>>(path: P) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(File::open(&path)?);
+
+    // peek at the beginning of the data without consuming it
+    let compression = match Compression::from_magic_bytes(reader.fill_buf()?) {
+        Compression::None if reader.buffer().len() < ZSTD_MAGIC.len() => {
+            Compression::from_extension(&path)
+        }
+        compression => compression,
+    };
+
+    decompress(reader, compression)
+}
+
+/// Wraps given reader in a decoder for compression format detected by magic bytes, e.g. for
+/// reading potentially compressed data from stdin.
+pub fn decompress_detected<R: Read + 'static>(reader: R) -> Result<Box<dyn Read>> {
+    let mut reader = BufReader::new(reader);
+    let compression = Compression::from_magic_bytes(reader.fill_buf()?);
+
+    decompress(reader, compression)
+}
+
+/// Wraps given reader in a decoder for given compression format.
+pub fn decompress<R: BufRead + 'static>(
+    reader: R,
+    compression: Compression,
+) -> Result<Box<dyn Read>> {
+    match compression {
+        Compression::None => Ok(Box::new(reader)),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => Ok(Box::new(flate2::bufread::MultiGzDecoder::new(reader))),
+        #[cfg(not(feature = "gzip"))]
+        Compression::Gzip => Err(unsupported(compression)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => Ok(Box::new(zstd::Decoder::with_buffer(reader)?)),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => Err(unsupported(compression)),
+    }
+}
+
+/// Wraps given writer in an encoder for given compression format.
+pub fn compress<W: Write>(writer: W, compression: Compression) -> Result<Encoder<W>> {
+    let encoder = match compression {
+        Compression::None => EncoderKind::None(writer),
+        #[cfg(feature = "gzip")]
+        Compression::Gzip => EncoderKind::Gzip(flate2::write::GzEncoder::new(
+            writer,
+            flate2::Compression::default(),
+        )),
+        #[cfg(not(feature = "gzip"))]
+        Compression::Gzip => return Err(unsupported(compression)),
+        #[cfg(feature = "zstd")]
+        Compression::Zstd => EncoderKind::Zstd(zstd::Encoder::new(writer, 0)?),
+        #[cfg(not(feature = "zstd"))]
+        Compression::Zstd => return Err(unsupported(compression)),
+    };
+
+    Ok(Encoder(encoder))
+}
+
+/// Writer compressing data in a given format. The compressed stream has to be completed by
+/// [`Encoder::finish`], which reports any errors of writing its trailer; an encoder dropped
+/// unfinished completes the stream as well, but silently ignores such errors.
+pub struct Encoder<W: Write>(EncoderKind<W>);
+
+enum EncoderKind<W: Write> {
+    None(W),
+    #[cfg(feature = "gzip")]
+    Gzip(flate2::write::GzEncoder<W>),
+    #[cfg(feature = "zstd")]
+    Zstd(zstd::Encoder<'static, W>),
+}
+
+impl<W: Write> Encoder<W> {
+    /// Writes any remaining compressed data along with the trailer, and flushes the underlying
+    /// writer. Further writes are not allowed afterwards.
+    pub fn finish(&mut self) -> Result<()> {
+        match &mut self.0 {
+            EncoderKind::None(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            EncoderKind::Gzip(encoder) => {
+                encoder.try_finish()?;
+                encoder.get_mut().flush()
+            }
+            #[cfg(feature = "zstd")]
+            EncoderKind::Zstd(encoder) => {
+                encoder.do_finish()?;
+                encoder.get_mut().flush()
+            }
+        }
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> Result<usize> {
+        match &mut self.0 {
+            EncoderKind::None(writer) => writer.write(buf),
+            #[cfg(feature = "gzip")]
+            EncoderKind::Gzip(encoder) => encoder.write(buf),
+            #[cfg(feature = "zstd")]
+            EncoderKind::Zstd(encoder) => encoder.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        match &mut self.0 {
+            EncoderKind::None(writer) => writer.flush(),
+            #[cfg(feature = "gzip")]
+            EncoderKind::Gzip(encoder) => encoder.flush(),
+            #[cfg(feature = "zstd")]
+            EncoderKind::Zstd(encoder) => encoder.flush(),
+        }
+    }
+}
+
+impl<W: Write> Drop for Encoder<W> {
+    fn drop(&mut self) {
+        // finishing an already finished stream doesn't write anything
+        let _ = self.finish();
+    }
+}
+
+#[cfg(not(all(feature = "gzip", feature = "zstd")))]
+fn unsupported(compression: Compression) -> Error {
+    Error::new(
+        ErrorKind::Unsupported,
+        format!(
+            "{:?} compression support is not enabled in this build!",
+            compression
+        ),
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    use std::cell::RefCell;
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    use std::io::{Cursor, Read, Write};
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    use std::rc::Rc;
+
+    use crate::compression::Compression;
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    use crate::compression::{compress, decompress};
+
+    #[test]
+    fn should_detect_compression_from_magic_bytes() {
+        assert_eq!(
+            Compression::from_magic_bytes(&[0x1f, 0x8b, 0x08]),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_magic_bytes(&[0x28, 0xb5, 0x2f, 0xfd]),
+            Compression::Zstd
+        );
+        assert_eq!(
+            Compression::from_magic_bytes(b"type,client"),
+            Compression::None
+        );
+    }
+
+    #[test]
+    fn should_detect_compression_from_extension() {
+        assert_eq!(
+            Compression::from_extension("input.csv.gz"),
+            Compression::Gzip
+        );
+        assert_eq!(
+            Compression::from_extension("input.csv.zst"),
+            Compression::Zstd
+        );
+        assert_eq!(Compression::from_extension("input.csv"), Compression::None);
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn should_round_trip_gzip_data() {
+        round_trip(Compression::Gzip);
+    }
+
+    #[cfg(feature = "zstd")]
+    #[test]
+    fn should_round_trip_zstd_data() {
+        round_trip(Compression::Zstd);
+    }
+
+    #[cfg(any(feature = "gzip", feature = "zstd"))]
+    fn round_trip(compression: Compression) {
+        #[derive(Clone, Default)]
+        struct SharedBuffer(Rc<RefCell<Vec<u8>>>);
+
+        impl Write for SharedBuffer {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let data = "type,client,tx,amount\ndeposit,1,1,1.0\n";
+        let buffer = SharedBuffer::default();
+
+        let mut writer = compress(buffer.clone(), compression).unwrap();
+        writer.write_all(data.as_bytes()).unwrap();
+        writer.finish().unwrap();
+
+        let compressed = buffer.0.take();
+        assert_eq!(Compression::from_magic_bytes(&compressed), compression);
+
+        let mut result = String::new();
+        decompress(Cursor::new(compressed), compression)
+            .unwrap()
+            .read_to_string(&mut result)
+            .unwrap();
+        assert_eq!(result, data);
+    }
+}
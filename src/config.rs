@@ -0,0 +1,85 @@
+use anyhow::{Context, Result};
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+
+use crate::exporter::ExporterConfig;
+use crate::importer::ImporterConfig;
+use crate::reconcile::Tolerance;
+use crate::service::AmountLimits;
+
+/// Engine configuration, loadable from a TOML file. All settings are optional and default to
+/// the canonical CSV format.
+#[derive(Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub importer: ImporterConfig,
+    pub exporter: ExporterConfig,
+    pub limits: AmountLimits,
+    pub tolerance: Tolerance,
+}
+
+impl Config {
+    /// Loads configuration from given TOML file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Error reading config file: {}", path.display()))?;
+
+        toml::from_str(&data)
+            .with_context(|| format!("Error parsing config file: {}", path.display()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::config::Config;
+
+    #[test]
+    fn should_parse_config() {
+        let config: Config = toml::from_str(
+            r##"
+[importer]
+delimiter = ";"
+comment = "#"
+ignore_extra_columns = false
+
+[importer.header_aliases]
+client = ["client_id"]
+tx = ["txid"]
+
+[exporter.headers]
+client = "client_id"
+
+[limits.deposit]
+min = "0.01"
+max = 1000000
+
+[tolerance]
+total = "0.0001"
+"##,
+        )
+        .unwrap();
+
+        assert_eq!(config.importer.delimiter, b';');
+        assert_eq!(config.importer.comment, Some(b'#'));
+        assert!(!config.importer.ignore_extra_columns);
+        assert_eq!(config.importer.header_aliases.client, vec!["client_id"]);
+        assert_eq!(config.importer.header_aliases.tx, vec!["txid"]);
+        assert_eq!(config.exporter.delimiter, b',');
+        assert_eq!(config.exporter.headers.client, "client_id");
+        assert_eq!(config.exporter.headers.total, "total");
+        assert_eq!(config.limits.deposit.min, Some(Decimal::new(1, 2)));
+        assert_eq!(config.limits.deposit.max, Some(Decimal::from(1000000)));
+        assert_eq!(config.limits.withdrawal.max, None);
+        assert_eq!(config.tolerance.total, Decimal::new(1, 4));
+        assert!(config.tolerance.available.is_zero());
+    }
+
+    #[test]
+    fn should_reject_non_ascii_delimiter() {
+        assert!(toml::from_str::<Config>("[importer]\ndelimiter = \"ą\"").is_err());
+    }
+}
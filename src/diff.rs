@@ -0,0 +1,219 @@
+use anyhow::{Context, Result};
+use csv::WriterBuilder;
+use fxhash::{FxHashMap, FxHashSet};
+use std::io::Write;
+
+use crate::exporter::ExporterConfig;
+use crate::model::{format_amount, ClientId, ClientState};
+
+/// Change of a single client state between two exports.
+#[derive(Debug, Copy, Clone)]
+pub enum StateChange {
+    /// Client present only in the new export.
+    Added(ClientState),
+    /// Client present only in the old export.
+    Removed(ClientState),
+    /// Client present in both exports, with at least one differing field.
+    Changed { old: ClientState, new: ClientState },
+}
+
+impl StateChange {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            StateChange::Added(state) | StateChange::Removed(state) => state.client_id(),
+            StateChange::Changed { new, .. } => new.client_id(),
+        }
+    }
+
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            StateChange::Added(_) => "added",
+            StateChange::Removed(_) => "removed",
+            StateChange::Changed { .. } => "changed",
+        }
+    }
+
+    /// Returns the state describing this change: the state of an added or removed client, or
+    /// per-field deltas of a changed one, along with its new locked flag. Returns `None` if any
+    /// delta is too large to be represented.
+    pub fn state(&self) -> Option<ClientState> {
+        match *self {
+            StateChange::Added(state) | StateChange::Removed(state) => Some(state),
+            StateChange::Changed { old, new } => Some(ClientState::from_parts(
+                new.client_id(),
+                new.available().checked_sub(old.available())?,
+                new.held().checked_sub(old.held())?,
+                new.total().checked_sub(old.total())?,
+                new.locked(),
+            )),
+        }
+    }
+}
+
+/// Computes changes between two lists of client states. Removed and changed clients are listed in
+/// order of old states, followed by added clients in order of new states; unchanged clients are
+/// omitted.
+pub fn diff_client_states(old: &[ClientState], new: &[ClientState]) -> Vec<StateChange> {
+    let new_by_id: FxHashMap<_, _> = new.iter().map(|state| (state.client_id(), state)).collect();
+
+    let mut changes: Vec<_> = old
+        .iter()
+        .filter_map(|&old| match new_by_id.get(&old.client_id()) {
+            None => Some(StateChange::Removed(old)),
+            Some(&&new) if !is_same(&old, &new) => Some(StateChange::Changed { old, new }),
+            Some(_) => None,
+        })
+        .collect();
+
+    let old_ids: FxHashSet<_> = old.iter().map(ClientState::client_id).collect();
+    changes.extend(
+        new.iter()
+            .filter(|state| !old_ids.contains(&state.client_id()))
+            .map(|&state| StateChange::Added(state)),
+    );
+
+    changes
+}
+
+/// Writes changes as CSV in the client state format, prefixed with a `change` column. Amounts of
+/// changed clients are deltas, and fail to be written if they overflow.
+pub fn write_state_changes<W: Write>(
+    writer: W,
+    changes: &[StateChange],
+    config: &ExporterConfig,
+) -> Result<()> {
+    let mut csv_writer = WriterBuilder::new()
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .has_headers(false)
+        .from_writer(writer);
+
+    let headers = &config.headers;
+    csv_writer
+        .write_record([
+            "change",
+            &headers.client,
+            &headers.available,
+            &headers.held,
+            &headers.total,
+            &headers.locked,
+        ])
+        .context("Error writing headers")?;
+
+    for change in changes {
+        let state = change.state().with_context(|| {
+            format!(
+                "Difference of amounts overflows for client: {}",
+                change.client_id()
+            )
+        })?;
+        let scale = config.amount_scale;
+
+        csv_writer
+            .serialize((
+                change.as_str(),
+                state.client_id(),
+                format_amount(state.available(), scale),
+                format_amount(state.held(), scale),
+                format_amount(state.total(), scale),
+                state.locked(),
+            ))
+            .with_context(|| {
+                format!(
+                    "Error serializing change for client: {}",
+                    change.client_id()
+                )
+            })?;
+    }
+
+    csv_writer.flush().context("Error flushing state changes")
+}
+
+fn is_same(old: &ClientState, new: &ClientState) -> bool {
+    old.available() == new.available()
+        && old.held() == new.held()
+        && old.total() == new.total()
+        && old.locked() == new.locked()
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::diff::{diff_client_states, write_state_changes};
+    use crate::exporter::ExporterConfig;
+    use crate::importer::read_client_states;
+    use crate::model::{ClientId, ClientState};
+
+    #[test]
+    fn should_write_added_removed_and_changed_clients() {
+        let old = "client,available,held,total,locked
+1,1.0000,0.0000,1.0000,false
+2,2.0000,0.0000,2.0000,false
+3,3.0000,0.0000,3.0000,false
+";
+        let new = "client,available,held,total,locked
+4,4.0000,0.0000,4.0000,false
+3,3.0,0.0,3.0,false
+1,0.2500,0.5000,0.7500,true
+";
+
+        let old = read_client_states(old.as_bytes(), &Default::default()).unwrap();
+        let new = read_client_states(new.as_bytes(), &Default::default()).unwrap();
+        let changes = diff_client_states(&old, &new);
+
+        let mut output = vec![];
+        write_state_changes(&mut output, &changes, &Default::default()).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "change,client,available,held,total,locked
+changed,1,-0.7500,0.5000,-0.2500,true
+removed,2,2.0000,0.0000,2.0000,false
+added,4,4.0000,0.0000,4.0000,false
+"
+        );
+    }
+
+    #[test]
+    fn should_write_amounts_with_configured_scale() {
+        let old = "client,available,held,total,locked
+1,1.00,0.00,1.00,false
+";
+        let new = "client,available,held,total,locked
+1,0.25,0.50,0.75,false
+";
+
+        let config = ExporterConfig {
+            amount_scale: 2,
+            ..Default::default()
+        };
+        let old = read_client_states(old.as_bytes(), &config).unwrap();
+        let new = read_client_states(new.as_bytes(), &config).unwrap();
+        let changes = diff_client_states(&old, &new);
+
+        let mut output = vec![];
+        write_state_changes(&mut output, &changes, &config).unwrap();
+
+        assert_eq!(
+            String::from_utf8(output).unwrap(),
+            "change,client,available,held,total,locked
+changed,1,-0.75,0.50,-0.25,false
+"
+        );
+    }
+
+    #[test]
+    fn should_fail_on_overflowing_differences() {
+        let state = |amount: Decimal| {
+            ClientState::from_parts(ClientId::new(1), amount, Decimal::ZERO, amount, false)
+        };
+        let changes = diff_client_states(&[state(Decimal::MIN)], &[state(Decimal::MAX)]);
+
+        let error = write_state_changes(vec![], &changes, &Default::default()).unwrap_err();
+        assert_eq!(
+            error.to_string(),
+            "Difference of amounts overflows for client: 1"
+        );
+    }
+}
@@ -1,13 +1,237 @@
 use anyhow::{Context, Result};
-use csv::Writer;
+use csv::{Writer, WriterBuilder};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize, Serializer};
 use std::io::Write;
 
-use crate::model::ClientState;
+use crate::fraud::FraudAlert;
+use crate::importer::deserialize_ascii_char;
+use crate::interner::SharedInterner;
+use crate::model::{format_amount, ClientId, ClientState, RoundingMode, TransactionId};
+use crate::service::{ProcessingError, TransactionResult, TransactionStatus};
+
+/// Header names for exported columns.
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnNames {
+    pub client: String,
+    pub available: String,
+    pub held: String,
+    pub total: String,
+    pub locked: String,
+}
+
+impl Default for ColumnNames {
+    fn default() -> Self {
+        Self {
+            client: "client".into(),
+            available: "available".into(),
+            held: "held".into(),
+            total: "total".into(),
+            locked: "locked".into(),
+        }
+    }
+}
+
+/// CSV dialect and header configuration for client state export.
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ExporterConfig {
+    /// Field delimiter.
+    #[serde(deserialize_with = "deserialize_ascii_char")]
+    pub delimiter: u8,
+
+    /// Quote character.
+    #[serde(deserialize_with = "deserialize_ascii_char")]
+    pub quote: u8,
+
+    /// Header names for exported columns.
+    pub headers: ColumnNames,
+
+    /// Number of decimal places of exported amounts.
+    pub amount_scale: u32,
+
+    /// Rounding mode of exported amounts.
+    pub rounding: RoundingMode,
+}
+
+impl Default for ExporterConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            headers: Default::default(),
+            amount_scale: 4,
+            rounding: RoundingMode::HalfEven,
+        }
+    }
+}
+
+/// Client state with amounts rounded for display. Total is computed from rounded parts, so
+/// displayed values always add up.
+#[derive(Serialize, Debug, Copy, Clone)]
+pub(crate) struct ClientStateRecord<'a> {
+    client: Label<'a, ClientId>,
+    #[serde(serialize_with = "serialize_with_scale")]
+    available: (Decimal, u32),
+    #[serde(serialize_with = "serialize_with_scale")]
+    held: (Decimal, u32),
+    #[serde(serialize_with = "serialize_with_scale")]
+    total: (Decimal, u32),
+    locked: bool,
+}
+
+impl<'a> ClientStateRecord<'a> {
+    pub(crate) fn new(client_state: &ClientState, scale: u32, rounding: RoundingMode) -> Self {
+        let rounded = client_state.round(scale, rounding);
+
+        Self {
+            client: Label::Id(rounded.client_id()),
+            available: (rounded.available(), scale),
+            held: (rounded.held(), scale),
+            total: (rounded.total(), scale),
+            locked: rounded.locked(),
+        }
+    }
+}
+
+impl From<ClientState> for ClientStateRecord<'static> {
+    fn from(client_state: ClientState) -> Self {
+        let defaults = ExporterConfig::default();
+        Self::new(&client_state, defaults.amount_scale, defaults.rounding)
+    }
+}
+
+// identifier as seen by the outside world
+#[derive(Serialize, Debug, Copy, Clone)]
+#[serde(untagged)]
+enum Label<'a, I> {
+    Id(I),
+    Name(&'a str),
+}
+
+fn serialize_with_scale<S: Serializer>(
+    (value, scale): &(Decimal, u32),
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_str(&format_amount(*value, *scale))
+}
+
+fn serialize_optional_with_scale<S: Serializer>(
+    value: &Option<(Decimal, u32)>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    match value {
+        Some(value) => serialize_with_scale(value, serializer),
+        None => serializer.serialize_none(),
+    }
+}
+
+/// Transaction result with resulting client state, rounded the same way as client state records.
+/// Records which could not be imported have no transaction nor client state, so only their status
+/// and error are set.
+#[derive(Serialize, Debug, Copy, Clone)]
+struct TransactionResultRecord<'a> {
+    tx: Option<Label<'a, TransactionId>>,
+    client: Option<Label<'a, ClientId>>,
+    r#type: Option<&'static str>,
+    status: &'static str,
+    error: Option<&'static str>,
+    #[serde(serialize_with = "serialize_optional_with_scale")]
+    available: Option<(Decimal, u32)>,
+    #[serde(serialize_with = "serialize_optional_with_scale")]
+    held: Option<(Decimal, u32)>,
+    #[serde(serialize_with = "serialize_optional_with_scale")]
+    total: Option<(Decimal, u32)>,
+    locked: Option<bool>,
+}
+
+/// Transaction which triggered a fraud heuristic; alerts are flattened to one record per
+/// transaction.
+#[derive(Serialize, Debug, Copy, Clone)]
+struct FlaggedAccountRecord<'a> {
+    client: Label<'a, ClientId>,
+    heuristic: &'static str,
+    action: &'static str,
+    tx: Label<'a, TransactionId>,
+    r#type: &'static str,
+    amount: Option<Decimal>,
+}
 
 /// Abstract client state exporter.
 pub trait ClientStateExporter {
     /// Serializes the give client state to its intended destination.
     fn serialize(&mut self, client_state: &ClientState) -> Result<()>;
+
+    /// Finishes exporting, e.g. by flushing any buffered data. Called once after all client states
+    /// have been serialized.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<E: ClientStateExporter + ?Sized> ClientStateExporter for Box<E> {
+    fn serialize(&mut self, client_state: &ClientState) -> Result<()> {
+        (**self).serialize(client_state)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Abstract exporter of per-transaction processing results.
+pub trait TransactionResultExporter {
+    /// Serializes the given transaction result to its intended destination.
+    fn serialize(&mut self, result: &TransactionResult) -> Result<()>;
+
+    /// Serializes the rejection of an input record which could not be imported as a transaction.
+    /// Does nothing by default.
+    fn serialize_invalid_record(&mut self, _error: &ProcessingError) -> Result<()> {
+        Ok(())
+    }
+
+    /// Finishes exporting, e.g. by flushing any buffered data. Called once after all transactions
+    /// have been processed.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<E: TransactionResultExporter + ?Sized> TransactionResultExporter for Box<E> {
+    fn serialize(&mut self, result: &TransactionResult) -> Result<()> {
+        (**self).serialize(result)
+    }
+
+    fn serialize_invalid_record(&mut self, error: &ProcessingError) -> Result<()> {
+        (**self).serialize_invalid_record(error)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
+}
+
+/// Abstract exporter of accounts flagged by fraud heuristics.
+pub trait FlaggedAccountExporter {
+    /// Serializes the given alert to its intended destination.
+    fn serialize(&mut self, alert: &FraudAlert) -> Result<()>;
+
+    /// Finishes exporting, e.g. by flushing any buffered data. Called once after all transactions
+    /// have been processed.
+    fn finish(&mut self) -> Result<()> {
+        Ok(())
+    }
+}
+
+impl<E: FlaggedAccountExporter + ?Sized> FlaggedAccountExporter for Box<E> {
+    fn serialize(&mut self, alert: &FraudAlert) -> Result<()> {
+        (**self).serialize(alert)
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        (**self).finish()
+    }
 }
 
 impl<W: Write> ClientStateExporter for Writer<W> {
@@ -20,14 +244,287 @@ impl<W: Write> ClientStateExporter for Writer<W> {
             )
         })
     }
+
+    fn finish(&mut self) -> Result<()> {
+        // dropping a writer flushes it too, but silently ignores any errors
+        self.flush().context("Error flushing client states")
+    }
+}
+
+/// Client state exporter to a CSV writer with configurable dialect and header names.
+pub struct ClientStateCsvExporter<W: Write> {
+    // taken when finishing, to finish the underlying writer
+    csv_writer: Option<Writer<W>>,
+    amount_scale: u32,
+    rounding: RoundingMode,
+    interner: Option<SharedInterner>,
+    finish_writer: fn(&mut W) -> std::io::Result<()>,
+
+    // written lazily before the first record, just like `csv` does
+    headers: Option<ColumnNames>,
+}
+
+impl<W: Write> ClientStateCsvExporter<W> {
+    /// Creates a new exporter writing to given `Writer`.
+    pub fn new(writer: W, config: ExporterConfig) -> Self {
+        let csv_writer = WriterBuilder::new()
+            .delimiter(config.delimiter)
+            .quote(config.quote)
+            .has_headers(false)
+            .from_writer(writer);
+
+        Self {
+            csv_writer: Some(csv_writer),
+            amount_scale: config.amount_scale,
+            rounding: config.rounding,
+            interner: None,
+            finish_writer: |_| Ok(()),
+            headers: Some(config.headers),
+        }
+    }
+
+    /// Exports opaque string client identifiers, resolved by given interner.
+    pub fn with_interner(mut self, interner: SharedInterner) -> Self {
+        self.interner = Some(interner);
+        self
+    }
+
+    /// Finishes the underlying writer with given function after flushing it, e.g. to complete a
+    /// compressed stream with [`Encoder::finish`](crate::compression::Encoder::finish).
+    pub fn with_finish(mut self, finish_writer: fn(&mut W) -> std::io::Result<()>) -> Self {
+        self.finish_writer = finish_writer;
+        self
+    }
+}
+
+impl<W: Write> ClientStateExporter for ClientStateCsvExporter<W> {
+    fn serialize(&mut self, client_state: &ClientState) -> Result<()> {
+        let csv_writer = self
+            .csv_writer
+            .as_mut()
+            .context("Client states already finished")?;
+        if let Some(headers) = self.headers.take() {
+            csv_writer
+                .write_record([
+                    headers.client,
+                    headers.available,
+                    headers.held,
+                    headers.total,
+                    headers.locked,
+                ])
+                .context("Error writing headers")?;
+        }
+
+        let interner = self.interner.as_ref().map(|interner| interner.borrow());
+        let mut record = ClientStateRecord::new(client_state, self.amount_scale, self.rounding);
+        if let Some(name) = interner
+            .as_ref()
+            .and_then(|interner| interner.client_name(client_state.client_id()))
+        {
+            record.client = Label::Name(name);
+        }
+
+        csv_writer.serialize(record).with_context(|| {
+            format!(
+                "Error serializing state for client: {}",
+                client_state.client_id()
+            )
+        })
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let Some(mut csv_writer) = self.csv_writer.take() else {
+            return Ok(());
+        };
+
+        ClientStateExporter::finish(&mut csv_writer)?;
+        // already flushed, so taking the writer doesn't fail
+        let mut writer = csv_writer
+            .into_inner()
+            .ok()
+            .context("Error flushing client states")?;
+        (self.finish_writer)(&mut writer).context("Error finishing client states")
+    }
+}
+
+/// Transaction result exporter to a CSV writer, using the dialect and amount format of client
+/// state export. Records consist of `tx`, `client`, `type`, `status` (applied, rejected or
+/// ignored), `error` (code of the rejection) and resulting client balance columns.
+pub struct TransactionResultCsvExporter<W: Write> {
+    csv_writer: Writer<W>,
+    amount_scale: u32,
+    rounding: RoundingMode,
+    interner: Option<SharedInterner>,
+}
+
+impl<W: Write> TransactionResultCsvExporter<W> {
+    /// Creates a new exporter writing to given `Writer`.
+    pub fn new(writer: W, config: ExporterConfig) -> Self {
+        let csv_writer = WriterBuilder::new()
+            .delimiter(config.delimiter)
+            .quote(config.quote)
+            .from_writer(writer);
+
+        Self {
+            csv_writer,
+            amount_scale: config.amount_scale,
+            rounding: config.rounding,
+            interner: None,
+        }
+    }
+
+    /// Exports opaque string client and transaction identifiers, resolved by given interner.
+    pub fn with_interner(mut self, interner: SharedInterner) -> Self {
+        self.interner = Some(interner);
+        self
+    }
+}
+
+impl<W: Write> TransactionResultExporter for TransactionResultCsvExporter<W> {
+    fn serialize(&mut self, result: &TransactionResult) -> Result<()> {
+        let transaction_id = result.transaction.transaction_id;
+        let state = ClientStateRecord::new(result.client_state, self.amount_scale, self.rounding);
+
+        let interner = self.interner.as_ref().map(|interner| interner.borrow());
+        let mut record = TransactionResultRecord {
+            tx: Some(Label::Id(transaction_id)),
+            client: Some(state.client),
+            r#type: Some(result.transaction.r#type.as_str()),
+            status: result.status.as_str(),
+            error: result.error.map(ProcessingError::code),
+            available: Some(state.available),
+            held: Some(state.held),
+            total: Some(state.total),
+            locked: Some(state.locked),
+        };
+
+        if let Some(interner) = &interner {
+            if let Some(name) = interner.transaction_name(transaction_id) {
+                record.tx = Some(Label::Name(name));
+            }
+
+            if let Some(name) = interner.client_name(result.client_state.client_id()) {
+                record.client = Some(Label::Name(name));
+            }
+        }
+
+        self.csv_writer.serialize(record).with_context(|| {
+            format!(
+                "Error serializing result for transaction: {}",
+                transaction_id
+            )
+        })
+    }
+
+    fn serialize_invalid_record(&mut self, error: &ProcessingError) -> Result<()> {
+        let record = TransactionResultRecord {
+            tx: None,
+            client: None,
+            r#type: None,
+            status: TransactionStatus::Rejected.as_str(),
+            error: Some(error.code()),
+            available: None,
+            held: None,
+            total: None,
+            locked: None,
+        };
+
+        self.csv_writer
+            .serialize(record)
+            .context("Error serializing result for invalid record")
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.csv_writer
+            .flush()
+            .context("Error flushing transaction results")
+    }
+}
+
+/// Flagged accounts exporter to a CSV writer, using the dialect of client state export. Every alert
+/// is written as one record per triggering transaction, consisting of `client`, `heuristic`,
+/// `action` (flag, hold or lock), `tx`, `type` and `amount` columns.
+pub struct FlaggedAccountCsvExporter<W: Write> {
+    csv_writer: Writer<W>,
+    interner: Option<SharedInterner>,
+}
+
+impl<W: Write> FlaggedAccountCsvExporter<W> {
+    /// Creates a new exporter writing to given `Writer`.
+    pub fn new(writer: W, config: ExporterConfig) -> Self {
+        let csv_writer = WriterBuilder::new()
+            .delimiter(config.delimiter)
+            .quote(config.quote)
+            .from_writer(writer);
+
+        Self {
+            csv_writer,
+            interner: None,
+        }
+    }
+
+    /// Exports opaque string client and transaction identifiers, resolved by given interner.
+    pub fn with_interner(mut self, interner: SharedInterner) -> Self {
+        self.interner = Some(interner);
+        self
+    }
+}
+
+impl<W: Write> FlaggedAccountExporter for FlaggedAccountCsvExporter<W> {
+    fn serialize(&mut self, alert: &FraudAlert) -> Result<()> {
+        let interner = self.interner.as_ref().map(|interner| interner.borrow());
+        let client = interner
+            .as_ref()
+            .and_then(|interner| interner.client_name(alert.client_id))
+            .map_or(Label::Id(alert.client_id), Label::Name);
+
+        for transaction in &alert.transactions {
+            let tx = interner
+                .as_ref()
+                .and_then(|interner| interner.transaction_name(transaction.transaction_id))
+                .map_or(Label::Id(transaction.transaction_id), Label::Name);
+
+            let record = FlaggedAccountRecord {
+                client,
+                heuristic: alert.heuristic.as_str(),
+                action: alert.action.as_str(),
+                tx,
+                r#type: transaction.r#type.as_str(),
+                amount: transaction.amount,
+            };
+
+            self.csv_writer.serialize(record).with_context(|| {
+                format!("Error serializing flagged client: {}", alert.client_id)
+            })?;
+        }
+
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        self.csv_writer
+            .flush()
+            .context("Error flushing flagged accounts")
+    }
 }
 
 #[cfg(test)]
 mod tests {
+    use anyhow::anyhow;
     use csv::Writer;
     use rust_decimal::Decimal;
+    use std::io::Write;
 
-    use crate::model::{ClientId, ClientState};
+    use crate::exporter::{
+        ClientStateCsvExporter, ClientStateExporter, ExporterConfig, FlaggedAccountCsvExporter,
+        FlaggedAccountExporter, TransactionResultCsvExporter, TransactionResultExporter,
+    };
+    use crate::fraud::{FraudAction, FraudAlert, Heuristic};
+    use crate::interner::IdentifierInterner;
+    use crate::model::{
+        ClientId, ClientState, RoundingMode, Transaction, TransactionId, TransactionType,
+    };
+    use crate::service::{ProcessingError, TransactionResult, TransactionStatus};
 
     #[test]
     fn should_serialize_state_to_csv() {
@@ -35,7 +532,7 @@ mod tests {
         state.deposit(Decimal::from(3)).unwrap();
 
         let mut writer = Writer::from_writer(vec![]);
-        writer.serialize(&state).unwrap();
+        writer.serialize(state).unwrap();
 
         let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
         assert_eq!(
@@ -43,4 +540,195 @@ mod tests {
             "client,available,held,total,locked\n2,3.0000,0.0000,3.0000,false\n"
         )
     }
+
+    #[test]
+    fn should_serialize_state_total_from_rounded_parts() {
+        let mut state = ClientState::new(ClientId::new(2));
+        state.deposit(Decimal::new(200010, 5)).unwrap();
+        state.dispute_deposit(Decimal::new(100005, 5)).unwrap();
+
+        let mut writer = Writer::from_writer(vec![]);
+        writer.serialize(state).unwrap();
+
+        let data = String::from_utf8(writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "client,available,held,total,locked\n2,1.0000,1.0000,2.0000,false\n"
+        )
+    }
+
+    #[test]
+    fn should_serialize_state_with_custom_dialect() {
+        let mut state = ClientState::new(ClientId::new(2));
+        state.deposit(Decimal::from(3)).unwrap();
+
+        let mut config = ExporterConfig {
+            delimiter: b';',
+            ..Default::default()
+        };
+        config.headers.client = "client_id".into();
+
+        let mut exporter = ClientStateCsvExporter::new(vec![], config);
+        exporter.serialize(&state).unwrap();
+        exporter.serialize(&state).unwrap();
+
+        let data = String::from_utf8(exporter.csv_writer.unwrap().into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "client_id;available;held;total;locked\n2;3.0000;0.0000;3.0000;false\n2;3.0000;0.0000;3.0000;false\n"
+        )
+    }
+
+    #[test]
+    fn should_serialize_state_with_consistent_rounding() {
+        let mut state = ClientState::new(ClientId::new(2));
+        state.deposit(Decimal::new(15, 2)).unwrap();
+        state.deposit(Decimal::new(15, 2)).unwrap();
+        state.dispute_deposit(Decimal::new(15, 2)).unwrap();
+
+        let config = ExporterConfig {
+            amount_scale: 1,
+            rounding: RoundingMode::HalfUp,
+            ..Default::default()
+        };
+
+        let mut exporter = ClientStateCsvExporter::new(vec![], config);
+        exporter.serialize(&state).unwrap();
+
+        // 0.15 + 0.15 would be displayed as 0.3 otherwise
+        let data = String::from_utf8(exporter.csv_writer.unwrap().into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "client,available,held,total,locked\n2,0.2,0.2,0.4,false\n"
+        )
+    }
+
+    #[test]
+    fn should_finish_writer_after_client_states() {
+        let state = ClientState::new(ClientId::new(2));
+
+        let mut data = vec![];
+        let mut exporter = ClientStateCsvExporter::new(&mut data, Default::default())
+            .with_finish(|writer| writer.write_all(b"end\n"));
+        exporter.serialize(&state).unwrap();
+        exporter.finish().unwrap();
+        exporter.serialize(&state).unwrap_err();
+        drop(exporter);
+
+        assert_eq!(
+            String::from_utf8(data).unwrap(),
+            "client,available,held,total,locked\n2,0.0000,0.0000,0.0000,false\nend\n"
+        );
+
+        let mut exporter = ClientStateCsvExporter::new(vec![], Default::default())
+            .with_finish(|_| Err(std::io::Error::other("trailer")));
+        exporter.serialize(&state).unwrap();
+        let error = exporter.finish().unwrap_err();
+        assert_eq!(error.root_cause().to_string(), "trailer");
+    }
+
+    #[test]
+    fn should_serialize_state_with_opaque_client_id() {
+        let interner = IdentifierInterner::new_shared();
+        let client_id = interner.borrow_mut().client_id("c-7f3a").unwrap();
+
+        let mut state = ClientState::new(client_id);
+        state.deposit(Decimal::from(3)).unwrap();
+
+        let mut exporter =
+            ClientStateCsvExporter::new(vec![], Default::default()).with_interner(interner);
+        exporter.serialize(&state).unwrap();
+
+        let data = String::from_utf8(exporter.csv_writer.unwrap().into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "client,available,held,total,locked\nc-7f3a,3.0000,0.0000,3.0000,false\n"
+        )
+    }
+
+    #[test]
+    fn should_serialize_transaction_results_to_csv() {
+        let transaction = Transaction {
+            r#type: TransactionType::Withdrawal,
+            client_id: ClientId::new(2),
+            transaction_id: TransactionId::new(5),
+            amount: Some(Decimal::from(4)),
+        };
+
+        let mut state = ClientState::new(ClientId::new(2));
+        state.deposit(Decimal::from(3)).unwrap();
+        let error = state.withdraw(Decimal::from(4)).unwrap_err();
+        let error = ProcessingError::TransactionError {
+            transaction_id: transaction.transaction_id,
+            error,
+        };
+
+        let mut exporter = TransactionResultCsvExporter::new(vec![], Default::default());
+        exporter
+            .serialize(&TransactionResult {
+                transaction: &transaction,
+                status: TransactionStatus::Rejected,
+                error: Some(&error),
+                client_state: &state,
+            })
+            .unwrap();
+
+        let data = String::from_utf8(exporter.csv_writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "tx,client,type,status,error,available,held,total,locked
+5,2,withdrawal,rejected,E_INSUFFICIENT_FUNDS,3.0000,0.0000,3.0000,false
+"
+        )
+    }
+
+    #[test]
+    fn should_serialize_invalid_records_to_csv() {
+        let error = ProcessingError::InvalidRecord(anyhow!("Invalid amount"));
+
+        let mut exporter = TransactionResultCsvExporter::new(vec![], Default::default());
+        exporter.serialize_invalid_record(&error).unwrap();
+
+        let data = String::from_utf8(exporter.csv_writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "tx,client,type,status,error,available,held,total,locked
+,,,rejected,E_INVALID_RECORD,,,,
+"
+        )
+    }
+
+    #[test]
+    fn should_serialize_flagged_accounts_to_csv() {
+        let deposit = Transaction {
+            r#type: TransactionType::Deposit,
+            client_id: ClientId::new(2),
+            transaction_id: TransactionId::new(5),
+            amount: Some(Decimal::from(4)),
+        };
+        let dispute = Transaction {
+            r#type: TransactionType::Dispute,
+            amount: None,
+            ..deposit
+        };
+
+        let mut exporter = FlaggedAccountCsvExporter::new(vec![], Default::default());
+        exporter
+            .serialize(&FraudAlert {
+                client_id: ClientId::new(2),
+                heuristic: Heuristic::DepositWithdrawDispute,
+                action: FraudAction::Flag,
+                transactions: vec![deposit, dispute],
+            })
+            .unwrap();
+
+        let data = String::from_utf8(exporter.csv_writer.into_inner().unwrap()).unwrap();
+        assert_eq!(
+            data,
+            "client,heuristic,action,tx,type,amount
+2,deposit_withdraw_dispute,flag,5,deposit,4
+2,deposit_withdraw_dispute,flag,5,dispute,
+"
+        )
+    }
 }
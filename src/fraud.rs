@@ -0,0 +1,615 @@
+use anyhow::Result;
+use fxhash::{FxHashMap, FxHashSet};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::collections::VecDeque;
+
+use crate::exporter::FlaggedAccountExporter;
+use crate::model::{ClientId, Transaction, TransactionType};
+use crate::rules::{Clock, SystemClock};
+use crate::service::ProcessingError;
+
+/// Action taken against an account which triggered a heuristic, from least to most severe.
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum FraudAction {
+    /// Only report the account.
+    Flag,
+    /// Reject further withdrawals, while still accepting other transactions.
+    Hold,
+    /// Lock the account, as a chargeback would.
+    Lock,
+}
+
+impl FraudAction {
+    /// Returns the canonical (output data) name of this action.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            FraudAction::Flag => "flag",
+            FraudAction::Hold => "hold",
+            FraudAction::Lock => "lock",
+        }
+    }
+}
+
+/// Sliding window of recent transactions of a single client.
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Window {
+    /// Last N applied transactions.
+    Transactions(usize),
+    /// Transactions applied within last N seconds.
+    Seconds(u64),
+}
+
+/// Limits number and total amount of withdrawals within a window.
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct VelocityCheck {
+    pub window: Window,
+    pub max_count: Option<usize>,
+    pub max_amount: Option<Decimal>,
+    pub action: FraudAction,
+}
+
+/// Detects disputes of deposits which have been followed by a withdrawal within a window.
+#[derive(Deserialize, Debug, Copy, Clone, Eq, PartialEq)]
+#[serde(deny_unknown_fields)]
+pub struct PatternCheck {
+    pub window: Window,
+    pub action: FraudAction,
+}
+
+/// Configuration of fraud heuristics. All heuristics are disabled by default.
+#[derive(Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct FraudConfig {
+    pub withdrawal_velocity: Option<VelocityCheck>,
+    pub deposit_withdraw_dispute: Option<PatternCheck>,
+}
+
+impl FraudConfig {
+    /// Returns whether any heuristic is enabled.
+    pub fn is_enabled(&self) -> bool {
+        self.withdrawal_velocity.is_some() || self.deposit_withdraw_dispute.is_some()
+    }
+}
+
+/// Heuristic which triggered an alert.
+#[derive(Debug, Copy, Clone, Eq, PartialEq, Hash)]
+pub enum Heuristic {
+    WithdrawalVelocity,
+    DepositWithdrawDispute,
+}
+
+impl Heuristic {
+    /// Returns the canonical (output data) name of this heuristic.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Heuristic::WithdrawalVelocity => "withdrawal_velocity",
+            Heuristic::DepositWithdrawDispute => "deposit_withdraw_dispute",
+        }
+    }
+}
+
+/// Account flagged by a heuristic, along with the transactions which triggered it.
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct FraudAlert {
+    pub client_id: ClientId,
+    pub heuristic: Heuristic,
+    pub action: FraudAction,
+    pub transactions: Vec<Transaction>,
+}
+
+// applied transaction along with the time it has been applied at
+#[derive(Debug, Copy, Clone)]
+struct Entry {
+    time: u64,
+    transaction: Transaction,
+}
+
+/// Applies fraud heuristics to applied transactions. Only applied transactions are taken into
+/// account, and, as transactions carry no timestamps, time windows are measured by given clock at
+/// the time of processing.
+pub struct FraudDetector {
+    config: FraudConfig,
+    clock: Box<dyn Clock>,
+    exporter: Option<Box<dyn FlaggedAccountExporter>>,
+    history: FxHashMap<ClientId, VecDeque<Entry>>,
+    held: FxHashSet<ClientId>,
+    // heuristics keep triggering while their window exceeds limits, so accounts are only alerted
+    // about once per heuristic
+    flagged: FxHashSet<(ClientId, Heuristic)>,
+}
+
+impl FraudDetector {
+    /// Creates a new detector with given heuristics, using the system clock.
+    pub fn new(config: FraudConfig) -> Self {
+        Self {
+            config,
+            clock: Box::new(SystemClock),
+            exporter: None,
+            history: Default::default(),
+            held: Default::default(),
+            flagged: Default::default(),
+        }
+    }
+
+    /// Uses given clock to measure time windows.
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    /// Reports flagged accounts to given exporter, as soon as they are detected.
+    pub fn with_exporter(mut self, exporter: Box<dyn FlaggedAccountExporter>) -> Self {
+        self.exporter = Some(exporter);
+        self
+    }
+
+    /// Checks whether given transaction can be applied, i.e. it's not a withdrawal from an account
+    /// on hold.
+    pub fn check(&self, transaction: &Transaction) -> Result<(), ProcessingError> {
+        if transaction.r#type == TransactionType::Withdrawal
+            && self.held.contains(&transaction.client_id)
+        {
+            Err(ProcessingError::AccountHeld(transaction.transaction_id))
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Records given applied transaction and runs heuristics on it. Returns the most severe action
+    /// of any newly triggered heuristic, after putting the account on hold if needed; locking is
+    /// left to the caller, which owns client state. Each heuristic alerts about an account once.
+    pub fn record(&mut self, transaction: &Transaction) -> Result<Option<FraudAction>> {
+        let now = self.clock.now();
+        let retention = self.retention();
+        let history = self.history.entry(transaction.client_id).or_default();
+        history.push_back(Entry {
+            time: now,
+            transaction: *transaction,
+        });
+        prune(history, retention, now);
+
+        let mut alerts = Vec::new();
+        if let Some(check) = &self.config.withdrawal_velocity {
+            if transaction.r#type == TransactionType::Withdrawal {
+                if let Some(transactions) = check_velocity(check, history, now) {
+                    alerts.push((Heuristic::WithdrawalVelocity, check.action, transactions));
+                }
+            }
+        }
+
+        if let Some(check) = &self.config.deposit_withdraw_dispute {
+            if transaction.r#type == TransactionType::Dispute {
+                if let Some(transactions) = check_pattern(check, history, now) {
+                    alerts.push((
+                        Heuristic::DepositWithdrawDispute,
+                        check.action,
+                        transactions,
+                    ));
+                }
+            }
+        }
+
+        let mut action = None;
+        for (heuristic, alert_action, transactions) in alerts {
+            if !self.flagged.insert((transaction.client_id, heuristic)) {
+                continue;
+            }
+
+            let alert = FraudAlert {
+                client_id: transaction.client_id,
+                heuristic,
+                action: alert_action,
+                transactions,
+            };
+
+            if alert.action == FraudAction::Hold {
+                self.held.insert(alert.client_id);
+            }
+
+            if let Some(exporter) = &mut self.exporter {
+                exporter.serialize(&alert)?;
+            }
+
+            action = action.max(Some(alert.action));
+        }
+
+        Ok(action)
+    }
+
+    /// Finishes reporting flagged accounts.
+    pub fn finish(&mut self) -> Result<()> {
+        match &mut self.exporter {
+            Some(exporter) => exporter.finish(),
+            None => Ok(()),
+        }
+    }
+
+    // widest windows of all heuristics, so each one sees all transactions it needs
+    fn retention(&self) -> (usize, Option<u64>) {
+        [
+            self.config.withdrawal_velocity.map(|check| check.window),
+            self.config
+                .deposit_withdraw_dispute
+                .map(|check| check.window),
+        ]
+        .into_iter()
+        .flatten()
+        .fold((0, None), |(count, seconds), window| match window {
+            Window::Transactions(n) => (count.max(n), seconds),
+            Window::Seconds(n) => (count, seconds.max(Some(n))),
+        })
+    }
+}
+
+fn prune(history: &mut VecDeque<Entry>, (count, seconds): (usize, Option<u64>), now: u64) {
+    while let Some(entry) = history.front() {
+        let outside_count = history.len() > count;
+        let outside_time = seconds.is_none_or(|seconds| now.saturating_sub(entry.time) > seconds);
+        if outside_count && outside_time {
+            history.pop_front();
+        } else {
+            break;
+        }
+    }
+}
+
+// transactions within given window, oldest first
+fn in_window(
+    history: &VecDeque<Entry>,
+    window: Window,
+    now: u64,
+) -> impl Iterator<Item = &Transaction> {
+    let skip = match window {
+        Window::Transactions(n) => history.len().saturating_sub(n),
+        Window::Seconds(n) => history
+            .iter()
+            .take_while(|entry| now.saturating_sub(entry.time) > n)
+            .count(),
+    };
+
+    history.iter().skip(skip).map(|entry| &entry.transaction)
+}
+
+// withdrawals within the window, if they exceed any limit
+fn check_velocity(
+    check: &VelocityCheck,
+    history: &VecDeque<Entry>,
+    now: u64,
+) -> Option<Vec<Transaction>> {
+    let withdrawals: Vec<_> = in_window(history, check.window, now)
+        .filter(|transaction| transaction.r#type == TransactionType::Withdrawal)
+        .collect();
+
+    // an overflowing sum exceeds any limit
+    let amount = withdrawals
+        .iter()
+        .filter_map(|transaction| transaction.amount)
+        .try_fold(Decimal::ZERO, |sum, amount| sum.checked_add(amount));
+    let exceeded = check.max_count.is_some_and(|max| withdrawals.len() > max)
+        || check
+            .max_amount
+            .is_some_and(|max| amount.is_none_or(|amount| amount > max));
+
+    exceeded.then(|| withdrawals.into_iter().copied().collect())
+}
+
+// disputed deposit, withdrawals following it and the dispute itself, if all are within the window
+fn check_pattern(
+    check: &PatternCheck,
+    history: &VecDeque<Entry>,
+    now: u64,
+) -> Option<Vec<Transaction>> {
+    let window: Vec<_> = in_window(history, check.window, now).collect();
+    let (dispute, earlier) = window.split_last()?;
+    let deposit = earlier.iter().position(|transaction| {
+        transaction.r#type == TransactionType::Deposit
+            && transaction.transaction_id == dispute.transaction_id
+    })?;
+
+    let mut transactions = vec![*earlier[deposit]];
+    transactions.extend(
+        earlier[deposit..]
+            .iter()
+            .filter(|transaction| transaction.r#type == TransactionType::Withdrawal)
+            .copied(),
+    );
+
+    if transactions.len() < 2 {
+        return None;
+    }
+
+    transactions.push(**dispute);
+    Some(transactions)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use rust_decimal::Decimal;
+
+    use crate::exporter::FlaggedAccountExporter;
+    use crate::fraud::{
+        FraudAction, FraudAlert, FraudConfig, FraudDetector, Heuristic, PatternCheck,
+        VelocityCheck, Window,
+    };
+    use crate::importer::{TransactionCsvImporter, TransactionImporter};
+    use crate::model::{ClientId, TransactionType};
+    use crate::rules::Clock;
+    use crate::service::{ProcessingContext, TransactionProcessor};
+
+    struct TestClock(Arc<AtomicU64>);
+
+    impl Clock for TestClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct RecordingExporter(Rc<RefCell<Vec<FraudAlert>>>);
+
+    impl FlaggedAccountExporter for RecordingExporter {
+        fn serialize(&mut self, alert: &FraudAlert) -> anyhow::Result<()> {
+            self.0.borrow_mut().push(alert.clone());
+            Ok(())
+        }
+    }
+
+    fn summarize(alert: &FraudAlert) -> (ClientId, Heuristic, FraudAction, Vec<u32>) {
+        (
+            alert.client_id,
+            alert.heuristic,
+            alert.action,
+            alert
+                .transactions
+                .iter()
+                .map(|transaction| transaction.transaction_id.into())
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn should_parse_fraud_config() {
+        let config: FraudConfig = toml::from_str(
+            r#"
+[withdrawal_velocity]
+window = { transactions = 10 }
+max_count = 3
+max_amount = "1000"
+action = "hold"
+
+[deposit_withdraw_dispute]
+window = { seconds = 3600 }
+action = "lock"
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(
+            config.withdrawal_velocity,
+            Some(VelocityCheck {
+                window: Window::Transactions(10),
+                max_count: Some(3),
+                max_amount: Some(1000.into()),
+                action: FraudAction::Hold,
+            })
+        );
+        assert_eq!(
+            config.deposit_withdraw_dispute,
+            Some(PatternCheck {
+                window: Window::Seconds(3600),
+                action: FraudAction::Lock,
+            })
+        );
+    }
+
+    #[test]
+    fn should_hold_accounts_exceeding_withdrawal_velocity() {
+        let csv = "type,client,tx,amount
+deposit,1,1,100
+withdrawal,1,2,10
+withdrawal,1,3,10
+deposit,2,4,100
+withdrawal,2,5,10
+withdrawal,1,6,10
+withdrawal,1,7,10
+deposit,1,8,10
+";
+
+        let config = FraudConfig {
+            withdrawal_velocity: Some(VelocityCheck {
+                window: Window::Transactions(3),
+                max_count: Some(2),
+                max_amount: None,
+                action: FraudAction::Hold,
+            }),
+            ..Default::default()
+        };
+        let exporter = RecordingExporter::default();
+        let detector = FraudDetector::new(config).with_exporter(Box::new(exporter.clone()));
+
+        let mut output = vec![];
+        TransactionProcessor::new(
+            TransactionCsvImporter::from_reader(csv.as_bytes()),
+            csv::Writer::from_writer(&mut output),
+        )
+        .with_fraud_detector(detector)
+        .process_transactions()
+        .unwrap();
+
+        // the withdrawal after triggering the hold is rejected, while deposits are still accepted
+        let alerts = exporter.0.borrow();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            summarize(&alerts[0]),
+            (
+                ClientId::new(1),
+                Heuristic::WithdrawalVelocity,
+                FraudAction::Hold,
+                vec![2, 3, 6]
+            )
+        );
+
+        let output = String::from_utf8(output).unwrap();
+        assert!(output.contains("1,80.0000,0.0000,80.0000,false"));
+        assert!(output.contains("2,90.0000,0.0000,90.0000,false"));
+    }
+
+    #[test]
+    fn should_alert_about_accounts_once_per_heuristic() {
+        let csv = "type,client,tx,amount
+deposit,1,1,100
+withdrawal,1,2,10
+withdrawal,1,3,10
+withdrawal,1,4,10
+withdrawal,1,5,10
+";
+
+        let config = FraudConfig {
+            withdrawal_velocity: Some(VelocityCheck {
+                window: Window::Transactions(3),
+                max_count: Some(1),
+                max_amount: None,
+                action: FraudAction::Flag,
+            }),
+            ..Default::default()
+        };
+        let exporter = RecordingExporter::default();
+        let detector = FraudDetector::new(config).with_exporter(Box::new(exporter.clone()));
+
+        TransactionProcessor::new(
+            TransactionCsvImporter::from_reader(csv.as_bytes()),
+            csv::Writer::from_writer(vec![]),
+        )
+        .with_fraud_detector(detector)
+        .process_transactions()
+        .unwrap();
+
+        // every later withdrawal keeps exceeding the limit, but the account is only reported once
+        let alerts = exporter.0.borrow();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            summarize(&alerts[0]),
+            (
+                ClientId::new(1),
+                Heuristic::WithdrawalVelocity,
+                FraudAction::Flag,
+                vec![2, 3]
+            )
+        );
+    }
+
+    #[test]
+    fn should_flag_accounts_with_overflowing_withdrawal_velocity() {
+        let csv = format!(
+            "type,client,tx,amount
+deposit,1,1,{max}
+withdrawal,1,2,{max}
+deposit,1,3,{max}
+withdrawal,1,4,{max}
+",
+            max = Decimal::MAX
+        );
+
+        let config = FraudConfig {
+            withdrawal_velocity: Some(VelocityCheck {
+                window: Window::Transactions(4),
+                max_count: None,
+                max_amount: Some(Decimal::MAX),
+                action: FraudAction::Flag,
+            }),
+            ..Default::default()
+        };
+        let exporter = RecordingExporter::default();
+        let detector = FraudDetector::new(config).with_exporter(Box::new(exporter.clone()));
+
+        TransactionProcessor::new(
+            TransactionCsvImporter::from_reader(csv.as_bytes()),
+            csv::Writer::from_writer(vec![]),
+        )
+        .with_fraud_detector(detector)
+        .process_transactions()
+        .unwrap();
+
+        // each withdrawal is within the limit, but their sum overflows
+        let alerts = exporter.0.borrow();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            summarize(&alerts[0]),
+            (
+                ClientId::new(1),
+                Heuristic::WithdrawalVelocity,
+                FraudAction::Flag,
+                vec![2, 4]
+            )
+        );
+    }
+
+    #[test]
+    fn should_lock_accounts_disputing_withdrawn_deposits() {
+        let now = Arc::new(AtomicU64::new(0));
+        let config = FraudConfig {
+            deposit_withdraw_dispute: Some(PatternCheck {
+                window: Window::Seconds(60),
+                action: FraudAction::Lock,
+            }),
+            ..Default::default()
+        };
+        let exporter = RecordingExporter::default();
+        let mut detector = FraudDetector::new(config)
+            .with_clock(Box::new(TestClock(now.clone())))
+            .with_exporter(Box::new(exporter.clone()));
+        let mut context = ProcessingContext::default();
+
+        let mut apply = |csv: &str| {
+            let mut importer = TransactionCsvImporter::from_reader(csv.as_bytes());
+            for transaction in importer.deserialize() {
+                let transaction = transaction.unwrap();
+                context.apply(&transaction, &Default::default()).unwrap();
+                if detector.record(&transaction).unwrap() == Some(FraudAction::Lock) {
+                    context.lock_client(transaction.client_id);
+                }
+            }
+
+            context.client_state(ClientId::new(1)).unwrap().locked()
+        };
+
+        // too slow to be suspicious
+        assert!(!apply(
+            "type,client,tx,amount\ndeposit,1,1,100\nwithdrawal,1,2,90\n"
+        ));
+        now.store(120, Ordering::Relaxed);
+        assert!(!apply(
+            "type,client,tx,amount\ndispute,1,1,\nresolve,1,1,\n"
+        ));
+        assert!(!apply(
+            "type,client,tx,amount\ndeposit,1,3,100\ndispute,1,3,\n"
+        ));
+
+        now.store(150, Ordering::Relaxed);
+        assert!(apply(
+            "type,client,tx,amount\nresolve,1,3,\nwithdrawal,1,4,50\ndispute,1,3,\n"
+        ));
+
+        let alerts = exporter.0.borrow();
+        assert_eq!(alerts.len(), 1);
+        assert_eq!(
+            summarize(&alerts[0]),
+            (
+                ClientId::new(1),
+                Heuristic::DepositWithdrawDispute,
+                FraudAction::Lock,
+                vec![3, 4, 3]
+            )
+        );
+        assert_eq!(alerts[0].transactions[2].r#type, TransactionType::Dispute);
+    }
+}
@@ -0,0 +1,651 @@
+use anyhow::{Context, Result};
+use csv::WriterBuilder;
+use fxhash::FxHashMap;
+use rand::distributions::{Distribution, WeightedIndex};
+use rand::rngs::StdRng;
+use rand::seq::SliceRandom;
+use rand::{Rng, SeedableRng};
+use rust_decimal::Decimal;
+use std::io::Write;
+use std::str::FromStr;
+
+use crate::model::{ClientId, ClientIdRepr, Transaction, TransactionId, TransactionType};
+
+/// Maximum number of generated clients, i.e. the size of the client ID range.
+pub const MAX_CLIENTS: u64 = (u64::MAX >> (u64::BITS - ClientIdRepr::BITS)).saturating_add(1);
+
+// generated amounts have 4 decimal places, so they're tracked as integer numbers of such units
+const AMOUNT_SCALE: u32 = 4;
+const MIN_DEPOSIT: i64 = 10_000;
+const MAX_DEPOSIT: i64 = 10_000_000;
+
+// bounds memory of long runs; once reached, random deposits are no longer disputed
+const MAX_OPEN_DEPOSITS: usize = 100_000;
+const MAX_WITHDRAWAL_ATTEMPTS: usize = 8;
+
+/// Relative weights of generated transaction types. Disputes, resolves and chargebacks only refer
+/// to previously generated deposits, so while there are none to refer to, deposits are generated
+/// instead; the same goes for withdrawals while no client has funds available. As chargebacks lock
+/// clients, many of them among few clients skew the mix towards deposits.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TransactionMix {
+    pub deposit: u32,
+    pub withdrawal: u32,
+    pub dispute: u32,
+    pub resolve: u32,
+    pub chargeback: u32,
+}
+
+impl TransactionMix {
+    const TYPES: [TransactionType; 5] = [
+        TransactionType::Deposit,
+        TransactionType::Withdrawal,
+        TransactionType::Dispute,
+        TransactionType::Resolve,
+        TransactionType::Chargeback,
+    ];
+
+    fn weights(&self) -> [u32; 5] {
+        [
+            self.deposit,
+            self.withdrawal,
+            self.dispute,
+            self.resolve,
+            self.chargeback,
+        ]
+    }
+}
+
+impl Default for TransactionMix {
+    fn default() -> Self {
+        Self {
+            deposit: 60,
+            withdrawal: 25,
+            dispute: 8,
+            resolve: 5,
+            chargeback: 2,
+        }
+    }
+}
+
+/// Parses comma-separated `type=weight` pairs, e.g. `deposit=3,withdrawal=1`. Omitted types get a
+/// weight of zero.
+impl FromStr for TransactionMix {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        let mut mix = Self {
+            deposit: 0,
+            withdrawal: 0,
+            dispute: 0,
+            resolve: 0,
+            chargeback: 0,
+        };
+
+        for pair in value.split(',') {
+            let (r#type, weight) = pair
+                .split_once('=')
+                .ok_or_else(|| format!("Expected type=weight pair: {}", pair))?;
+            let weight = weight
+                .trim()
+                .parse()
+                .map_err(|_| format!("Invalid weight: {}", weight))?;
+
+            match r#type.trim().parse()? {
+                TransactionType::Deposit => mix.deposit = weight,
+                TransactionType::Withdrawal => mix.withdrawal = weight,
+                TransactionType::Dispute => mix.dispute = weight,
+                TransactionType::Resolve => mix.resolve = weight,
+                TransactionType::Chargeback => mix.chargeback = weight,
+            }
+        }
+
+        if mix.weights().iter().all(|weight| *weight == 0) {
+            return Err("At least one transaction type needs a positive weight".into());
+        }
+
+        Ok(mix)
+    }
+}
+
+// generated deposit, which can be disputed, resolved or charged back
+#[derive(Debug, Copy, Clone)]
+struct Deposit {
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: i64,
+}
+
+#[derive(Debug, Default, Copy, Clone)]
+struct GeneratedClient {
+    available: i64,
+    locked: bool,
+}
+
+/// Generates an endless, deterministic stream of synthetic transactions for load testing and
+/// demos, mirroring the ledger state the processor would arrive at: withdrawals never exceed
+/// available funds, and disputes, resolves and chargebacks form chains on earlier deposits of the
+/// same client. Deposits and withdrawals get sequential transaction IDs, which wrap around after
+/// `u32::MAX`.
+///
+/// A configurable ratio of transactions is invalid, i.e. rejected or ignored by the processor:
+/// missing, zero or negative amounts, withdrawals exceeding available funds, and references to
+/// unknown transactions. Generated items are transactions, so they always form importable rows;
+/// malformed rows, which cannot be imported at all, are only generated by [`Self::into_rows`].
+pub struct TransactionGenerator {
+    rng: StdRng,
+    clients: u64,
+    types: Option<WeightedIndex<u32>>,
+    invalid_ratio: f64,
+    malformed_ratio: f64,
+    next_id: u32,
+    states: FxHashMap<ClientId, GeneratedClient>,
+    deposits: Vec<Deposit>,
+    disputes: Vec<Deposit>,
+}
+
+impl TransactionGenerator {
+    /// Creates a new generator for 100 clients with the default transaction mix and no invalid
+    /// transactions or malformed rows. The same seed and settings always yield the same stream.
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: StdRng::seed_from_u64(seed),
+            clients: 100,
+            types: WeightedIndex::new(TransactionMix::default().weights()).ok(),
+            invalid_ratio: 0.0,
+            malformed_ratio: 0.0,
+            next_id: 0,
+            states: FxHashMap::default(),
+            deposits: Vec::new(),
+            disputes: Vec::new(),
+        }
+    }
+
+    /// Sets the number of clients, with IDs starting at 0. Clamped to the client ID range.
+    pub fn with_clients(mut self, clients: u64) -> Self {
+        self.clients = clients.clamp(1, MAX_CLIENTS);
+        self
+    }
+
+    /// Sets relative weights of transaction types. If all of them are zero, only deposits are
+    /// generated.
+    pub fn with_mix(mut self, mix: TransactionMix) -> Self {
+        self.types = WeightedIndex::new(mix.weights()).ok();
+        self
+    }
+
+    /// Sets the ratio of invalid transactions, between 0 and 1.
+    pub fn with_invalid_ratio(mut self, ratio: f64) -> Self {
+        self.invalid_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Sets the ratio of malformed rows generated by [`Self::into_rows`], between 0 and 1.
+    pub fn with_malformed_ratio(mut self, ratio: f64) -> Self {
+        self.malformed_ratio = ratio.clamp(0.0, 1.0);
+        self
+    }
+
+    /// Turns this generator into an endless stream of input rows, a ratio of which is malformed:
+    /// unknown transaction types, unparseable client IDs, transaction IDs or amounts, and wrong
+    /// numbers of columns. Malformed rows don't affect the generated ledger state.
+    pub fn into_rows(self) -> GeneratedRows {
+        GeneratedRows { generator: self }
+    }
+
+    fn deposit(&mut self) -> Transaction {
+        let client_id = self.random_client();
+        let amount = self.rng.gen_range(MIN_DEPOSIT..=MAX_DEPOSIT);
+        let transaction_id = self.next_id();
+
+        self.states.entry(client_id).or_default().available += amount;
+        self.open(Deposit {
+            client_id,
+            transaction_id,
+            amount,
+        });
+
+        amount_transaction(TransactionType::Deposit, client_id, transaction_id, amount)
+    }
+
+    fn withdrawal(&mut self) -> Transaction {
+        // clients with open deposits are likely to have funds, even when most clients have none
+        for _ in 0..MAX_WITHDRAWAL_ATTEMPTS {
+            let Some(client_id) = self
+                .deposits
+                .choose(&mut self.rng)
+                .map(|deposit| deposit.client_id)
+            else {
+                break;
+            };
+
+            let Some(state) = self
+                .states
+                .get_mut(&client_id)
+                .filter(|state| !state.locked && state.available > 0)
+            else {
+                continue;
+            };
+
+            let amount = self.rng.gen_range(1..=state.available);
+            state.available -= amount;
+
+            let transaction_id = self.next_id();
+            return amount_transaction(
+                TransactionType::Withdrawal,
+                client_id,
+                transaction_id,
+                amount,
+            );
+        }
+
+        self.deposit()
+    }
+
+    fn dispute(&mut self) -> Transaction {
+        if self.deposits.is_empty() {
+            return self.deposit();
+        }
+
+        let deposit = self
+            .deposits
+            .swap_remove(self.rng.gen_range(0..self.deposits.len()));
+        self.states.entry(deposit.client_id).or_default().available -= deposit.amount;
+        self.disputes.push(deposit);
+
+        reference_transaction(TransactionType::Dispute, deposit)
+    }
+
+    fn resolve_or_chargeback(&mut self, r#type: TransactionType) -> Transaction {
+        if self.disputes.is_empty() {
+            return self.deposit();
+        }
+
+        let deposit = self
+            .disputes
+            .swap_remove(self.rng.gen_range(0..self.disputes.len()));
+        let state = self.states.entry(deposit.client_id).or_default();
+        if r#type == TransactionType::Resolve {
+            // resolved deposits can be disputed again
+            state.available += deposit.amount;
+            self.open(deposit);
+        } else {
+            state.locked = true;
+        }
+
+        reference_transaction(r#type, deposit)
+    }
+
+    fn invalid(&mut self) -> Transaction {
+        let client_id = self.random_client();
+        let transaction_id = self.next_id();
+        let r#type = if self.rng.gen_bool(0.5) {
+            TransactionType::Deposit
+        } else {
+            TransactionType::Withdrawal
+        };
+
+        let amount = match self.rng.gen_range(0..4) {
+            0 => None,
+            1 => Some(-self.rng.gen_range(0..=MAX_DEPOSIT)),
+            2 => {
+                // IDs are fresh, so nothing refers to them yet
+                let r#type = Self::reference_type(&mut self.rng);
+                return Transaction {
+                    r#type,
+                    client_id,
+                    transaction_id,
+                    amount: None,
+                };
+            }
+            _ => {
+                let available = self
+                    .states
+                    .get(&client_id)
+                    .map_or(0, |state| state.available);
+                return amount_transaction(
+                    TransactionType::Withdrawal,
+                    client_id,
+                    transaction_id,
+                    available.max(0) + self.rng.gen_range(1..=MAX_DEPOSIT),
+                );
+            }
+        };
+
+        Transaction {
+            r#type,
+            client_id,
+            transaction_id,
+            amount: amount.map(|amount| Decimal::new(amount, AMOUNT_SCALE)),
+        }
+    }
+
+    fn malformed(&mut self) -> Vec<String> {
+        let client_id = self.random_client().to_string();
+        let transaction_id = self.next_id().to_string();
+        let amount = Decimal::new(self.rng.gen_range(MIN_DEPOSIT..=MAX_DEPOSIT), AMOUNT_SCALE);
+        let r#type = if self.rng.gen_bool(0.5) {
+            TransactionType::Deposit
+        } else {
+            TransactionType::Withdrawal
+        }
+        .as_str()
+        .to_owned();
+
+        let mut row = vec![r#type, client_id, transaction_id, amount.to_string()];
+        match self.rng.gen_range(0..5) {
+            0 => row[0] = "transfer".into(),
+            1 => row[1] = format!("client-{}", row[1]),
+            2 => row[2] = format!("tx-{}", row[2]),
+            3 => row[3] = row[3].replace('.', ","),
+            _ => {
+                if self.rng.gen_bool(0.5) {
+                    row.pop();
+                } else {
+                    row.push(amount.to_string());
+                }
+            }
+        }
+
+        row
+    }
+
+    fn reference_type(rng: &mut StdRng) -> TransactionType {
+        match rng.gen_range(0..3) {
+            0 => TransactionType::Dispute,
+            1 => TransactionType::Resolve,
+            _ => TransactionType::Chargeback,
+        }
+    }
+
+    fn open(&mut self, deposit: Deposit) {
+        if self.deposits.len() < MAX_OPEN_DEPOSITS {
+            self.deposits.push(deposit);
+        } else {
+            let index = self.rng.gen_range(0..self.deposits.len());
+            self.deposits[index] = deposit;
+        }
+    }
+
+    fn random_client(&mut self) -> ClientId {
+        let index = self.rng.gen_range(0..self.clients);
+        ClientId::new(ClientIdRepr::try_from(index).unwrap_or(ClientIdRepr::MAX))
+    }
+
+    fn next_id(&mut self) -> TransactionId {
+        let id = self.next_id;
+        self.next_id = self.next_id.wrapping_add(1);
+        TransactionId::new(id)
+    }
+}
+
+impl Iterator for TransactionGenerator {
+    type Item = Transaction;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.invalid_ratio > 0.0 && self.rng.gen_bool(self.invalid_ratio) {
+            return Some(self.invalid());
+        }
+
+        let r#type = match &self.types {
+            Some(types) => TransactionMix::TYPES[types.sample(&mut self.rng)],
+            None => TransactionType::Deposit,
+        };
+
+        Some(match r#type {
+            TransactionType::Deposit => self.deposit(),
+            TransactionType::Withdrawal => self.withdrawal(),
+            TransactionType::Dispute => self.dispute(),
+            r#type => self.resolve_or_chargeback(r#type),
+        })
+    }
+}
+
+/// Input row generated by [`GeneratedRows`].
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub enum GeneratedRow {
+    Transaction(Transaction),
+    /// Raw fields of a row which cannot be imported as a transaction.
+    Malformed(Vec<String>),
+}
+
+/// Endless, deterministic stream of input rows, created by [`TransactionGenerator::into_rows`].
+pub struct GeneratedRows {
+    generator: TransactionGenerator,
+}
+
+impl Iterator for GeneratedRows {
+    type Item = GeneratedRow;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let generator = &mut self.generator;
+        if generator.malformed_ratio > 0.0 && generator.rng.gen_bool(generator.malformed_ratio) {
+            return Some(GeneratedRow::Malformed(generator.malformed()));
+        }
+
+        generator.next().map(GeneratedRow::Transaction)
+    }
+}
+
+/// Writes given transactions as CSV in the default input format.
+pub fn write_transactions<W, I>(writer: W, transactions: I) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = Transaction>,
+{
+    write_rows(
+        writer,
+        transactions.into_iter().map(GeneratedRow::Transaction),
+    )
+}
+
+/// Writes given rows as CSV in the default input format. Malformed rows are written as they are,
+/// even if their number of columns differs.
+pub fn write_rows<W, I>(writer: W, rows: I) -> Result<()>
+where
+    W: Write,
+    I: IntoIterator<Item = GeneratedRow>,
+{
+    let mut csv_writer = WriterBuilder::new().flexible(true).from_writer(writer);
+    csv_writer
+        .write_record(["type", "client", "tx", "amount"])
+        .context("Error writing headers")?;
+
+    for (index, row) in rows.into_iter().enumerate() {
+        let result = match row {
+            GeneratedRow::Transaction(transaction) => csv_writer.write_record([
+                transaction.r#type.as_str(),
+                &transaction.client_id.to_string(),
+                &transaction.transaction_id.to_string(),
+                &transaction
+                    .amount
+                    .map_or_else(String::new, |amount| amount.to_string()),
+            ]),
+            GeneratedRow::Malformed(fields) => csv_writer.write_record(&fields),
+        };
+
+        result.with_context(|| format!("Error writing row: {}", index + 1))?;
+    }
+
+    csv_writer.flush().context("Error flushing transactions")
+}
+
+fn amount_transaction(
+    r#type: TransactionType,
+    client_id: ClientId,
+    transaction_id: TransactionId,
+    amount: i64,
+) -> Transaction {
+    Transaction {
+        r#type,
+        client_id,
+        transaction_id,
+        amount: Some(Decimal::new(amount, AMOUNT_SCALE)),
+    }
+}
+
+fn reference_transaction(r#type: TransactionType, deposit: Deposit) -> Transaction {
+    Transaction {
+        r#type,
+        client_id: deposit.client_id,
+        transaction_id: deposit.transaction_id,
+        amount: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::exporter::{ClientStateCsvExporter, TransactionResultExporter};
+    use crate::generator::{
+        write_rows, write_transactions, GeneratedRow, TransactionGenerator, TransactionMix,
+    };
+    use crate::importer::TransactionCsvImporter;
+    use crate::model::TransactionType;
+    use crate::service::{
+        ProcessingError, TransactionProcessor, TransactionResult, TransactionStatus,
+    };
+
+    #[derive(Clone, Default)]
+    struct StatusExporter(Rc<RefCell<Vec<(TransactionType, TransactionStatus)>>>);
+
+    impl TransactionResultExporter for StatusExporter {
+        fn serialize(&mut self, result: &TransactionResult) -> anyhow::Result<()> {
+            self.0
+                .borrow_mut()
+                .push((result.transaction.r#type, result.status));
+            Ok(())
+        }
+    }
+
+    #[derive(Clone, Default)]
+    struct InvalidRecordExporter(Rc<RefCell<Vec<&'static str>>>);
+
+    impl TransactionResultExporter for InvalidRecordExporter {
+        fn serialize(&mut self, _result: &TransactionResult) -> anyhow::Result<()> {
+            Ok(())
+        }
+
+        fn serialize_invalid_record(&mut self, error: &ProcessingError) -> anyhow::Result<()> {
+            self.0.borrow_mut().push(error.code());
+            Ok(())
+        }
+    }
+
+    // writes generated transactions as CSV and processes them, returning their statuses
+    fn process(
+        generator: TransactionGenerator,
+        count: usize,
+    ) -> Vec<(TransactionType, TransactionStatus)> {
+        let mut csv = vec![];
+        write_transactions(&mut csv, generator.take(count)).unwrap();
+
+        let statuses = StatusExporter::default();
+        TransactionProcessor::new(
+            TransactionCsvImporter::from_reader(csv.as_slice()),
+            ClientStateCsvExporter::new(std::io::sink(), Default::default()),
+        )
+        .with_result_exporter(Box::new(statuses.clone()))
+        .with_invariant_checks()
+        .process_transactions()
+        .unwrap();
+
+        statuses.0.take()
+    }
+
+    #[test]
+    fn should_generate_applicable_transaction_chains() {
+        let generator = TransactionGenerator::new(7).with_clients(10);
+        let statuses = process(generator, 5000);
+
+        assert_eq!(statuses.len(), 5000);
+        assert!(statuses
+            .iter()
+            .all(|(_, status)| *status == TransactionStatus::Applied));
+        for r#type in [
+            TransactionType::Withdrawal,
+            TransactionType::Dispute,
+            TransactionType::Resolve,
+            TransactionType::Chargeback,
+        ] {
+            assert!(statuses.iter().any(|(other, _)| *other == r#type));
+        }
+    }
+
+    #[test]
+    fn should_generate_invalid_transactions() {
+        let generator = TransactionGenerator::new(7).with_invalid_ratio(1.0);
+        let statuses = process(generator, 1000);
+
+        assert!(statuses
+            .iter()
+            .all(|(_, status)| *status != TransactionStatus::Applied));
+    }
+
+    #[test]
+    fn should_generate_malformed_rows() {
+        let rows: Vec<_> = TransactionGenerator::new(7)
+            .with_malformed_ratio(0.5)
+            .into_rows()
+            .take(1000)
+            .collect();
+        let malformed = rows
+            .iter()
+            .filter(|row| matches!(row, GeneratedRow::Malformed(_)))
+            .count();
+        assert!(malformed > 0 && malformed < rows.len());
+
+        let mut csv = vec![];
+        write_rows(&mut csv, rows).unwrap();
+
+        // malformed rows are rejected by the importer, while all others still apply
+        let invalid_records = InvalidRecordExporter::default();
+        TransactionProcessor::new(
+            TransactionCsvImporter::from_reader(csv.as_slice()),
+            ClientStateCsvExporter::new(std::io::sink(), Default::default()),
+        )
+        .with_result_exporter(Box::new(invalid_records.clone()))
+        .with_invariant_checks()
+        .process_transactions()
+        .unwrap();
+
+        let codes = invalid_records.0.take();
+        assert_eq!(codes.len(), malformed);
+        assert!(codes.iter().all(|code| *code == "E_INVALID_RECORD"));
+    }
+
+    #[test]
+    fn should_generate_same_stream_for_same_seed() {
+        let mix: TransactionMix = "deposit=1, dispute=1".parse().unwrap();
+        let generate = |invalid_ratio| {
+            TransactionGenerator::new(42)
+                .with_mix(mix)
+                .with_invalid_ratio(invalid_ratio)
+                .take(100)
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(generate(0.1), generate(0.1));
+
+        let generate_rows = || {
+            TransactionGenerator::new(42)
+                .with_malformed_ratio(0.1)
+                .into_rows()
+                .take(100)
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(generate_rows(), generate_rows());
+
+        assert!(generate(0.0)
+            .iter()
+            .all(|transaction| transaction.r#type != TransactionType::Withdrawal));
+        assert_eq!(
+            "deposit=1,transfer=1"
+                .parse::<TransactionMix>()
+                .unwrap_err(),
+            "Unknown transaction type: transfer"
+        );
+    }
+}
@@ -0,0 +1,470 @@
+use anyhow::anyhow;
+use rust_decimal::Decimal;
+use std::future::Future;
+use std::mem;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+use tokio::net::TcpListener;
+use tokio_stream::wrappers::TcpListenerStream;
+use tokio_stream::{Stream, StreamExt};
+use tonic::{Request, Response, Status, Streaming};
+
+use crate::exporter::ExporterConfig;
+use crate::importer::ImporterConfig;
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
+use crate::model::{
+    self, format_amount, ClientId, ClientIdRepr, Severity, TransactionId, TransactionType,
+};
+use crate::rules::RuleSet;
+use crate::service::{
+    log_result, transaction_span, AmountLimits, ProcessingContext, ProcessingError,
+    TransactionStatus,
+};
+
+use proto::processing_error::Kind;
+use proto::transaction_engine_server::{TransactionEngine, TransactionEngineServer};
+use proto::transaction_result::Outcome;
+
+/// Protobuf messages and gRPC client/server stubs generated from `proto/engine.proto`.
+pub mod proto {
+    tonic::include_proto!("engine");
+}
+
+/// Long-running gRPC server, which keeps processing context in memory and applies streamed
+/// transactions in order, returning a result for each one. Streams are applied one transaction at
+/// a time, so transactions of any client are never reordered.
+pub struct GrpcTransactionServer {
+    context: ProcessingContext,
+    limits: AmountLimits,
+    importer_config: ImporterConfig,
+    exporter_config: ExporterConfig,
+    rules: RuleSet,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+}
+
+// state shared between request handlers; only the context is mutable
+struct EngineState {
+    context: Mutex<ProcessingContext>,
+    // only locked while holding the context lock
+    rules: Mutex<RuleSet>,
+    limits: AmountLimits,
+    importer_config: ImporterConfig,
+    exporter_config: ExporterConfig,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+}
+
+// gRPC service implementation; streamed results need to own the state they're produced from
+struct EngineService(Arc<EngineState>);
+
+impl GrpcTransactionServer {
+    /// Creates a new server, which continues processing from given context.
+    pub fn new(context: ProcessingContext) -> Self {
+        Self {
+            context,
+            limits: Default::default(),
+            importer_config: Default::default(),
+            exporter_config: Default::default(),
+            rules: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        }
+    }
+
+    /// Sets amount limits for incoming transactions. Transactions outside of limits are rejected.
+    pub fn with_limits(mut self, limits: AmountLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets amount precision handling of incoming transactions.
+    pub fn with_importer_config(mut self, config: ImporterConfig) -> Self {
+        self.importer_config = config;
+        self
+    }
+
+    /// Sets amount scale and rounding of returned client states, like those of exported ones.
+    pub fn with_exporter_config(mut self, config: ExporterConfig) -> Self {
+        self.exporter_config = config;
+        self
+    }
+
+    /// Validates incoming transactions with given rules before applying them.
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Records processing metrics to given, possibly shared, metrics.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Serves requests from given listener until `shutdown` completes. In-flight requests are
+    /// allowed to finish, after which the final processing context is returned, e.g. for writing
+    /// a snapshot.
+    pub async fn serve<F>(
+        self,
+        listener: TcpListener,
+        shutdown: F,
+    ) -> anyhow::Result<ProcessingContext>
+    where
+        F: Future<Output = ()> + Send,
+    {
+        let state = Arc::new(EngineState {
+            context: Mutex::new(self.context),
+            rules: Mutex::new(self.rules),
+            limits: self.limits,
+            importer_config: self.importer_config,
+            exporter_config: self.exporter_config,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+        });
+
+        tonic::transport::Server::builder()
+            .add_service(TransactionEngineServer::new(EngineService(state.clone())))
+            .serve_with_incoming_shutdown(TcpListenerStream::new(listener), shutdown)
+            .await?;
+
+        let context = mem::take(&mut *state.lock_context());
+        Ok(context)
+    }
+}
+
+impl EngineState {
+    fn lock_context(&self) -> MutexGuard<'_, ProcessingContext> {
+        // transactions are applied atomically, so a panicking handler cannot leave a client state
+        // half-updated
+        self.context.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn lock_rules(&self) -> MutexGuard<'_, RuleSet> {
+        self.rules.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    fn process(&self, transaction: proto::Transaction) -> proto::TransactionResult {
+        let outcome = match self.decode_transaction(&transaction) {
+            Ok(decoded) => {
+                let _span = transaction_span(&decoded).entered();
+                let mut context = self.lock_context();
+                let mut rules = self.lock_rules();
+                #[cfg(feature = "metrics")]
+                let started = Instant::now();
+
+                let result = context.apply_with_rules(&decoded, &self.limits, &mut rules);
+                log_result(&result);
+
+                #[cfg(feature = "metrics")]
+                self.metrics.record(
+                    &decoded,
+                    *result.as_ref().unwrap_or(&TransactionStatus::Rejected),
+                    started.elapsed(),
+                );
+
+                let client_state = encode_client_state(
+                    context
+                        .client_state(decoded.client_id)
+                        .expect("Client state should exist after applying a transaction"),
+                    &self.exporter_config,
+                );
+
+                match result {
+                    Ok(TransactionStatus::Ignored) => Outcome::Ignored(client_state),
+                    Ok(_) => Outcome::Applied(client_state),
+                    Err(error) => Outcome::Rejected(encode_error(&error)),
+                }
+            }
+            // a single malformed transaction doesn't stop the stream, like an invalid record
+            Err(error) => Outcome::Rejected(encode_error(&ProcessingError::InvalidRecord(error))),
+        };
+
+        proto::TransactionResult {
+            tx: transaction.tx,
+            client: transaction.client,
+            outcome: Some(outcome),
+        }
+    }
+
+    fn decode_transaction(
+        &self,
+        transaction: &proto::Transaction,
+    ) -> anyhow::Result<model::Transaction> {
+        let r#type = match proto::TransactionType::try_from(transaction.r#type) {
+            Ok(proto::TransactionType::Deposit) => TransactionType::Deposit,
+            Ok(proto::TransactionType::Withdrawal) => TransactionType::Withdrawal,
+            Ok(proto::TransactionType::Dispute) => TransactionType::Dispute,
+            Ok(proto::TransactionType::Resolve) => TransactionType::Resolve,
+            Ok(proto::TransactionType::Chargeback) => TransactionType::Chargeback,
+            Ok(proto::TransactionType::Unspecified) | Err(_) => {
+                return Err(anyhow!("Unknown transaction type: {}", transaction.r#type))
+            }
+        };
+
+        let amount = transaction
+            .amount
+            .as_deref()
+            .map(Decimal::from_str)
+            .transpose()?;
+
+        let transaction = model::Transaction {
+            r#type,
+            client_id: decode_client_id(transaction.client)?,
+            transaction_id: TransactionId::new(transaction.tx),
+            amount,
+        };
+
+        Ok(self
+            .importer_config
+            .precision_policy
+            .apply(transaction, self.importer_config.amount_scale)?)
+    }
+}
+
+#[tonic::async_trait]
+impl TransactionEngine for EngineService {
+    type ProcessStream =
+        Pin<Box<dyn Stream<Item = Result<proto::TransactionResult, Status>> + Send + 'static>>;
+
+    // the stream item type is dictated by tonic
+    #[allow(clippy::result_large_err)]
+    async fn process(
+        &self,
+        request: Request<Streaming<proto::Transaction>>,
+    ) -> Result<Response<Self::ProcessStream>, Status> {
+        // results are produced lazily, as the client consumes them, which keeps stream order
+        let state = self.0.clone();
+        let results = request
+            .into_inner()
+            .map(move |transaction| Ok(state.process(transaction?)));
+
+        Ok(Response::new(Box::pin(results)))
+    }
+
+    async fn get_client(
+        &self,
+        request: Request<proto::GetClientRequest>,
+    ) -> Result<Response<proto::ClientState>, Status> {
+        let client = request.into_inner().client;
+        let client_id = decode_client_id(client)
+            .map_err(|error| Status::invalid_argument(error.to_string()))?;
+
+        match self.0.lock_context().client_state(client_id) {
+            Some(client_state) => Ok(Response::new(encode_client_state(
+                client_state,
+                &self.0.exporter_config,
+            ))),
+            None => Err(Status::not_found(format!("Unknown client: {}", client))),
+        }
+    }
+
+    async fn list_clients(
+        &self,
+        _request: Request<proto::ListClientsRequest>,
+    ) -> Result<Response<proto::ListClientsResponse>, Status> {
+        let mut states: Vec<_> = self.0.lock_context().client_states().copied().collect();
+        states.sort_by_key(|state| ClientIdRepr::from(state.client_id()));
+
+        Ok(Response::new(proto::ListClientsResponse {
+            clients: states
+                .iter()
+                .map(|state| encode_client_state(state, &self.0.exporter_config))
+                .collect(),
+        }))
+    }
+}
+
+// widest client IDs are 64-bit, so the schema uses those regardless of enabled features
+#[allow(clippy::useless_conversion)]
+fn decode_client_id(client: u64) -> anyhow::Result<ClientId> {
+    ClientIdRepr::try_from(client)
+        .map(ClientId::new)
+        .map_err(|_| anyhow!("Client ID out of range: {}", client))
+}
+
+// amounts are rounded and formatted like exported ones
+#[allow(clippy::useless_conversion)]
+fn encode_client_state(
+    client_state: &model::ClientState,
+    config: &ExporterConfig,
+) -> proto::ClientState {
+    let scale = config.amount_scale;
+    let rounded = client_state.round(scale, config.rounding);
+
+    proto::ClientState {
+        client: ClientIdRepr::from(rounded.client_id()).into(),
+        available: format_amount(rounded.available(), scale),
+        held: format_amount(rounded.held(), scale),
+        total: format_amount(rounded.total(), scale),
+        locked: rounded.locked(),
+    }
+}
+
+fn encode_error(error: &ProcessingError) -> proto::ProcessingError {
+    let kind = match error {
+        ProcessingError::InvalidRecord(_) => Kind::Import,
+        ProcessingError::ImportError(_)
+        | ProcessingError::ExportError(_)
+        | ProcessingError::StoreError(_)
+        | ProcessingError::InvariantViolation(_) => Kind::Unspecified,
+        ProcessingError::MissingAmount(_) => Kind::MissingAmount,
+        ProcessingError::CannotDispute(_) => Kind::CannotDispute,
+        ProcessingError::CannotResolveOrChargeBack(_) => Kind::CannotResolveOrChargeBack,
+        ProcessingError::TransactionUnderDispute(_) => Kind::TransactionUnderDispute,
+        ProcessingError::TransactionError { .. } => Kind::Transaction,
+        ProcessingError::AccountHeld(_) => Kind::AccountHeld,
+        ProcessingError::RuleViolation { .. } => Kind::RuleViolation,
+    };
+
+    let severity = match error.severity() {
+        Severity::Warning => proto::Severity::Warning,
+        Severity::Error => proto::Severity::Error,
+        Severity::Fatal => proto::Severity::Fatal,
+    };
+
+    proto::ProcessingError {
+        kind: kind.into(),
+        message: error.to_string(),
+        code: error.code().into(),
+        severity: severity.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use tokio::net::TcpListener;
+    use tokio::sync::oneshot;
+    use tonic::Code;
+
+    use crate::exporter::ExporterConfig;
+    use crate::grpc::proto::processing_error::Kind;
+    use crate::grpc::proto::transaction_engine_client::TransactionEngineClient;
+    use crate::grpc::proto::transaction_result::Outcome;
+    use crate::grpc::proto::{
+        GetClientRequest, ListClientsRequest, Severity, Transaction, TransactionType,
+    };
+    use crate::grpc::{encode_client_state, GrpcTransactionServer};
+    use crate::model::{ClientId, ClientState};
+
+    fn transaction(r#type: TransactionType, client: u64, tx: u32, amount: &str) -> Transaction {
+        Transaction {
+            r#type: r#type.into(),
+            client,
+            tx,
+            amount: (!amount.is_empty()).then(|| amount.into()),
+        }
+    }
+
+    #[tokio::test]
+    async fn should_process_streamed_transactions() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (shutdown_sender, shutdown_receiver) = oneshot::channel::<()>();
+
+        let server = tokio::spawn(GrpcTransactionServer::new(Default::default()).serve(
+            listener,
+            async {
+                let _ = shutdown_receiver.await;
+            },
+        ));
+
+        let mut client = TransactionEngineClient::connect(format!("http://{}", address))
+            .await
+            .unwrap();
+
+        let transactions = vec![
+            transaction(TransactionType::Deposit, 1, 1, "2.5"),
+            transaction(TransactionType::Withdrawal, 1, 2, "5"),
+            transaction(TransactionType::Deposit, 1, 3, "1.23456"),
+            transaction(TransactionType::Dispute, 1, 1, ""),
+            transaction(TransactionType::Resolve, 1, 7, ""),
+        ];
+        let mut results = client
+            .process(tokio_stream::iter(transactions))
+            .await
+            .unwrap()
+            .into_inner();
+
+        let mut outcomes = Vec::new();
+        while let Some(result) = results.message().await.unwrap() {
+            outcomes.push(result.outcome.unwrap());
+        }
+
+        assert_eq!(outcomes.len(), 5);
+        assert!(matches!(&outcomes[0], Outcome::Applied(state) if state.available == "2.5000"));
+        assert!(
+            matches!(&outcomes[1], Outcome::Rejected(error) if error.code == "E_INSUFFICIENT_FUNDS")
+        );
+        assert!(matches!(&outcomes[2], Outcome::Rejected(error)
+            if error.kind == Kind::Import as i32
+                && error.code == "E_EXCESSIVE_PRECISION"
+                && error.severity == Severity::Error as i32));
+        assert!(matches!(&outcomes[3], Outcome::Applied(state) if state.held == "2.5000"));
+        assert!(matches!(&outcomes[4], Outcome::Ignored(state) if state.held == "2.5000"));
+
+        let state = client
+            .get_client(GetClientRequest { client: 1 })
+            .await
+            .unwrap()
+            .into_inner();
+        assert_eq!(state.total, "2.5000");
+
+        let status = client
+            .get_client(GetClientRequest { client: 2 })
+            .await
+            .unwrap_err();
+        assert_eq!(status.code(), Code::NotFound);
+
+        let clients = client
+            .list_clients(ListClientsRequest {})
+            .await
+            .unwrap()
+            .into_inner()
+            .clients;
+        assert_eq!(clients.len(), 1);
+
+        shutdown_sender.send(()).unwrap();
+        let context = server.await.unwrap().unwrap();
+        assert_eq!(context.transactions().count(), 1);
+    }
+
+    #[test]
+    fn should_encode_large_amounts() {
+        let state = ClientState::from_parts(
+            ClientId::new(1),
+            Decimal::MAX,
+            Decimal::ZERO,
+            Decimal::MAX,
+            false,
+        );
+
+        let encoded = encode_client_state(&state, &Default::default());
+        assert_eq!(encoded.available, "79228162514264337593543950335");
+        assert_eq!(encoded.held, "0.0000");
+
+        let config = ExporterConfig {
+            amount_scale: 2,
+            ..Default::default()
+        };
+        let state = ClientState::from_parts(
+            ClientId::new(1),
+            Decimal::new(1005, 3),
+            Decimal::new(1015, 3),
+            Decimal::new(2020, 3),
+            false,
+        );
+
+        // amounts are rounded half to even, like exported ones
+        let encoded = encode_client_state(&state, &config);
+        assert_eq!(encoded.available, "1.00");
+        assert_eq!(encoded.held, "1.02");
+        assert_eq!(encoded.total, "2.02");
+    }
+}
@@ -1,10 +1,194 @@
-use csv::{Error, Reader, ReaderBuilder, Trim};
-use std::fmt::Display;
-use std::fs::File;
-use std::io::Read;
+use anyhow::Context;
+use csv::{Error, Position, Reader, ReaderBuilder, StringRecord, Trim};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Deserializer};
+use std::borrow::BorrowMut;
+use std::cell::RefCell;
+use std::fmt::{self, Display, Formatter};
+use std::io::{stdin, Read};
+use std::iter;
 use std::path::Path;
+use thiserror::Error;
+use tracing::info;
 
-use crate::model::Transaction;
+use crate::compression::{decompress_detected, open_decompressed};
+use crate::exporter::ExporterConfig;
+use crate::interner::SharedInterner;
+use crate::model::{
+    deserialize_optional_decimal, ClientState, RoundingMode, Transaction, TransactionId,
+    TransactionType,
+};
+
+/// Input path denoting stdin.
+pub const STDIN_INPUT: &str = "-";
+
+/// Columns required to be present in input headers.
+const REQUIRED_COLUMNS: [&str; 4] = ["type", "client", "tx", "amount"];
+
+/// Errors related to malformed input structure.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InputFormatError {
+    #[error("Missing column in input headers: {0}")]
+    MissingColumn(String),
+    #[error("Unexpected column in input headers: {0}")]
+    UnexpectedColumn(String),
+    #[error("Amount {amount} of transaction {transaction_id} exceeds {scale} decimal places")]
+    ExcessivePrecision {
+        transaction_id: TransactionId,
+        amount: Decimal,
+        scale: u32,
+    },
+}
+
+impl InputFormatError {
+    /// Returns a stable, machine-readable code of this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            InputFormatError::MissingColumn(_) => "E_MISSING_COLUMN",
+            InputFormatError::UnexpectedColumn(_) => "E_UNEXPECTED_COLUMN",
+            InputFormatError::ExcessivePrecision { .. } => "E_EXCESSIVE_PRECISION",
+        }
+    }
+}
+
+/// Error of a single input record, e.g. a malformed one or one with an amount exceeding the
+/// allowed precision. Such records are rejected like invalid transactions, while importing
+/// continues with the next record.
+#[derive(Error, Debug)]
+pub struct RecordError {
+    /// Line of the record in its input, if known.
+    pub line: Option<u64>,
+    pub error: anyhow::Error,
+}
+
+impl Display for RecordError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self.line {
+            Some(line) => write!(f, "Invalid record at line {}: {}", line, self.error),
+            None => write!(f, "Invalid record: {}", self.error),
+        }
+    }
+}
+
+/// Handling of amounts with more decimal places than allowed.
+#[derive(Deserialize, Debug, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum PrecisionPolicy {
+    /// Rejects such amounts with [`InputFormatError::ExcessivePrecision`].
+    Reject,
+    /// Rounds such amounts to the nearest allowed value, with midpoints rounded to even.
+    RoundHalfEven,
+    /// Truncates excessive decimal places.
+    Truncate,
+}
+
+impl PrecisionPolicy {
+    /// Applies this policy to the amount of given transaction, if it exceeds given scale.
+    pub fn apply(
+        self,
+        mut transaction: Transaction,
+        scale: u32,
+    ) -> Result<Transaction, InputFormatError> {
+        let amount = match transaction.amount {
+            // trailing zeros don't count towards precision
+            Some(amount) if amount.normalize().scale() > scale => amount,
+            _ => return Ok(transaction),
+        };
+
+        transaction.amount = Some(match self {
+            PrecisionPolicy::Reject => {
+                return Err(InputFormatError::ExcessivePrecision {
+                    transaction_id: transaction.transaction_id,
+                    amount,
+                    scale,
+                })
+            }
+            PrecisionPolicy::RoundHalfEven => RoundingMode::HalfEven.round(amount, scale),
+            PrecisionPolicy::Truncate => RoundingMode::Down.round(amount, scale),
+        });
+
+        Ok(transaction)
+    }
+}
+
+/// Alternative header names for input columns.
+#[derive(Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ColumnAliases {
+    pub r#type: Vec<String>,
+    pub client: Vec<String>,
+    pub tx: Vec<String>,
+    pub amount: Vec<String>,
+}
+
+impl ColumnAliases {
+    fn canonical_name(&self, header: &str) -> Option<&'static str> {
+        let aliases = [&self.r#type, &self.client, &self.tx, &self.amount];
+        REQUIRED_COLUMNS
+            .into_iter()
+            .zip(aliases)
+            .find(|(column, aliases)| *column == header || aliases.iter().any(|a| a == header))
+            .map(|(column, _)| column)
+    }
+}
+
+/// CSV dialect and column mapping configuration for transaction import.
+#[derive(Deserialize, Debug, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ImporterConfig {
+    /// Field delimiter.
+    #[serde(deserialize_with = "deserialize_ascii_char")]
+    pub delimiter: u8,
+
+    /// Quote character.
+    #[serde(deserialize_with = "deserialize_ascii_char")]
+    pub quote: u8,
+
+    /// Whether quoted fields are supported.
+    pub quoting: bool,
+
+    /// Lines starting with this character are ignored.
+    #[serde(deserialize_with = "deserialize_optional_ascii_char")]
+    pub comment: Option<u8>,
+
+    /// Whether input starts with a header row. Headerless input is mapped using `columns`.
+    pub has_headers: bool,
+
+    /// Positional column names for headerless input. Empty names denote ignored columns.
+    pub columns: Vec<String>,
+
+    /// Alternative header names, which are mapped to canonical column names.
+    pub header_aliases: ColumnAliases,
+
+    /// Whether unknown header columns are ignored, rather than rejected.
+    pub ignore_extra_columns: bool,
+
+    /// Maximum number of decimal places in transaction amounts.
+    pub amount_scale: u32,
+
+    /// Handling of amounts exceeding `amount_scale`.
+    pub precision_policy: PrecisionPolicy,
+}
+
+impl Default for ImporterConfig {
+    fn default() -> Self {
+        Self {
+            delimiter: b',',
+            quote: b'"',
+            quoting: true,
+            comment: None,
+            has_headers: true,
+            columns: REQUIRED_COLUMNS
+                .iter()
+                .map(|column| column.to_string())
+                .collect(),
+            header_aliases: Default::default(),
+            ignore_extra_columns: true,
+            amount_scale: 4,
+            precision_policy: PrecisionPolicy::Reject,
+        }
+    }
+}
 
 /// Abstract transaction importer.
 pub trait TransactionImporter {
@@ -13,53 +197,329 @@ pub trait TransactionImporter {
     fn deserialize(&mut self) -> Box<dyn Iterator<Item = anyhow::Result<Transaction>> + '_>;
 }
 
+impl<I: TransactionImporter + ?Sized> TransactionImporter for Box<I> {
+    fn deserialize(&mut self) -> Box<dyn Iterator<Item = anyhow::Result<Transaction>> + '_> {
+        (**self).deserialize()
+    }
+}
+
 /// Transaction importer from a CSV reader. Takes care of header/data normalization (whitespace
-/// support).
+/// support) and mapping configured dialect and columns to the canonical input format.
 pub struct TransactionCsvImporter<R: Read> {
     csv_reader: Reader<R>,
+    config: ImporterConfig,
+    interner: Option<SharedInterner>,
 }
 
 impl<R: Read> TransactionImporter for TransactionCsvImporter<R> {
     fn deserialize(&mut self) -> Box<dyn Iterator<Item = anyhow::Result<Transaction>> + '_> {
-        Box::new(
-            self.csv_reader
-                .deserialize()
-                .map(|tx| tx.map_err(|error| error.into())),
-        )
+        match self.create_decoder() {
+            Ok(decoder) => Box::new(deserialize_records(&mut self.csv_reader, decoder)),
+            Err(error) => Box::new(iter::once(Err(error))),
+        }
     }
 }
 
-impl TransactionCsvImporter<File> {
-    /// Creates a new importer from given input file.
+impl TransactionCsvImporter<Box<dyn Read>> {
+    /// Creates a new importer from given input file. Compressed files are transparently
+    /// decompressed, if support for given compression format is enabled.
     pub fn from_path<P: AsRef<Path> + Display>(input_file: P) -> Result<Self, Error> {
-        Self::configure_reader_builder(&mut ReaderBuilder::new())
-            .from_path(&input_file)
-            .map(|csv_reader| Self { csv_reader })
+        let reader = open_decompressed(input_file)?;
+        Ok(Self::from_reader(reader))
+    }
+
+    /// Creates a new importer from given input file or stdin, if given [`STDIN_INPUT`].
+    pub fn from_input(input: &str, config: ImporterConfig) -> Result<Self, Error> {
+        let reader = if input == STDIN_INPUT {
+            decompress_detected(stdin())?
+        } else {
+            open_decompressed(input)?
+        };
+
+        Ok(Self::from_reader_with_config(reader, config))
     }
 }
 
 impl<R: Read> TransactionCsvImporter<R> {
     /// Creates a new importer from given input `Reader`.
-    #[cfg(test)]
-    pub(crate) fn from_reader(reader: R) -> Self {
+    pub fn from_reader(reader: R) -> Self {
+        Self::from_reader_with_config(reader, Default::default())
+    }
+
+    /// Creates a new importer from given input `Reader` with custom CSV dialect and columns.
+    pub fn from_reader_with_config(reader: R, config: ImporterConfig) -> Self {
         let csv_reader =
-            Self::configure_reader_builder(&mut ReaderBuilder::new()).from_reader(reader);
+            Self::configure_reader_builder(&mut ReaderBuilder::new(), &config).from_reader(reader);
+
+        Self {
+            csv_reader,
+            config,
+            interner: None,
+        }
+    }
+
+    /// Switches to opaque string identifiers (e.g. UUIDs) for clients and transactions, which are
+    /// interned into numeric IDs by given interner.
+    pub fn with_interner(mut self, interner: SharedInterner) -> Self {
+        self.interner = Some(interner);
+        self
+    }
+
+    /// Converts this importer into an owning iterator over deserialized transactions.
+    pub fn into_transactions(mut self) -> Box<dyn Iterator<Item = anyhow::Result<Transaction>>>
+    where
+        R: 'static,
+    {
+        match self.create_decoder() {
+            Ok(decoder) => Box::new(deserialize_records(self.csv_reader, decoder)),
+            Err(error) => Box::new(iter::once(Err(error))),
+        }
+    }
 
-        Self { csv_reader }
+    fn create_decoder(&mut self) -> anyhow::Result<RecordDecoder> {
+        Ok(RecordDecoder {
+            headers: self.resolve_headers()?,
+            amount_scale: self.config.amount_scale,
+            precision_policy: self.config.precision_policy,
+            interner: self.interner.clone(),
+        })
     }
 
-    fn configure_reader_builder(builder: &mut ReaderBuilder) -> &mut ReaderBuilder {
+    // returns canonical headers for input data
+    fn resolve_headers(&mut self) -> anyhow::Result<StringRecord> {
+        let headers: StringRecord = if self.config.has_headers {
+            let mut headers = StringRecord::new();
+            for header in self.csv_reader.headers()? {
+                match self.config.header_aliases.canonical_name(header) {
+                    Some(column) => headers.push_field(column),
+                    None if self.config.ignore_extra_columns => headers.push_field(header),
+                    None => return Err(InputFormatError::UnexpectedColumn(header.into()).into()),
+                }
+            }
+
+            headers
+        } else {
+            self.config.columns.iter().collect()
+        };
+
+        for column in REQUIRED_COLUMNS {
+            if !headers.iter().any(|header| header == column) {
+                return Err(InputFormatError::MissingColumn(column.to_string()).into());
+            }
+        }
+
+        Ok(headers)
+    }
+
+    fn configure_reader_builder<'a>(
+        builder: &'a mut ReaderBuilder,
+        config: &ImporterConfig,
+    ) -> &'a mut ReaderBuilder {
         // headers and data can contain whitespace sometimes, so we need to trim them
-        builder.trim(Trim::All)
+        builder
+            .trim(Trim::All)
+            .delimiter(config.delimiter)
+            .quote(config.quote)
+            .quoting(config.quoting)
+            .comment(config.comment)
+            .has_headers(config.has_headers)
+    }
+}
+
+/// Transaction importer from a sequence of CSV inputs (files or stdin), processed in order as a
+/// single logical stream. Inputs are opened lazily, one at a time, and headers are validated
+/// separately for each of them.
+pub struct TransactionCsvFilesImporter {
+    inputs: Vec<String>,
+    config: ImporterConfig,
+    interner: Option<SharedInterner>,
+}
+
+impl TransactionCsvFilesImporter {
+    /// Creates a new importer from given input paths, where [`STDIN_INPUT`] denotes stdin.
+    pub fn new(inputs: Vec<String>) -> Self {
+        Self {
+            inputs,
+            config: Default::default(),
+            interner: None,
+        }
     }
+
+    /// Sets CSV dialect and column configuration for all inputs.
+    pub fn with_config(mut self, config: ImporterConfig) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Switches to opaque string identifiers for all inputs. See
+    /// [`TransactionCsvImporter::with_interner`].
+    pub fn with_interner(mut self, interner: SharedInterner) -> Self {
+        self.interner = Some(interner);
+        self
+    }
+}
+
+impl TransactionImporter for TransactionCsvFilesImporter {
+    fn deserialize(&mut self) -> Box<dyn Iterator<Item = anyhow::Result<Transaction>> + '_> {
+        let config = &self.config;
+        let interner = &self.interner;
+        Box::new(self.inputs.iter().flat_map(move |input| {
+            info!(input = input.as_str(), "Importing transactions");
+            let transactions = match TransactionCsvImporter::from_input(input, config.clone()) {
+                Ok(importer) => match interner {
+                    Some(interner) => importer.with_interner(interner.clone()).into_transactions(),
+                    None => importer.into_transactions(),
+                },
+                Err(error) => Box::new(iter::once(Err(error.into()))),
+            };
+
+            transactions.map(move |tx| tx.with_context(|| format!("Error reading {}", input)))
+        }))
+    }
+}
+
+// decodes raw records into transactions, according to importer configuration
+struct RecordDecoder {
+    headers: StringRecord,
+    amount_scale: u32,
+    precision_policy: PrecisionPolicy,
+    interner: Option<SharedInterner>,
+}
+
+impl RecordDecoder {
+    fn decode(&self, record: &StringRecord) -> anyhow::Result<Transaction> {
+        let transaction = match &self.interner {
+            None => record.deserialize(Some(&self.headers))?,
+            Some(interner) => {
+                let transaction: OpaqueIdTransaction = record.deserialize(Some(&self.headers))?;
+                let mut interner = RefCell::borrow_mut(interner);
+
+                Transaction {
+                    r#type: transaction.r#type,
+                    client_id: interner.client_id(transaction.client)?,
+                    transaction_id: interner.transaction_id(transaction.tx)?,
+                    amount: transaction.amount,
+                }
+            }
+        };
+
+        Ok(self
+            .precision_policy
+            .apply(transaction, self.amount_scale)?)
+    }
+}
+
+// transaction with opaque string identifiers, borrowed from the record
+#[derive(Deserialize)]
+struct OpaqueIdTransaction<'a> {
+    r#type: TransactionType,
+    client: &'a str,
+    tx: &'a str,
+    #[serde(deserialize_with = "deserialize_optional_decimal")]
+    amount: Option<Decimal>,
+}
+
+fn deserialize_records<R: Read, B: BorrowMut<Reader<R>>>(
+    mut csv_reader: B,
+    decoder: RecordDecoder,
+) -> impl Iterator<Item = anyhow::Result<Transaction>> {
+    // similar to `Reader::deserialize`, but with our own headers and a reusable record buffer
+    let mut record = StringRecord::new();
+    iter::from_fn(
+        move || match csv_reader.borrow_mut().read_record(&mut record) {
+            Ok(true) => Some(decoder.decode(&record).map_err(|error| {
+                RecordError {
+                    line: record.position().map(Position::line),
+                    error,
+                }
+                .into()
+            })),
+            Ok(false) => None,
+            // the reader can't continue after I/O errors, but it can after malformed records
+            Err(error) if error.is_io_error() => Some(Err(error.into())),
+            Err(error) => Some(Err(RecordError {
+                line: error.position().map(Position::line),
+                error: error.into(),
+            }
+            .into())),
+        },
+    )
+}
+
+/// Reads client states in the format written by
+/// [`ClientStateCsvExporter`](crate::exporter::ClientStateCsvExporter) with given configuration,
+/// e.g. to compare them against processing results. Only numeric client identifiers are supported.
+pub fn read_client_states<R: Read>(
+    reader: R,
+    config: &ExporterConfig,
+) -> anyhow::Result<Vec<ClientState>> {
+    let mut csv_reader = ReaderBuilder::new()
+        .trim(Trim::All)
+        .delimiter(config.delimiter)
+        .quote(config.quote)
+        .from_reader(reader);
+
+    // map configured header names back to the canonical ones of `ClientState`
+    let names = &config.headers;
+    let columns = [
+        (&names.client, "client"),
+        (&names.available, "available"),
+        (&names.held, "held"),
+        (&names.total, "total"),
+        (&names.locked, "locked"),
+    ];
+    let headers: StringRecord = csv_reader
+        .headers()
+        .context("Error reading client state headers")?
+        .iter()
+        .map(|header| {
+            columns
+                .iter()
+                .find(|(name, _)| *name == header)
+                .map_or(header, |(_, column)| column)
+        })
+        .collect();
+    csv_reader.set_headers(headers);
+
+    csv_reader
+        .deserialize()
+        .map(|state| state.context("Error reading client state"))
+        .collect()
+}
+
+pub(crate) fn deserialize_ascii_char<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<u8, D::Error> {
+    let value = char::deserialize(deserializer)?;
+    u8::try_from(value)
+        .ok()
+        .filter(u8::is_ascii)
+        .ok_or_else(|| serde::de::Error::custom(format!("Expected an ASCII character: {}", value)))
+}
+
+pub(crate) fn deserialize_optional_ascii_char<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Option<u8>, D::Error> {
+    // serde doesn't yet support custom deserialization wrapped in an Option
+    #[derive(Deserialize)]
+    #[repr(transparent)]
+    struct AsciiCharWrapper(#[serde(deserialize_with = "deserialize_ascii_char")] u8);
+    Option::deserialize(deserializer).map(|value| value.map(|AsciiCharWrapper(value)| value))
 }
 
 #[cfg(test)]
 mod tests {
     use itertools::Itertools;
     use rust_decimal::prelude::*;
+    use std::io::Write;
+    use tempfile::NamedTempFile;
 
-    use crate::importer::{TransactionCsvImporter, TransactionImporter};
+    use crate::exporter::ExporterConfig;
+    use crate::importer::{
+        read_client_states, ImporterConfig, InputFormatError, PrecisionPolicy, RecordError,
+        TransactionCsvFilesImporter, TransactionCsvImporter, TransactionImporter,
+    };
+    use crate::interner::IdentifierInterner;
     use crate::model::{ClientId, Transaction, TransactionId, TransactionType};
 
     fn create_test_transactions() -> Vec<Transaction> {
@@ -102,4 +562,223 @@ withdrawal, 1, 4 , 1.5
         let transactions: Vec<_> = importer.deserialize().try_collect().unwrap();
         assert_eq!(transactions, create_test_transactions());
     }
+
+    #[test]
+    fn should_reject_csv_with_missing_columns() {
+        let csv = "type,client,amount
+deposit,1,1.0
+";
+
+        let mut importer = TransactionCsvImporter::from_reader(csv.as_bytes());
+        let error = importer
+            .deserialize()
+            .try_collect::<_, Vec<_>, _>()
+            .unwrap_err();
+        assert_eq!(
+            error.downcast::<InputFormatError>().unwrap(),
+            InputFormatError::MissingColumn("tx".into())
+        );
+    }
+
+    #[test]
+    fn should_parse_multiple_csv_files_in_order() {
+        let mut first = NamedTempFile::new().unwrap();
+        write!(first, "type,client,tx,amount\ndeposit,1,1,1.0\n").unwrap();
+
+        let mut second = NamedTempFile::new().unwrap();
+        write!(second, "tx, type, client, amount\n4, withdrawal, 1, 1.5\n").unwrap();
+
+        let mut importer = TransactionCsvFilesImporter::new(vec![
+            first.path().to_string_lossy().into_owned(),
+            second.path().to_string_lossy().into_owned(),
+        ]);
+        let transactions: Vec<_> = importer.deserialize().try_collect().unwrap();
+        assert_eq!(transactions, create_test_transactions());
+    }
+
+    #[test]
+    fn should_parse_csv_with_custom_dialect_and_aliases() {
+        let csv = "# partner export
+client_id;txid;type;amount;note
+1;1;deposit;1.0;first
+1;4;withdrawal;1.5;\"second; quoted\"
+";
+
+        let mut config = ImporterConfig {
+            delimiter: b';',
+            comment: Some(b'#'),
+            ..Default::default()
+        };
+        config.header_aliases.client = vec!["client_id".into()];
+        config.header_aliases.tx = vec!["txid".into()];
+
+        let mut importer = TransactionCsvImporter::from_reader_with_config(csv.as_bytes(), config);
+        let transactions: Vec<_> = importer.deserialize().try_collect().unwrap();
+        assert_eq!(transactions, create_test_transactions());
+    }
+
+    #[test]
+    fn should_reject_extra_columns_if_configured() {
+        let csv = "type,client,tx,amount,note
+deposit,1,1,1.0,first
+";
+
+        let config = ImporterConfig {
+            ignore_extra_columns: false,
+            ..Default::default()
+        };
+
+        let mut importer = TransactionCsvImporter::from_reader_with_config(csv.as_bytes(), config);
+        let error = importer
+            .deserialize()
+            .try_collect::<_, Vec<_>, _>()
+            .unwrap_err();
+        assert_eq!(
+            error.downcast::<InputFormatError>().unwrap(),
+            InputFormatError::UnexpectedColumn("note".into())
+        );
+    }
+
+    #[test]
+    fn should_parse_headerless_csv_by_position() {
+        let csv = "x,1,1,deposit,1.0
+y,1,4,withdrawal,1.5
+";
+
+        let config = ImporterConfig {
+            has_headers: false,
+            columns: ["", "client", "tx", "type", "amount"]
+                .map(String::from)
+                .to_vec(),
+            ..Default::default()
+        };
+
+        let mut importer = TransactionCsvImporter::from_reader_with_config(csv.as_bytes(), config);
+        let transactions: Vec<_> = importer.deserialize().try_collect().unwrap();
+        assert_eq!(transactions, create_test_transactions());
+    }
+
+    #[test]
+    fn should_reject_excessive_precision() {
+        let csv = "type,client,tx,amount
+deposit,1,1,1.00000
+deposit,1,2,0.00005
+";
+
+        let mut importer = TransactionCsvImporter::from_reader(csv.as_bytes());
+        let mut transactions = importer.deserialize();
+        assert!(transactions.next().unwrap().is_ok());
+
+        let error = transactions
+            .next()
+            .unwrap()
+            .unwrap_err()
+            .downcast::<RecordError>()
+            .unwrap();
+        assert_eq!(error.line, Some(3));
+        assert_eq!(
+            error.error.downcast::<InputFormatError>().unwrap(),
+            InputFormatError::ExcessivePrecision {
+                transaction_id: TransactionId::new(2),
+                amount: Decimal::new(5, 5),
+                scale: 4,
+            }
+        );
+    }
+
+    #[test]
+    fn should_round_or_truncate_excessive_precision() {
+        let csv = "type,client,tx,amount
+deposit,1,1,0.00005
+deposit,1,2,0.00015
+";
+
+        let amounts = |precision_policy| {
+            let config = ImporterConfig {
+                precision_policy,
+                ..Default::default()
+            };
+
+            TransactionCsvImporter::from_reader_with_config(csv.as_bytes(), config)
+                .into_transactions()
+                .map(|tx| tx.unwrap().amount.unwrap())
+                .collect_vec()
+        };
+
+        assert_eq!(
+            amounts(PrecisionPolicy::RoundHalfEven),
+            vec![Decimal::ZERO, Decimal::new(2, 4)]
+        );
+        assert_eq!(
+            amounts(PrecisionPolicy::Truncate),
+            vec![Decimal::ZERO, Decimal::new(1, 4)]
+        );
+    }
+
+    #[cfg(any(feature = "client-id-u32", feature = "client-id-u64"))]
+    #[test]
+    fn should_parse_wide_client_ids() {
+        let csv = "type,client,tx,amount
+deposit,4000000000,1,1.0
+";
+
+        let mut importer = TransactionCsvImporter::from_reader(csv.as_bytes());
+        let transactions: Vec<_> = importer.deserialize().try_collect().unwrap();
+        assert_eq!(transactions[0].client_id, ClientId::new(4000000000));
+    }
+
+    #[test]
+    fn should_intern_opaque_identifiers() {
+        let csv = "type,client,tx,amount
+deposit,c-7f3a,7d0e5c1a-8a1b-4c3e-9f1e-0c6b2a9d4e11,1.0
+withdrawal,c-7f3a,tx-2,1.5
+deposit,c-0001,tx-2,2.0
+";
+
+        let interner = IdentifierInterner::new_shared();
+        let mut importer =
+            TransactionCsvImporter::from_reader(csv.as_bytes()).with_interner(interner.clone());
+        let transactions: Vec<_> = importer.deserialize().try_collect().unwrap();
+
+        assert_eq!(
+            transactions
+                .iter()
+                .map(|tx| (tx.client_id, tx.transaction_id))
+                .collect_vec(),
+            vec![
+                (ClientId::new(0), TransactionId::new(0)),
+                (ClientId::new(0), TransactionId::new(1)),
+                (ClientId::new(1), TransactionId::new(1)),
+            ]
+        );
+        assert_eq!(
+            interner.borrow().client_name(ClientId::new(1)),
+            Some("c-0001")
+        );
+    }
+
+    #[test]
+    fn should_read_client_states_with_custom_headers() {
+        let csv = "id;available;held;total;locked
+1;1.5000;0.2500;1.7500;false
+2; 0.0000 ;0.0000;0.0000;true
+";
+
+        let mut config = ExporterConfig {
+            delimiter: b';',
+            ..Default::default()
+        };
+        config.headers.client = "id".into();
+
+        let states = read_client_states(csv.as_bytes(), &config).unwrap();
+
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].client_id(), ClientId::new(1));
+        assert_eq!(states[0].available(), Decimal::new(15, 1));
+        assert_eq!(states[0].held(), Decimal::new(25, 2));
+        assert_eq!(states[0].total(), Decimal::new(175, 2));
+        assert!(!states[0].locked());
+        assert!(states[1].available().is_zero());
+        assert!(states[1].locked());
+    }
 }
@@ -0,0 +1,145 @@
+use fxhash::FxHashMap;
+use std::cell::RefCell;
+use std::rc::Rc;
+use thiserror::Error;
+
+use crate::model::{ClientId, ClientIdRepr, TransactionId};
+
+/// Interner shared between importers, which assign IDs, and exporters, which resolve them back.
+pub type SharedInterner = Rc<RefCell<IdentifierInterner>>;
+
+/// Errors related to interning identifiers.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InternerError {
+    #[error("Too many distinct client identifiers, cannot intern: {0}")]
+    ClientIdsExhausted(String),
+    #[error("Too many distinct transaction identifiers, cannot intern: {0}")]
+    TransactionIdsExhausted(String),
+}
+
+impl InternerError {
+    /// Returns a stable, machine-readable code of this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            InternerError::ClientIdsExhausted(_) => "E_CLIENT_IDS_EXHAUSTED",
+            InternerError::TransactionIdsExhausted(_) => "E_TRANSACTION_IDS_EXHAUSTED",
+        }
+    }
+}
+
+/// Maps opaque string identifiers (e.g. UUIDs) to dense integer IDs, so the rest of the engine
+/// can keep using compact keys in its maps.
+#[derive(Default, Debug)]
+pub struct IdentifierInterner {
+    clients: Interner,
+    transactions: Interner,
+}
+
+impl IdentifierInterner {
+    /// Creates a new interner, shareable between an importer and an exporter.
+    pub fn new_shared() -> SharedInterner {
+        Default::default()
+    }
+
+    /// Returns the client ID for given external identifier, assigning a new one if needed.
+    pub fn client_id(&mut self, name: &str) -> Result<ClientId, InternerError> {
+        self.clients
+            .intern(name, ClientIdRepr::MAX as usize)
+            .map(|index| ClientId::new(index as ClientIdRepr))
+            .ok_or_else(|| InternerError::ClientIdsExhausted(name.into()))
+    }
+
+    /// Returns the transaction ID for given external identifier, assigning a new one if needed.
+    pub fn transaction_id(&mut self, name: &str) -> Result<TransactionId, InternerError> {
+        self.transactions
+            .intern(name, u32::MAX as usize)
+            .map(|index| TransactionId::new(index as u32))
+            .ok_or_else(|| InternerError::TransactionIdsExhausted(name.into()))
+    }
+
+    /// Returns the external identifier of given client, if it has been interned.
+    pub fn client_name(&self, client_id: ClientId) -> Option<&str> {
+        self.clients.resolve(ClientIdRepr::from(client_id) as usize)
+    }
+
+    /// Returns the external identifier of given transaction, if it has been interned.
+    pub fn transaction_name(&self, transaction_id: TransactionId) -> Option<&str> {
+        self.transactions
+            .resolve(u32::from(transaction_id) as usize)
+    }
+}
+
+// single namespace of interned strings; strings are shared between the lookup map and the reverse
+// index, so each one is stored only once
+#[derive(Default, Debug)]
+struct Interner {
+    indices: FxHashMap<Rc<str>, usize>,
+    names: Vec<Rc<str>>,
+}
+
+impl Interner {
+    fn intern(&mut self, name: &str, max_index: usize) -> Option<usize> {
+        if let Some(index) = self.indices.get(name) {
+            return Some(*index);
+        }
+
+        let index = self.names.len();
+        if index > max_index {
+            return None;
+        }
+
+        let name: Rc<str> = name.into();
+        self.indices.insert(name.clone(), index);
+        self.names.push(name);
+
+        Some(index)
+    }
+
+    #[inline]
+    fn resolve(&self, index: usize) -> Option<&str> {
+        self.names.get(index).map(|name| name.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::interner::IdentifierInterner;
+    use crate::model::{ClientId, TransactionId};
+
+    #[test]
+    fn should_intern_identifiers() {
+        let mut interner = IdentifierInterner::default();
+
+        assert_eq!(interner.client_id("alice").unwrap(), ClientId::new(0));
+        assert_eq!(interner.client_id("bob").unwrap(), ClientId::new(1));
+        assert_eq!(interner.client_id("alice").unwrap(), ClientId::new(0));
+        assert_eq!(
+            interner.transaction_id("alice").unwrap(),
+            TransactionId::new(0)
+        );
+
+        assert_eq!(interner.client_name(ClientId::new(1)), Some("bob"));
+        assert_eq!(
+            interner.transaction_name(TransactionId::new(0)),
+            Some("alice")
+        );
+        assert_eq!(interner.client_name(ClientId::new(2)), None);
+    }
+
+    #[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
+    #[test]
+    fn should_reject_exhausted_client_ids() {
+        use crate::interner::InternerError;
+        use crate::model::ClientIdRepr;
+
+        let mut interner = IdentifierInterner::default();
+        for index in 0..=ClientIdRepr::MAX as usize {
+            interner.clients.intern(&index.to_string(), usize::MAX);
+        }
+
+        assert_eq!(
+            interner.client_id("overflow").unwrap_err(),
+            InternerError::ClientIdsExhausted("overflow".into())
+        );
+    }
+}
@@ -0,0 +1,187 @@
+use rust_decimal::Decimal;
+use thiserror::Error;
+
+use crate::model::{ClientId, ClientState, Transaction};
+use crate::service::{ProcessingContext, TransactionState};
+
+/// Violated ledger invariant of a single client.
+#[derive(Error, Debug, Clone, PartialEq, Eq)]
+pub enum InvariantViolation {
+    #[error(
+        "Total {total} of client {client_id} doesn't equal available {available} plus held {held}"
+    )]
+    Total {
+        client_id: ClientId,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+    },
+    #[error("Held {held} of client {client_id} doesn't equal disputed amount {disputed}")]
+    Held {
+        client_id: ClientId,
+        held: Decimal,
+        disputed: Decimal,
+    },
+}
+
+impl InvariantViolation {
+    pub fn client_id(&self) -> ClientId {
+        match self {
+            InvariantViolation::Total { client_id, .. }
+            | InvariantViolation::Held { client_id, .. } => *client_id,
+        }
+    }
+}
+
+/// Transaction after which a ledger invariant was violated, along with client states before and
+/// after it.
+#[derive(Error, Debug, Clone)]
+#[error(
+    "{violation}; after {} {} with amount {}, state changed from {} to {}",
+    .transaction.r#type.as_str(),
+    .transaction.transaction_id,
+    display_amount(&.transaction.amount),
+    display_state(.before),
+    display_state(.after)
+)]
+pub struct ViolatingTransaction {
+    pub transaction: Transaction,
+    pub before: ClientState,
+    pub after: ClientState,
+    #[source]
+    pub violation: InvariantViolation,
+}
+
+/// Checks ledger invariants of given client: total equals available plus held funds, and held
+/// funds equal the sum of disputed transaction amounts. Clients without any processed
+/// transactions trivially pass.
+pub fn check_client(
+    context: &ProcessingContext,
+    client_id: ClientId,
+) -> Result<(), InvariantViolation> {
+    let Some(state) = context.client_state(client_id) else {
+        return Ok(());
+    };
+
+    let (available, held, total) = (state.available(), state.held(), state.total());
+    if available.checked_add(held) != Some(total) {
+        return Err(InvariantViolation::Total {
+            client_id,
+            available,
+            held,
+            total,
+        });
+    }
+
+    // a sum exceeding the decimal range can't match held funds, which are range-checked
+    let disputed = context
+        .client_transactions(client_id)
+        .filter(|(_, info)| info.state == TransactionState::Disputed)
+        .fold(Decimal::ZERO, |sum, (_, info)| {
+            sum.saturating_add(info.amount)
+        });
+    if disputed != held {
+        return Err(InvariantViolation::Held {
+            client_id,
+            held,
+            disputed,
+        });
+    }
+
+    Ok(())
+}
+
+/// Checks ledger invariants of all clients in given context, e.g. of a loaded snapshot. Returns
+/// the first violation of each violating client.
+pub fn check_context(context: &ProcessingContext) -> Vec<InvariantViolation> {
+    context
+        .client_states()
+        .filter_map(|state| check_client(context, state.client_id()).err())
+        .collect()
+}
+
+fn display_amount(amount: &Option<Decimal>) -> String {
+    amount.map_or_else(|| "none".into(), |amount| amount.to_string())
+}
+
+fn display_state(state: &ClientState) -> String {
+    format!(
+        "(available {}, held {}, total {}, locked {})",
+        state.available(),
+        state.held(),
+        state.total(),
+        state.locked()
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::invariants::{check_client, check_context, InvariantViolation};
+    use crate::model::{ClientId, ClientState, TransactionId, TransactionType};
+    use crate::service::{ProcessingContext, TransactionInfo, TransactionState};
+
+    #[test]
+    fn should_detect_total_and_held_mismatches() {
+        let mut context = ProcessingContext::default();
+        let client_id = ClientId::new(1);
+        context.insert_client_state(ClientState::from_parts(
+            client_id,
+            Decimal::from(2),
+            Decimal::from(3),
+            Decimal::from(5),
+            false,
+        ));
+        context.insert_transaction(
+            client_id,
+            TransactionId::new(1),
+            TransactionInfo {
+                amount: Decimal::from(3),
+                state: TransactionState::Disputed,
+                r#type: TransactionType::Deposit,
+            },
+        );
+        assert_eq!(check_client(&context, client_id), Ok(()));
+
+        context.insert_transaction(
+            client_id,
+            TransactionId::new(2),
+            TransactionInfo {
+                amount: Decimal::from(1),
+                state: TransactionState::Disputed,
+                r#type: TransactionType::Deposit,
+            },
+        );
+        assert_eq!(
+            check_client(&context, client_id),
+            Err(InvariantViolation::Held {
+                client_id,
+                held: Decimal::from(3),
+                disputed: Decimal::from(4),
+            })
+        );
+
+        let other_id = ClientId::new(2);
+        context.insert_client_state(ClientState::from_parts(
+            other_id,
+            Decimal::from(2),
+            Decimal::ZERO,
+            Decimal::from(3),
+            false,
+        ));
+
+        let mut violations = check_context(&context);
+        violations.sort_by_key(|violation| violation.client_id().to_string());
+        assert_eq!(violations.len(), 2);
+        assert_eq!(
+            violations[1],
+            InvariantViolation::Total {
+                client_id: other_id,
+                available: Decimal::from(2),
+                held: Decimal::ZERO,
+                total: Decimal::from(3),
+            }
+        );
+    }
+}
@@ -1,4 +1,27 @@
+#[cfg(feature = "arrow")]
+pub mod columnar;
+pub mod compression;
+pub mod config;
+pub mod diff;
 pub mod exporter;
+pub mod fraud;
+#[cfg(feature = "generator")]
+pub mod generator;
+#[cfg(feature = "grpc")]
+pub mod grpc;
 pub mod importer;
+pub mod interner;
+pub mod invariants;
+#[cfg(feature = "metrics")]
+pub mod metrics;
 pub mod model;
+pub mod observer;
+#[cfg(test)]
+mod proptests;
+pub mod reconcile;
+pub mod rules;
+#[cfg(feature = "server")]
+pub mod server;
 pub mod service;
+#[cfg(feature = "sqlite")]
+pub mod sqlite;
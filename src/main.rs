@@ -1,27 +1,805 @@
-use anyhow::{anyhow, Context, Result};
-use csv::Writer;
-use std::env;
-use std::io::stdout;
+use anyhow::{bail, Context, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use rust_decimal::Decimal;
+use std::fs::File;
+#[cfg(any(feature = "server", feature = "grpc"))]
+use std::future::Future;
+#[cfg(feature = "sqlite")]
+use std::io::Write;
+use std::io::{stderr, stdout, IsTerminal};
+#[cfg(any(feature = "server", feature = "grpc"))]
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+#[cfg(any(feature = "server", feature = "grpc"))]
+use tokio::net::TcpListener;
+use tracing_subscriber::EnvFilter;
 
-use simple_csv_tx_engine::importer::TransactionCsvImporter;
-use simple_csv_tx_engine::service::TransactionProcessor;
+#[cfg(feature = "arrow")]
+use simple_csv_tx_engine::columnar::{
+    ArrowClientStateExporter, ArrowTransactionImporter, ColumnarFormat,
+};
+use simple_csv_tx_engine::compression::{compress, open_decompressed, Compression, Encoder};
+use simple_csv_tx_engine::config::Config;
+use simple_csv_tx_engine::diff::{diff_client_states, write_state_changes};
+use simple_csv_tx_engine::exporter::{
+    ClientStateCsvExporter, ClientStateExporter, ExporterConfig, FlaggedAccountCsvExporter,
+    TransactionResultCsvExporter, TransactionResultExporter,
+};
+use simple_csv_tx_engine::fraud::{FraudConfig, FraudDetector};
+use simple_csv_tx_engine::generator::{
+    write_rows, TransactionGenerator, TransactionMix, MAX_CLIENTS,
+};
+#[cfg(feature = "grpc")]
+use simple_csv_tx_engine::grpc::GrpcTransactionServer;
+use simple_csv_tx_engine::importer::{
+    read_client_states, ImporterConfig, TransactionCsvFilesImporter, TransactionImporter,
+    STDIN_INPUT,
+};
+use simple_csv_tx_engine::interner::{IdentifierInterner, SharedInterner};
+#[cfg(feature = "sqlite")]
+use simple_csv_tx_engine::invariants::check_context;
+#[cfg(feature = "metrics")]
+use simple_csv_tx_engine::metrics::Metrics;
+use simple_csv_tx_engine::model::ClientState;
+use simple_csv_tx_engine::reconcile::{ReconcilingExporter, Tolerance};
+#[cfg(any(feature = "server", feature = "grpc"))]
+use simple_csv_tx_engine::rules::RuleSet;
+use simple_csv_tx_engine::rules::RulesConfig;
+#[cfg(feature = "server")]
+use simple_csv_tx_engine::server::TransactionServer;
+#[cfg(any(feature = "server", feature = "grpc"))]
+use simple_csv_tx_engine::service::ProcessingContext;
+use simple_csv_tx_engine::service::{ContextStore, TransactionProcessor};
+#[cfg(feature = "sqlite")]
+use simple_csv_tx_engine::sqlite::{SqliteClientStateExporter, SqliteContextStore};
+
+/// Processes transactions from CSV files and writes resulting client states to stdout as CSV.
+#[derive(Parser)]
+#[command(
+    version,
+    args_conflicts_with_subcommands = true,
+    subcommand_negates_reqs = true
+)]
+struct Cli {
+    #[command(subcommand)]
+    command: Option<Command>,
+
+    #[command(flatten)]
+    process: ProcessArgs,
+
+    #[command(flatten)]
+    logging: LoggingArgs,
+}
+
+#[derive(Args)]
+struct LoggingArgs {
+    /// Log level or filter directives, e.g. `info` or `simple_csv_tx_engine=debug`. Defaults to
+    /// `RUST_LOG`, or `error` if it's not set. Logs are written to stderr.
+    #[arg(long, global = true)]
+    log_level: Option<String>,
+
+    /// Log output format.
+    #[arg(long, global = true, value_enum, default_value = "text")]
+    log_format: LogFormat,
+}
+
+#[derive(Copy, Clone, ValueEnum)]
+enum LogFormat {
+    Text,
+    Json,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Processes transactions from input files; this is the default when no command is given.
+    Process(ProcessArgs),
+
+    /// Processes transactions like `process`, but rather than writing client states, compares
+    /// them against expected ones and writes a CSV report of discrepancies to stdout. Exits with
+    /// an error if any are found.
+    Reconcile(ReconcileArgs),
+
+    /// Compares two client state CSV exports and writes added and removed clients, along with
+    /// per-field deltas of changed ones, to stdout.
+    Diff(DiffArgs),
+
+    /// Checks ledger invariants of processing state persisted in a SQLite database, writing any
+    /// violations to stdout. Exits with an error if any are found.
+    #[cfg(feature = "sqlite")]
+    Check(CheckArgs),
+
+    /// Generates synthetic transactions in the input CSV format and writes them to stdout, e.g.
+    /// for load testing and demos.
+    Generate(GenerateArgs),
+
+    /// Runs an HTTP server, which applies incoming transactions and serves client states. Final
+    /// client states are written on shutdown (Ctrl-C or SIGTERM).
+    #[cfg(feature = "server")]
+    Serve(ServeArgs),
+
+    /// Runs a gRPC server, which applies streamed transactions and serves client states. Final
+    /// client states are written on shutdown (Ctrl-C or SIGTERM).
+    #[cfg(feature = "grpc")]
+    Grpc(GrpcArgs),
+}
+
+#[derive(Args)]
+struct ProcessArgs {
+    /// Input CSV files or glob patterns, processed in order as a single stream; use `-` for
+    /// stdin. Gzip or zstd compressed inputs are detected automatically. With the `arrow` feature,
+    /// inputs can also be Parquet or Arrow IPC files, as long as all of them are.
+    #[arg(required = true)]
+    inputs: Vec<String>,
+
+    /// Write a result record for every input row to given CSV file, with its status
+    /// (applied, rejected or ignored), error code and resulting client balances.
+    #[arg(long)]
+    results: Option<PathBuf>,
+
+    /// Write accounts flagged by fraud heuristics of the rules file to given CSV file, listing the
+    /// transactions which triggered them.
+    #[arg(long)]
+    flagged_accounts: Option<PathBuf>,
+
+    /// Check ledger invariants of the affected client after every transaction, stopping at the
+    /// first violating one. Slows processing down considerably.
+    #[arg(long)]
+    check_invariants: bool,
+
+    #[command(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Args)]
+struct ReconcileArgs {
+    /// Expected client states, in the same CSV format as exported ones.
+    #[arg(long)]
+    expected: PathBuf,
+
+    /// Maximum absolute difference of amounts, which is still considered a match; overrides
+    /// tolerance settings of the config file.
+    #[arg(long)]
+    tolerance: Option<Decimal>,
+
+    #[command(flatten)]
+    process: ProcessArgs,
+}
+
+#[derive(Args)]
+struct DiffArgs {
+    /// Older client state export; gzip or zstd compressed files are detected automatically.
+    old: PathBuf,
+
+    /// Newer client state export.
+    new: PathBuf,
+
+    /// TOML configuration file, whose exporter settings describe the CSV format of both exports.
+    #[arg(long)]
+    config: Option<PathBuf>,
+}
+
+#[cfg(feature = "sqlite")]
+#[derive(Args)]
+struct CheckArgs {
+    /// SQLite database with processing state, as written by `--state-db`.
+    state_db: PathBuf,
+}
+
+#[derive(Args)]
+struct GenerateArgs {
+    /// Number of transactions to generate.
+    #[arg(long, default_value = "10000")]
+    transactions: usize,
+
+    /// Number of clients, with IDs starting at 0.
+    #[arg(long, default_value = "100")]
+    clients: u64,
+
+    /// Relative weights of transaction types, as comma-separated `type=weight` pairs; omitted
+    /// types aren't generated.
+    #[arg(
+        long,
+        default_value = "deposit=60,withdrawal=25,dispute=8,resolve=5,chargeback=2"
+    )]
+    mix: TransactionMix,
+
+    /// Ratio of invalid transactions, which are rejected or ignored by the processor, between 0
+    /// and 1.
+    #[arg(long, default_value = "0")]
+    invalid_ratio: f64,
+
+    /// Ratio of malformed rows, which cannot be imported as transactions, between 0 and 1.
+    #[arg(long, default_value = "0")]
+    malformed_ratio: f64,
+
+    /// Seed of the random number generator; the same seed and settings always yield the same
+    /// output.
+    #[arg(long, default_value = "0")]
+    seed: u64,
+
+    /// Compression format for output data: none, gzip or zstd.
+    #[arg(long, default_value = "none")]
+    compress: Compression,
+}
+
+#[cfg(feature = "server")]
+#[derive(Args)]
+struct ServeArgs {
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:8080")]
+    listen: SocketAddr,
+
+    #[command(flatten)]
+    common: CommonArgs,
+}
+
+#[cfg(feature = "grpc")]
+#[derive(Args)]
+struct GrpcArgs {
+    /// Address to listen on.
+    #[arg(long, default_value = "127.0.0.1:50051")]
+    listen: SocketAddr,
+
+    #[command(flatten)]
+    common: CommonArgs,
+}
+
+#[derive(Args)]
+struct CommonArgs {
+    /// Compression format for output data: none, gzip or zstd.
+    #[arg(long, default_value = "none")]
+    compress: Compression,
+
+    /// Treat client and transaction identifiers as opaque strings (e.g. UUIDs), rather than
+    /// numbers.
+    #[arg(long)]
+    string_ids: bool,
+
+    /// TOML configuration file with CSV dialect, column mapping and amount limit settings.
+    #[arg(long)]
+    config: Option<PathBuf>,
+
+    /// TOML rules file with validation rules applied before each transaction.
+    #[arg(long)]
+    rules: Option<PathBuf>,
+
+    /// Write processing metrics in Prometheus text format to given file at the end of the run.
+    #[cfg(feature = "metrics")]
+    #[arg(long)]
+    metrics: Option<PathBuf>,
+
+    /// Write client states to given Parquet or Arrow IPC file, rather than to stdout; format is
+    /// detected by extension.
+    #[cfg(feature = "arrow")]
+    #[cfg_attr(
+        feature = "sqlite",
+        arg(long, conflicts_with_all = ["string_ids", "sqlite_output"])
+    )]
+    #[cfg_attr(not(feature = "sqlite"), arg(long, conflicts_with = "string_ids"))]
+    arrow_output: Option<PathBuf>,
+
+    /// Upsert client states into given SQLite database, rather than writing them to stdout.
+    #[cfg(feature = "sqlite")]
+    #[arg(long, conflicts_with = "string_ids")]
+    sqlite_output: Option<PathBuf>,
+
+    /// Persist processing state in given SQLite database, resuming from any previously saved
+    /// state.
+    #[cfg(feature = "sqlite")]
+    #[arg(long, conflicts_with = "string_ids")]
+    state_db: Option<PathBuf>,
+}
+
+impl CommonArgs {
+    fn load_config(&self) -> Result<Config> {
+        Ok(self
+            .config
+            .as_ref()
+            .map(Config::from_path)
+            .transpose()?
+            .unwrap_or_default())
+    }
+
+    fn load_rules(&self) -> Result<RulesConfig> {
+        let rules = self
+            .rules
+            .as_ref()
+            .map(RulesConfig::from_path)
+            .transpose()?
+            .unwrap_or_default();
+
+        // blocked clients are given by numeric IDs, which don't match interned ones
+        if self.string_ids && !rules.blocked_clients.is_empty() {
+            bail!("Blocked clients are not supported with string identifiers");
+        }
+
+        Ok(rules)
+    }
+}
 
 fn main() -> Result<()> {
-    // for more complex/generic apps, we should use a crate like `clap` for argument handling, but
-    // in this case, our app interface is well-defined and consistent + we're prioritizing speed
-    let input_file = env::args()
-        .nth(1)
-        .ok_or_else(|| anyhow!("Missing input file!"))?;
+    let cli = Cli::parse();
+    init_logging(&cli.logging)?;
 
-    // import from our input file; export to stdout by default
-    let importer = TransactionCsvImporter::from_path(&input_file)?;
+    match cli.command {
+        Some(Command::Process(args)) => process(args),
+        Some(Command::Reconcile(args)) => reconcile(args),
+        Some(Command::Diff(args)) => diff(args),
+        #[cfg(feature = "sqlite")]
+        Some(Command::Check(args)) => check(args),
+        Some(Command::Generate(args)) => generate(args),
+        #[cfg(feature = "server")]
+        Some(Command::Serve(args)) => serve(args),
+        #[cfg(feature = "grpc")]
+        Some(Command::Grpc(args)) => serve_grpc(args),
+        None => process(cli.process),
+    }
+}
 
-    // note: we're locking stdout upfront to avoid locking on every write; there's no need to add
-    // buffering, since `csv` already does that
-    let exporter = Writer::from_writer(stdout().lock());
+fn init_logging(args: &LoggingArgs) -> Result<()> {
+    let filter = match &args.log_level {
+        Some(directives) => EnvFilter::try_new(directives)
+            .with_context(|| format!("Invalid log level: {}", directives))?,
+        None => EnvFilter::try_from_default_env().unwrap_or_else(|_| EnvFilter::new("error")),
+    };
+
+    let subscriber = tracing_subscriber::fmt()
+        .with_env_filter(filter)
+        .with_writer(stderr)
+        .with_ansi(stderr().is_terminal());
+    match args.log_format {
+        LogFormat::Text => subscriber.init(),
+        // span fields are what correlates events of a single transaction
+        LogFormat::Json => subscriber
+            .json()
+            .with_current_span(true)
+            .with_span_list(false)
+            .init(),
+    }
+
+    Ok(())
+}
+
+fn process(args: ProcessArgs) -> Result<()> {
+    process_with(args, |common, config, interner| {
+        create_exporter(common, config.exporter.clone(), interner)
+    })
+}
+
+fn reconcile(args: ReconcileArgs) -> Result<()> {
+    // expected states are matched by numeric IDs, which don't match interned ones
+    if args.process.common.string_ids {
+        bail!("Reconciliation is not supported with string identifiers");
+    }
+
+    let expected_path = args.expected;
+    let tolerance = args.tolerance;
+    process_with(args.process, |common, config, _| {
+        let expected = load_client_states(&expected_path, &config.exporter)?;
+        let exporter = ReconcilingExporter::new(
+            expected,
+            compress(stdout().lock(), common.compress)?,
+            config.exporter.clone(),
+        )
+        .with_tolerance(tolerance.map_or(config.tolerance, Tolerance::uniform))
+        .with_finish(Encoder::finish);
+
+        Ok(Box::new(exporter))
+    })
+}
+
+fn diff(args: DiffArgs) -> Result<()> {
+    let config = args
+        .config
+        .as_ref()
+        .map(Config::from_path)
+        .transpose()?
+        .unwrap_or_default();
+
+    let old = load_client_states(&args.old, &config.exporter)?;
+    let new = load_client_states(&args.new, &config.exporter)?;
+
+    write_state_changes(
+        stdout().lock(),
+        &diff_client_states(&old, &new),
+        &config.exporter,
+    )
+}
+
+#[cfg(feature = "sqlite")]
+fn check(args: CheckArgs) -> Result<()> {
+    // opening a store creates a missing database, which would trivially pass
+    if !args.state_db.exists() {
+        bail!("State database not found: {}", args.state_db.display());
+    }
+
+    let context = SqliteContextStore::from_path(&args.state_db)?.load()?;
+    let violations = check_context(&context);
+
+    let mut output = stdout().lock();
+    for violation in &violations {
+        writeln!(output, "{}", violation)?;
+    }
+
+    if !violations.is_empty() {
+        bail!("{} ledger invariant violations found", violations.len());
+    }
+
+    Ok(())
+}
+
+fn generate(args: GenerateArgs) -> Result<()> {
+    if !(1..=MAX_CLIENTS).contains(&args.clients) {
+        bail!("Number of clients must be between 1 and {}", MAX_CLIENTS);
+    }
+
+    if !(0.0..=1.0).contains(&args.invalid_ratio) {
+        bail!("Invalid ratio must be between 0 and 1");
+    }
+
+    if !(0.0..=1.0).contains(&args.malformed_ratio) {
+        bail!("Malformed ratio must be between 0 and 1");
+    }
+
+    let generator = TransactionGenerator::new(args.seed)
+        .with_clients(args.clients)
+        .with_mix(args.mix)
+        .with_invalid_ratio(args.invalid_ratio)
+        .with_malformed_ratio(args.malformed_ratio);
+
+    let mut writer = compress(stdout().lock(), args.compress)?;
+    write_rows(&mut writer, generator.into_rows().take(args.transactions))?;
+    writer
+        .finish()
+        .context("Error finishing generated transactions")
+}
+
+// processes transactions, exporting client states to an exporter created by given function
+fn process_with<F>(args: ProcessArgs, create_exporter: F) -> Result<()>
+where
+    F: FnOnce(&CommonArgs, &Config, Option<SharedInterner>) -> Result<Box<dyn ClientStateExporter>>,
+{
+    let config = args.common.load_config()?;
+    let rules = args.common.load_rules()?;
+
+    let inputs = expand_inputs(&args.inputs)?;
+    let description = inputs.join(", ");
+
+    // string identifiers are mapped to numeric ones on import, and back on export
+    let interner = args.common.string_ids.then(IdentifierInterner::new_shared);
+
+    // import from our input files; export to stdout by default
+    let exporter = create_exporter(&args.common, &config, interner.clone())?;
+    let importer = create_importer(inputs, config.importer, interner.clone())?;
+    let result_exporter = args
+        .results
+        .as_ref()
+        .map(|path| create_result_exporter(path, config.exporter.clone(), interner.clone()))
+        .transpose()?;
+    let fraud_detector = (rules.fraud.is_enabled() || args.flagged_accounts.is_some())
+        .then(|| {
+            create_fraud_detector(
+                args.flagged_accounts.as_deref(),
+                rules.fraud,
+                config.exporter.clone(),
+                interner.clone(),
+            )
+        })
+        .transpose()?;
+    let mut processor = TransactionProcessor::new(importer, exporter)
+        .with_limits(config.limits)
+        .with_rules(rules.build());
+    if let Some(interner) = interner {
+        processor = processor.with_interner(interner);
+    }
+
+    if let Some(result_exporter) = result_exporter {
+        processor = processor.with_result_exporter(result_exporter);
+    }
+
+    if let Some(fraud_detector) = fraud_detector {
+        processor = processor.with_fraud_detector(fraud_detector);
+    }
+
+    if let Some(store) = create_store(&args.common)? {
+        processor = processor.with_store(store);
+    }
+
+    if args.check_invariants {
+        processor = processor.with_invariant_checks();
+    }
+
+    #[cfg(feature = "metrics")]
+    let metrics = Metrics::new();
+    #[cfg(feature = "metrics")]
+    if args.common.metrics.is_some() {
+        processor = processor.with_metrics(metrics.clone());
+    }
 
-    let processor = TransactionProcessor::new(importer, exporter);
     processor
         .process_transactions()
-        .with_context(|| format!("Error processing {}!", input_file))
+        .with_context(|| format!("Error processing {}!", description))?;
+
+    #[cfg(feature = "metrics")]
+    write_metrics(&args.common, &metrics)?;
+
+    Ok(())
+}
+
+#[cfg(feature = "server")]
+fn serve(args: ServeArgs) -> Result<()> {
+    run_server(&args.common, args.listen, |setup, listener| {
+        let server = TransactionServer::new(setup.context)
+            .with_limits(setup.config.limits)
+            .with_importer_config(setup.config.importer.clone())
+            .with_exporter_config(setup.config.exporter.clone())
+            .with_rules(setup.rules);
+
+        #[cfg(feature = "metrics")]
+        let server = server.with_metrics(setup.metrics);
+
+        server.serve(listener, shutdown_signal())
+    })
+}
+
+#[cfg(feature = "grpc")]
+fn serve_grpc(args: GrpcArgs) -> Result<()> {
+    run_server(&args.common, args.listen, |setup, listener| {
+        let server = GrpcTransactionServer::new(setup.context)
+            .with_limits(setup.config.limits)
+            .with_importer_config(setup.config.importer.clone())
+            .with_exporter_config(setup.config.exporter.clone())
+            .with_rules(setup.rules);
+
+        #[cfg(feature = "metrics")]
+        let server = server.with_metrics(setup.metrics);
+
+        server.serve(listener, shutdown_signal())
+    })
+}
+
+// everything a long-running server is created from
+#[cfg(any(feature = "server", feature = "grpc"))]
+struct ServerSetup<'a> {
+    context: ProcessingContext,
+    config: &'a Config,
+    rules: RuleSet,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+}
+
+#[cfg(any(feature = "server", feature = "grpc"))]
+fn run_server<F, R>(common: &CommonArgs, listen: SocketAddr, serve: F) -> Result<()>
+where
+    F: FnOnce(ServerSetup<'_>, TcpListener) -> R,
+    R: Future<Output = Result<ProcessingContext>>,
+{
+    // interned identifiers would need to be shared between request handlers, and persisted
+    if common.string_ids {
+        bail!("String identifiers are not supported in server mode");
+    }
+
+    let config = common.load_config()?;
+    let rules = common.load_rules()?;
+    if rules.fraud.is_enabled() {
+        bail!("Fraud heuristics are not supported in server mode");
+    }
+
+    let rules = rules.build();
+    #[cfg(feature = "metrics")]
+    let metrics = Metrics::new();
+    let mut store = create_store(common)?;
+    let context = match &mut store {
+        Some(store) => store.load()?,
+        None => Default::default(),
+    };
+
+    let context = tokio::runtime::Runtime::new()?.block_on(async {
+        let listener = TcpListener::bind(listen)
+            .await
+            .with_context(|| format!("Error listening on {}", listen))?;
+        tracing::info!(%listen, "Listening for requests");
+
+        let setup = ServerSetup {
+            context,
+            config: &config,
+            rules,
+            #[cfg(feature = "metrics")]
+            metrics: metrics.clone(),
+        };
+
+        serve(setup, listener).await
+    })?;
+
+    // write the final snapshot the same way a one-shot run would
+    let mut exporter = create_exporter(common, config.exporter, None)?;
+    context
+        .export_client_states(&mut exporter)
+        .context("Error writing final client states!")?;
+
+    #[cfg(feature = "metrics")]
+    {
+        metrics.update_gauges(&context);
+        write_metrics(common, &metrics)?;
+    }
+
+    match &mut store {
+        Some(store) => store
+            .save(&context)
+            .context("Error saving processing state!"),
+        None => Ok(()),
+    }
+}
+
+#[cfg(any(feature = "server", feature = "grpc"))]
+async fn shutdown_signal() {
+    let ctrl_c = async {
+        // without a signal handler, the server can only be killed, so keep serving
+        if tokio::signal::ctrl_c().await.is_err() {
+            std::future::pending::<()>().await;
+        }
+    };
+
+    #[cfg(unix)]
+    let terminate = async {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut terminate) => {
+                terminate.recv().await;
+            }
+            Err(_) => std::future::pending().await,
+        }
+    };
+
+    #[cfg(not(unix))]
+    let terminate = std::future::pending::<()>();
+
+    tokio::select! {
+        _ = ctrl_c => {},
+        _ = terminate => {},
+    }
+}
+
+#[cfg_attr(not(feature = "sqlite"), allow(unused_variables))]
+fn create_store(args: &CommonArgs) -> Result<Option<Box<dyn ContextStore>>> {
+    #[cfg(feature = "sqlite")]
+    if let Some(state_db) = &args.state_db {
+        return Ok(Some(Box::new(SqliteContextStore::from_path(state_db)?)));
+    }
+
+    Ok(None)
+}
+
+fn create_importer(
+    inputs: Vec<String>,
+    config: ImporterConfig,
+    interner: Option<SharedInterner>,
+) -> Result<Box<dyn TransactionImporter>> {
+    #[cfg(feature = "arrow")]
+    if inputs
+        .iter()
+        .all(|input| ColumnarFormat::from_extension(input).is_some())
+    {
+        if interner.is_some() {
+            bail!("String identifiers are not supported with columnar inputs");
+        }
+
+        return Ok(Box::new(
+            ArrowTransactionImporter::from_paths(inputs.into_iter().map(PathBuf::from).collect())
+                .with_config(config),
+        ));
+    }
+
+    let mut importer = TransactionCsvFilesImporter::new(inputs).with_config(config);
+    if let Some(interner) = interner {
+        importer = importer.with_interner(interner);
+    }
+
+    Ok(Box::new(importer))
+}
+
+fn create_exporter(
+    args: &CommonArgs,
+    config: ExporterConfig,
+    interner: Option<SharedInterner>,
+) -> Result<Box<dyn ClientStateExporter>> {
+    #[cfg(feature = "arrow")]
+    if let Some(arrow_output) = &args.arrow_output {
+        return Ok(Box::new(ArrowClientStateExporter::from_path(
+            arrow_output,
+            config,
+        )?));
+    }
+
+    #[cfg(feature = "sqlite")]
+    if let Some(sqlite_output) = &args.sqlite_output {
+        return Ok(Box::new(SqliteClientStateExporter::from_path(
+            sqlite_output,
+        )?));
+    }
+
+    // note: we're locking stdout upfront to avoid locking on every write; there's no need to add
+    // buffering, since `csv` already does that
+    let mut exporter =
+        ClientStateCsvExporter::new(compress(stdout().lock(), args.compress)?, config)
+            .with_finish(Encoder::finish);
+    if let Some(interner) = interner {
+        exporter = exporter.with_interner(interner);
+    }
+
+    Ok(Box::new(exporter))
+}
+
+fn load_client_states(path: &Path, config: &ExporterConfig) -> Result<Vec<ClientState>> {
+    let error_context = || format!("Error reading {}", path.display());
+    let reader = open_decompressed(path).with_context(error_context)?;
+    read_client_states(reader, config).with_context(error_context)
+}
+
+fn create_result_exporter(
+    path: &Path,
+    config: ExporterConfig,
+    interner: Option<SharedInterner>,
+) -> Result<Box<dyn TransactionResultExporter>> {
+    let file = File::create(path).with_context(|| format!("Error creating {}", path.display()))?;
+
+    let mut exporter = TransactionResultCsvExporter::new(file, config);
+    if let Some(interner) = interner {
+        exporter = exporter.with_interner(interner);
+    }
+
+    Ok(Box::new(exporter))
+}
+
+#[cfg(feature = "metrics")]
+fn write_metrics(args: &CommonArgs, metrics: &Metrics) -> Result<()> {
+    match &args.metrics {
+        Some(path) => metrics.write_to_path(path),
+        None => Ok(()),
+    }
+}
+
+fn create_fraud_detector(
+    path: Option<&Path>,
+    fraud: FraudConfig,
+    config: ExporterConfig,
+    interner: Option<SharedInterner>,
+) -> Result<FraudDetector> {
+    let detector = FraudDetector::new(fraud);
+    let path = match path {
+        Some(path) => path,
+        None => return Ok(detector),
+    };
+
+    let file = File::create(path).with_context(|| format!("Error creating {}", path.display()))?;
+
+    let mut exporter = FlaggedAccountCsvExporter::new(file, config);
+    if let Some(interner) = interner {
+        exporter = exporter.with_interner(interner);
+    }
+
+    Ok(detector.with_exporter(Box::new(exporter)))
+}
+
+fn expand_inputs(inputs: &[String]) -> Result<Vec<String>> {
+    let mut result = Vec::with_capacity(inputs.len());
+    for input in inputs {
+        // leave plain paths alone, so missing files are reported when opened
+        if input == STDIN_INPUT || !input.contains(['*', '?', '[']) {
+            result.push(input.clone());
+            continue;
+        }
+
+        // glob returns paths in alphabetical order, which gives us stable processing order
+        let expanded_len = result.len();
+        for path in glob::glob(input).with_context(|| format!("Invalid pattern: {}", input))? {
+            result.push(path?.to_string_lossy().into_owned());
+        }
+
+        if result.len() == expanded_len {
+            bail!("No input files match pattern: {}", input);
+        }
+    }
+
+    Ok(result)
 }
@@ -0,0 +1,180 @@
+use anyhow::{Context, Result};
+use prometheus::{
+    exponential_buckets, Encoder, Histogram, HistogramOpts, IntCounterVec, IntGauge, Opts,
+    Registry, TextEncoder,
+};
+use std::fs;
+use std::path::Path;
+use std::time::Duration;
+
+use crate::model::Transaction;
+use crate::service::{ProcessingContext, TransactionState, TransactionStatus};
+
+/// Content type of the Prometheus text exposition format.
+pub const CONTENT_TYPE: &str = "text/plain; version=0.0.4";
+
+/// Processing metrics, exposed in Prometheus text format:
+///
+/// * `engine_transactions_total` - processed transactions by `type` and `outcome` (applied,
+///   rejected or ignored),
+/// * `engine_transaction_processing_seconds` - histogram of time spent applying a transaction,
+/// * `engine_clients`, `engine_tracked_transactions` and `engine_open_disputes` - gauges of
+///   processing context size, updated via [`update_gauges`](Metrics::update_gauges).
+///
+/// Clones share the same underlying metrics, so they can be handed out to concurrent handlers.
+#[derive(Clone)]
+pub struct Metrics {
+    registry: Registry,
+    transactions: IntCounterVec,
+    latency: Histogram,
+    clients: IntGauge,
+    tracked_transactions: IntGauge,
+    open_disputes: IntGauge,
+}
+
+impl Metrics {
+    /// Creates a new set of metrics, with all values at zero.
+    pub fn new() -> Self {
+        let transactions = IntCounterVec::new(
+            Opts::new(
+                "engine_transactions_total",
+                "Processed transactions by type and outcome",
+            ),
+            &["type", "outcome"],
+        )
+        .expect("Transaction counter should be valid");
+
+        // from 1 microsecond up to ~0.26 seconds
+        let latency = Histogram::with_opts(
+            HistogramOpts::new(
+                "engine_transaction_processing_seconds",
+                "Time spent applying a single transaction",
+            )
+            .buckets(exponential_buckets(1e-6, 4.0, 10).expect("Buckets should be valid")),
+        )
+        .expect("Latency histogram should be valid");
+
+        let clients = IntGauge::new("engine_clients", "Number of known clients")
+            .expect("Client gauge should be valid");
+        let tracked_transactions = IntGauge::new(
+            "engine_tracked_transactions",
+            "Number of transactions which can be referenced by disputes",
+        )
+        .expect("Tracked transaction gauge should be valid");
+        let open_disputes = IntGauge::new("engine_open_disputes", "Number of open disputes")
+            .expect("Open dispute gauge should be valid");
+
+        let registry = Registry::new();
+        for collector in [
+            Box::new(transactions.clone()) as Box<dyn prometheus::core::Collector>,
+            Box::new(latency.clone()),
+            Box::new(clients.clone()),
+            Box::new(tracked_transactions.clone()),
+            Box::new(open_disputes.clone()),
+        ] {
+            registry
+                .register(collector)
+                .expect("Metric names should be unique");
+        }
+
+        Self {
+            registry,
+            transactions,
+            latency,
+            clients,
+            tracked_transactions,
+            open_disputes,
+        }
+    }
+
+    /// Records the outcome of processing a single transaction.
+    pub fn record(&self, transaction: &Transaction, status: TransactionStatus, latency: Duration) {
+        self.transactions
+            .with_label_values(&[transaction.r#type.as_str(), status.as_str()])
+            .inc();
+        self.latency.observe(latency.as_secs_f64());
+    }
+
+    /// Updates gauges from given processing context. Requires going through all tracked
+    /// transactions, so should be called when exposing metrics, rather than per transaction.
+    pub fn update_gauges(&self, context: &ProcessingContext) {
+        let mut tracked_transactions = 0;
+        let mut open_disputes = 0;
+        for (_, _, info) in context.transactions() {
+            tracked_transactions += 1;
+            if info.state == TransactionState::Disputed {
+                open_disputes += 1;
+            }
+        }
+
+        self.clients.set(context.client_states().count() as i64);
+        self.tracked_transactions.set(tracked_transactions);
+        self.open_disputes.set(open_disputes);
+    }
+
+    /// Encodes all metrics in Prometheus text format.
+    pub fn encode(&self) -> String {
+        let mut buffer = Vec::new();
+        // text encoding only fails for invalid metric families, which we never register
+        TextEncoder::new()
+            .encode(&self.registry.gather(), &mut buffer)
+            .expect("Metrics should be encodable");
+
+        String::from_utf8(buffer).expect("Text encoding should be valid UTF-8")
+    }
+
+    /// Writes all metrics to given file in Prometheus text format, e.g. for the node exporter
+    /// textfile collector.
+    pub fn write_to_path<P: AsRef<Path>>(&self, path: P) -> Result<()> {
+        let path = path.as_ref();
+        fs::write(path, self.encode())
+            .with_context(|| format!("Error writing metrics to {}", path.display()))
+    }
+}
+
+impl Default for Metrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::importer::TransactionCsvImporter;
+    use crate::metrics::Metrics;
+    use crate::service::TransactionProcessor;
+
+    #[test]
+    fn should_count_transactions_and_context_size() {
+        let csv = "type,client,tx,amount
+deposit,1,1,2
+deposit,2,2,2
+withdrawal,1,3,5
+dispute,1,1,
+dispute,1,9,
+";
+
+        let metrics = Metrics::new();
+        TransactionProcessor::new(
+            TransactionCsvImporter::from_reader(csv.as_bytes()),
+            csv::Writer::from_writer(vec![]),
+        )
+        .with_metrics(metrics.clone())
+        .process_transactions()
+        .unwrap();
+
+        let text = metrics.encode();
+        for line in [
+            r#"engine_transactions_total{outcome="applied",type="deposit"} 2"#,
+            r#"engine_transactions_total{outcome="rejected",type="withdrawal"} 1"#,
+            r#"engine_transactions_total{outcome="applied",type="dispute"} 1"#,
+            r#"engine_transactions_total{outcome="ignored",type="dispute"} 1"#,
+            "engine_transaction_processing_seconds_count 5",
+            "engine_clients 2",
+            "engine_tracked_transactions 2",
+            "engine_open_disputes 1",
+        ] {
+            assert!(text.contains(line), "missing {} in:\n{}", line, text);
+        }
+    }
+}
@@ -1,16 +1,37 @@
-use derive_more::{Constructor, Display};
-use rust_decimal::Decimal;
+use derive_more::{Constructor, Display, Into};
+use rust_decimal::{Decimal, RoundingStrategy};
+use serde::ser::SerializeMap;
 use serde::{Deserialize, Deserializer, Serialize, Serializer};
+use std::str::FromStr;
 use thiserror::Error;
 
+use crate::exporter::ClientStateRecord;
+
+/// Underlying client ID representation, widened by the `client-id-u64` feature.
+#[cfg(feature = "client-id-u64")]
+pub type ClientIdRepr = u64;
+
+/// Underlying client ID representation, widened by the `client-id-u32` feature.
+#[cfg(all(feature = "client-id-u32", not(feature = "client-id-u64")))]
+pub type ClientIdRepr = u32;
+
+/// Underlying client ID representation. Defaults to `u16`, which keeps client maps compact; wider
+/// ID spaces can be enabled with `client-id-u32` or `client-id-u64` features.
+#[cfg(not(any(feature = "client-id-u32", feature = "client-id-u64")))]
+pub type ClientIdRepr = u16;
+
 /// Domain-specific client ID.
 #[repr(transparent)]
-#[derive(Deserialize, Serialize, Debug, Constructor, Eq, PartialEq, Display, Copy, Clone, Hash)]
-pub struct ClientId(u16);
+#[derive(
+    Deserialize, Serialize, Debug, Constructor, Into, Eq, PartialEq, Display, Copy, Clone, Hash,
+)]
+pub struct ClientId(ClientIdRepr);
 
 /// Domain-specific transaction ID.
 #[repr(transparent)]
-#[derive(Deserialize, Serialize, Debug, Constructor, Eq, PartialEq, Display, Copy, Clone, Hash)]
+#[derive(
+    Deserialize, Serialize, Debug, Constructor, Into, Eq, PartialEq, Display, Copy, Clone, Hash,
+)]
 pub struct TransactionId(u32);
 
 /// Possible transaction type.
@@ -24,6 +45,34 @@ pub enum TransactionType {
     Chargeback,
 }
 
+impl TransactionType {
+    /// Returns the canonical (input data) name of this type.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransactionType::Deposit => "deposit",
+            TransactionType::Withdrawal => "withdrawal",
+            TransactionType::Dispute => "dispute",
+            TransactionType::Resolve => "resolve",
+            TransactionType::Chargeback => "chargeback",
+        }
+    }
+}
+
+impl FromStr for TransactionType {
+    type Err = String;
+
+    fn from_str(value: &str) -> Result<Self, Self::Err> {
+        match value {
+            "deposit" => Ok(TransactionType::Deposit),
+            "withdrawal" => Ok(TransactionType::Withdrawal),
+            "dispute" => Ok(TransactionType::Dispute),
+            "resolve" => Ok(TransactionType::Resolve),
+            "chargeback" => Ok(TransactionType::Chargeback),
+            _ => Err(format!("Unknown transaction type: {}", value)),
+        }
+    }
+}
+
 /// A single transaction to process.
 #[derive(Deserialize, Debug, Eq, PartialEq, Copy, Clone)]
 pub struct Transaction {
@@ -35,10 +84,46 @@ pub struct Transaction {
     #[serde(rename = "tx")]
     pub transaction_id: TransactionId,
 
-    #[serde(deserialize_with = "deserialize_optional_decimal")]
+    #[serde(default, deserialize_with = "deserialize_optional_decimal")]
     pub amount: Option<Decimal>,
 }
 
+/// Rounding mode for decimal amounts.
+#[derive(Deserialize, Debug, Eq, PartialEq, Copy, Clone)]
+#[serde(rename_all = "snake_case")]
+pub enum RoundingMode {
+    /// Round to the nearest value, with midpoints rounded to the nearest even number.
+    HalfEven,
+    /// Round to the nearest value, with midpoints rounded away from zero.
+    HalfUp,
+    /// Round to the nearest value, with midpoints rounded towards zero.
+    HalfDown,
+    /// Truncate towards zero.
+    Down,
+    /// Round away from zero.
+    Up,
+}
+
+impl RoundingMode {
+    /// Rounds given value to given number of decimal places.
+    #[inline]
+    pub fn round(self, value: Decimal, scale: u32) -> Decimal {
+        value.round_dp_with_strategy(scale, self.into())
+    }
+}
+
+impl From<RoundingMode> for RoundingStrategy {
+    fn from(mode: RoundingMode) -> Self {
+        match mode {
+            RoundingMode::HalfEven => RoundingStrategy::MidpointNearestEven,
+            RoundingMode::HalfUp => RoundingStrategy::MidpointAwayFromZero,
+            RoundingMode::HalfDown => RoundingStrategy::MidpointTowardZero,
+            RoundingMode::Down => RoundingStrategy::ToZero,
+            RoundingMode::Up => RoundingStrategy::AwayFromZero,
+        }
+    }
+}
+
 /// Errors related to invalid transaction operations.
 #[derive(Debug, Error, Copy, Clone, PartialEq, Eq)]
 pub enum TransactionError {
@@ -48,24 +133,110 @@ pub enum TransactionError {
     InsufficientFunds,
     #[error("Operation not permitted on a locked account!")]
     AccountLocked,
+    #[error("Amount {amount} is below the minimum of {min}")]
+    AmountBelowMinimum { amount: Decimal, min: Decimal },
+    #[error("Amount {amount} exceeds the maximum of {max}")]
+    AmountAboveMaximum { amount: Decimal, max: Decimal },
+    #[error("Arithmetic overflow while applying the amount!")]
+    Overflow,
 }
 
-/// Single client state after applying a list of transactions.
-#[derive(Serialize, Debug, Copy, Clone)]
+impl TransactionError {
+    /// Returns a stable, machine-readable code of this error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            TransactionError::InvalidAmount(_) => "E_INVALID_AMOUNT",
+            TransactionError::InsufficientFunds => "E_INSUFFICIENT_FUNDS",
+            TransactionError::AccountLocked => "E_ACCOUNT_LOCKED",
+            TransactionError::AmountBelowMinimum { .. } => "E_AMOUNT_BELOW_MINIMUM",
+            TransactionError::AmountAboveMaximum { .. } => "E_AMOUNT_ABOVE_MAXIMUM",
+            TransactionError::Overflow => "E_OVERFLOW",
+        }
+    }
+
+    /// Returns the severity of this error.
+    pub fn severity(&self) -> Severity {
+        match self {
+            TransactionError::InsufficientFunds
+            | TransactionError::AccountLocked
+            | TransactionError::AmountBelowMinimum { .. }
+            | TransactionError::AmountAboveMaximum { .. } => Severity::Warning,
+            TransactionError::InvalidAmount(_) | TransactionError::Overflow => Severity::Error,
+        }
+    }
+
+    /// Serializes structured fields of this error, if any, as map entries.
+    pub(crate) fn serialize_fields<M: SerializeMap>(&self, map: &mut M) -> Result<(), M::Error> {
+        match self {
+            TransactionError::InvalidAmount(amount) => map.serialize_entry("amount", amount),
+            TransactionError::AmountBelowMinimum { amount, min } => {
+                map.serialize_entry("amount", amount)?;
+                map.serialize_entry("min", min)
+            }
+            TransactionError::AmountAboveMaximum { amount, max } => {
+                map.serialize_entry("amount", amount)?;
+                map.serialize_entry("max", max)
+            }
+            TransactionError::InsufficientFunds
+            | TransactionError::AccountLocked
+            | TransactionError::Overflow => Ok(()),
+        }
+    }
+}
+
+/// Serializes as a map of `code`, `severity`, `message` and any structured fields.
+impl Serialize for TransactionError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("severity", &self.severity())?;
+        map.serialize_entry("message", &self.to_string())?;
+        self.serialize_fields(&mut map)?;
+        map.end()
+    }
+}
+
+/// Severity of processing errors, from least to most severe.
+#[derive(Serialize, Debug, Copy, Clone, Eq, PartialEq, Ord, PartialOrd, Hash)]
+#[serde(rename_all = "lowercase")]
+pub enum Severity {
+    /// Valid transaction rejected by business rules, e.g. due to insufficient funds.
+    Warning,
+    /// Malformed or invalid transaction data.
+    Error,
+    /// Error which stops processing altogether, e.g. unreadable input.
+    Fatal,
+}
+
+impl Severity {
+    /// Returns the canonical (output data) name of this severity.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            Severity::Warning => "warning",
+            Severity::Error => "error",
+            Severity::Fatal => "fatal",
+        }
+    }
+}
+
+/// Single client state after applying a list of transactions. Serialized with amounts rounded like
+/// exported ones by default, so totals always equal the sum of displayed parts.
+#[derive(Deserialize, Serialize, Debug, Copy, Clone)]
+#[serde(into = "ClientStateRecord<'static>")]
 pub struct ClientState {
     #[serde(rename = "client")]
     client_id: ClientId,
 
     /// The total funds that are available for trading, staking, withdrawal, etc.
-    #[serde(serialize_with = "serialize_with_fixed_precision")]
+    #[serde(deserialize_with = "rust_decimal::serde::str::deserialize")]
     available: Decimal,
 
     /// The total funds that are held for dispute.
-    #[serde(serialize_with = "serialize_with_fixed_precision")]
+    #[serde(deserialize_with = "rust_decimal::serde::str::deserialize")]
     held: Decimal,
 
     /// The total funds that are available or held.
-    #[serde(serialize_with = "serialize_with_fixed_precision")]
+    #[serde(deserialize_with = "rust_decimal::serde::str::deserialize")]
     total: Decimal,
 
     /// Whether the account is locked.
@@ -85,14 +256,35 @@ impl ClientState {
         }
     }
 
+    /// Restores a previously persisted state.
+    #[inline]
+    pub fn from_parts(
+        client_id: ClientId,
+        available: Decimal,
+        held: Decimal,
+        total: Decimal,
+        locked: bool,
+    ) -> Self {
+        Self {
+            client_id,
+            available,
+            held,
+            total,
+            locked,
+        }
+    }
+
     /// Deposits some funds into the account, increasing the available amount.
     pub fn deposit(&mut self, amount: Decimal) -> Result<(), TransactionError> {
-        if amount.is_sign_negative() {
+        if amount.is_sign_negative() || amount.is_zero() {
             return Err(TransactionError::InvalidAmount(amount));
         }
 
-        self.available += amount;
-        self.total += amount;
+        let available = checked_add(self.available, amount)?;
+        let total = checked_add(self.total, amount)?;
+
+        self.available = available;
+        self.total = total;
 
         Ok(())
     }
@@ -103,7 +295,7 @@ impl ClientState {
             return Err(TransactionError::AccountLocked);
         }
 
-        if amount.is_sign_negative() {
+        if amount.is_sign_negative() || amount.is_zero() {
             return Err(TransactionError::InvalidAmount(amount));
         }
 
@@ -111,8 +303,11 @@ impl ClientState {
             return Err(TransactionError::InsufficientFunds);
         }
 
-        self.available -= amount;
-        self.total -= amount;
+        let available = checked_sub(self.available, amount)?;
+        let total = checked_sub(self.total, amount)?;
+
+        self.available = available;
+        self.total = total;
 
         Ok(())
     }
@@ -127,8 +322,11 @@ impl ClientState {
             return Err(TransactionError::InvalidAmount(amount));
         }
 
-        self.available -= amount;
-        self.held += amount;
+        let available = checked_sub(self.available, amount)?;
+        let held = checked_add(self.held, amount)?;
+
+        self.available = available;
+        self.held = held;
 
         Ok(())
     }
@@ -143,8 +341,11 @@ impl ClientState {
             return Err(TransactionError::InsufficientFunds);
         }
 
-        self.available += amount;
-        self.held -= amount;
+        let available = checked_add(self.available, amount)?;
+        let held = checked_sub(self.held, amount)?;
+
+        self.available = available;
+        self.held = held;
 
         Ok(())
     }
@@ -160,42 +361,102 @@ impl ClientState {
             return Err(TransactionError::InsufficientFunds);
         }
 
-        self.held -= amount;
-        self.total -= amount;
+        let held = checked_sub(self.held, amount)?;
+        let total = checked_sub(self.total, amount)?;
+
+        self.held = held;
+        self.total = total;
         self.locked = true;
 
         Ok(())
     }
 
+    /// Locks the account, so no further withdrawals can take place.
+    pub fn lock(&mut self) {
+        self.locked = true;
+    }
+
+    /// Returns a copy with amounts rounded to given scale. Total is computed from rounded parts,
+    /// so rounded values always add up.
+    pub fn round(&self, scale: u32, rounding: RoundingMode) -> Self {
+        let available = rounding.round(self.available, scale);
+        let held = rounding.round(self.held, scale);
+
+        Self {
+            available,
+            held,
+            total: available + held,
+            ..*self
+        }
+    }
+
     #[inline]
     pub fn client_id(&self) -> ClientId {
         self.client_id
     }
 
-    #[cfg(test)]
-    pub(crate) fn held(&self) -> Decimal {
+    #[inline]
+    pub fn available(&self) -> Decimal {
+        self.available
+    }
+
+    #[inline]
+    pub fn held(&self) -> Decimal {
         self.held
     }
 
-    #[cfg(test)]
-    pub(crate) fn total(&self) -> Decimal {
+    #[inline]
+    pub fn total(&self) -> Decimal {
         self.total
     }
 
-    #[cfg(test)]
-    pub(crate) fn locked(&self) -> bool {
+    #[inline]
+    pub fn locked(&self) -> bool {
         self.locked
     }
 }
 
-fn serialize_with_fixed_precision<S: Serializer>(
-    value: &Decimal,
-    serializer: S,
-) -> Result<S::Ok, S::Error> {
-    serializer.serialize_str(&format!("{:.4}", value))
+// balances need to stay consistent, so all updates are computed upfront and applied only if none
+// of them overflowed
+#[inline]
+fn checked_add(value: Decimal, amount: Decimal) -> Result<Decimal, TransactionError> {
+    exact(value, amount, value.checked_add(amount))
+}
+
+#[inline]
+fn checked_sub(value: Decimal, amount: Decimal) -> Result<Decimal, TransactionError> {
+    exact(value, amount, value.checked_sub(amount))
 }
 
-fn deserialize_optional_decimal<'de, D: Deserializer<'de>>(
+// near the end of the decimal range, results silently lose fractional digits to fit the mantissa,
+// which would make amounts no longer add up, so such results are treated as overflows as well;
+// adding to zero is exact, but doesn't keep the scale of the zero operand
+#[inline]
+fn exact(
+    value: Decimal,
+    amount: Decimal,
+    result: Option<Decimal>,
+) -> Result<Decimal, TransactionError> {
+    result
+        .filter(|result| {
+            value.is_zero()
+                || amount.is_zero()
+                || result.scale() >= value.scale().max(amount.scale())
+        })
+        .ok_or(TransactionError::Overflow)
+}
+
+/// Formats given amount with exactly `scale` decimal places, truncating any excess ones, like
+/// `format!("{:.*}", scale, value)` does. That however panics for values with 28 or more integer
+/// digits, as they overflow an internal `Decimal` buffer, so the value is rescaled instead. Values
+/// too large to carry all decimal places keep as many as fit.
+pub fn format_amount(value: Decimal, scale: u32) -> String {
+    let mut value = value.round_dp_with_strategy(scale, RoundingStrategy::ToZero);
+    value.rescale(scale);
+    value.to_string()
+}
+
+pub(crate) fn deserialize_optional_decimal<'de, D: Deserializer<'de>>(
     deserializer: D,
 ) -> Result<Option<Decimal>, D::Error> {
     // serde doesn't yet support custom (de)serialization wrapped in an Option, so need to work
@@ -210,7 +471,7 @@ fn deserialize_optional_decimal<'de, D: Deserializer<'de>>(
 mod tests {
     use rust_decimal::Decimal;
 
-    use crate::model::{ClientId, ClientState, TransactionError};
+    use crate::model::{format_amount, ClientId, ClientState, TransactionError};
 
     #[test]
     fn should_deposit_funds() {
@@ -235,6 +496,46 @@ mod tests {
         assert!(state.total.is_zero());
     }
 
+    #[test]
+    fn should_not_deposit_zero_funds() {
+        let mut state = ClientState::new(ClientId::new(2));
+        assert_eq!(
+            state.deposit(Decimal::ZERO).unwrap_err(),
+            TransactionError::InvalidAmount(Decimal::ZERO)
+        );
+
+        assert!(state.available.is_zero());
+        assert!(state.total.is_zero());
+    }
+
+    #[test]
+    fn should_not_overflow_on_deposit() {
+        let mut state = ClientState::new(ClientId::new(2));
+        state.deposit(Decimal::MAX).unwrap();
+        assert_eq!(
+            state.deposit(Decimal::from(1)).unwrap_err(),
+            TransactionError::Overflow
+        );
+
+        assert_eq!(state.available, Decimal::MAX);
+        assert!(state.held.is_zero());
+        assert_eq!(state.total, Decimal::MAX);
+    }
+
+    #[test]
+    fn should_not_lose_precision_on_deposit() {
+        let mut state = ClientState::new(ClientId::new(2));
+        state.deposit(Decimal::MAX - Decimal::from(1000)).unwrap();
+        assert_eq!(
+            state.deposit(Decimal::new(15, 1)).unwrap_err(),
+            TransactionError::Overflow
+        );
+        state.deposit(Decimal::from(15)).unwrap();
+
+        assert_eq!(state.available, Decimal::MAX - Decimal::from(985));
+        assert_eq!(state.total, Decimal::MAX - Decimal::from(985));
+    }
+
     #[test]
     fn should_withdraw_funds() {
         let mut state = ClientState::new(ClientId::new(2));
@@ -289,6 +590,23 @@ mod tests {
         assert_eq!(state.total, Decimal::from(4));
     }
 
+    #[test]
+    fn should_not_overflow_on_dispute() {
+        let mut state = ClientState::new(ClientId::new(2));
+        state.deposit(Decimal::MAX).unwrap();
+        state.withdraw(Decimal::MAX).unwrap();
+        state.dispute_deposit(Decimal::MAX).unwrap();
+        state.deposit(Decimal::MAX).unwrap();
+        assert_eq!(
+            state.dispute_deposit(Decimal::MAX).unwrap_err(),
+            TransactionError::Overflow
+        );
+
+        assert!(state.available.is_zero());
+        assert_eq!(state.held, Decimal::MAX);
+        assert_eq!(state.total, Decimal::MAX);
+    }
+
     #[test]
     fn should_dispute_funds() {
         let mut state = ClientState::new(ClientId::new(2));
@@ -400,4 +718,19 @@ mod tests {
         assert_eq!(state.total, Decimal::from(4));
         assert!(!state.locked);
     }
+
+    #[test]
+    fn should_format_amounts_with_fixed_scale() {
+        assert_eq!(format_amount(Decimal::new(15, 1), 4), "1.5000");
+        assert_eq!(format_amount(Decimal::new(-212345, 5), 4), "-2.1234");
+        assert_eq!(format_amount(Decimal::from(3), 0), "3");
+        assert_eq!(
+            format_amount(Decimal::from_i128_with_scale(10_i128.pow(27), 0), 4),
+            "1000000000000000000000000000.0"
+        );
+        assert_eq!(
+            format_amount(Decimal::MAX, 4),
+            "79228162514264337593543950335"
+        );
+    }
 }
@@ -0,0 +1,203 @@
+use crate::model::{ClientState, Transaction, TransactionType};
+use crate::service::{ProcessingError, TransactionStatus};
+
+/// Hooks into the transaction lifecycle, e.g. for notifications, fraud scoring or metrics. All
+/// callbacks receive the processed transaction along with client state before and after it; they
+/// do nothing by default, so implementations only need to override the ones they care about.
+pub trait ProcessingObserver {
+    /// Called after a transaction has been applied to client state.
+    fn on_transaction_applied(
+        &mut self,
+        _transaction: &Transaction,
+        _before: &ClientState,
+        _after: &ClientState,
+    ) {
+    }
+
+    /// Called after a transaction has been rejected; client state is left intact.
+    fn on_transaction_rejected(
+        &mut self,
+        _transaction: &Transaction,
+        _error: &ProcessingError,
+        _state: &ClientState,
+    ) {
+    }
+
+    /// Called after a dispute, resolve or chargeback referring to an unknown transaction has been
+    /// ignored.
+    fn on_transaction_ignored(&mut self, _transaction: &Transaction, _state: &ClientState) {}
+
+    /// Called after a dispute has been opened, i.e. funds of the disputed transaction are held.
+    fn on_dispute_opened(
+        &mut self,
+        _transaction: &Transaction,
+        _before: &ClientState,
+        _after: &ClientState,
+    ) {
+    }
+
+    /// Called after a disputed transaction has been charged back.
+    fn on_chargeback(
+        &mut self,
+        _transaction: &Transaction,
+        _before: &ClientState,
+        _after: &ClientState,
+    ) {
+    }
+
+    /// Called after a transaction has caused the account to become locked.
+    fn on_account_locked(
+        &mut self,
+        _transaction: &Transaction,
+        _before: &ClientState,
+        _after: &ClientState,
+    ) {
+    }
+}
+
+/// Notifies all observers about the outcome of a single transaction. General callbacks come
+/// first, followed by more specific ones.
+pub(crate) fn notify_observers(
+    observers: &mut [Box<dyn ProcessingObserver>],
+    transaction: &Transaction,
+    result: &Result<TransactionStatus, ProcessingError>,
+    before: &ClientState,
+    after: &ClientState,
+) {
+    for observer in observers {
+        match result {
+            Ok(TransactionStatus::Ignored) => observer.on_transaction_ignored(transaction, after),
+            Err(error) => observer.on_transaction_rejected(transaction, error, after),
+            // rejections are returned as errors, so any other status has been applied
+            Ok(_) => {
+                observer.on_transaction_applied(transaction, before, after);
+
+                match transaction.r#type {
+                    TransactionType::Dispute => {
+                        observer.on_dispute_opened(transaction, before, after)
+                    }
+                    TransactionType::Chargeback => {
+                        observer.on_chargeback(transaction, before, after)
+                    }
+                    _ => {}
+                }
+
+                if !before.locked() && after.locked() {
+                    observer.on_account_locked(transaction, before, after);
+                }
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    use crate::importer::TransactionCsvImporter;
+    use crate::model::{ClientState, Transaction};
+    use crate::observer::ProcessingObserver;
+    use crate::service::{ProcessingError, TransactionProcessor};
+
+    #[derive(Clone, Default)]
+    struct RecordingObserver(Rc<RefCell<Vec<String>>>);
+
+    impl RecordingObserver {
+        fn record(&self, event: &str, transaction: &Transaction, state: &ClientState) {
+            self.0.borrow_mut().push(format!(
+                "{} {} {}",
+                event,
+                transaction.transaction_id,
+                state.available()
+            ));
+        }
+    }
+
+    impl ProcessingObserver for RecordingObserver {
+        fn on_transaction_applied(
+            &mut self,
+            transaction: &Transaction,
+            before: &ClientState,
+            after: &ClientState,
+        ) {
+            self.record("applied", transaction, before);
+            self.record("applied", transaction, after);
+        }
+
+        fn on_transaction_rejected(
+            &mut self,
+            transaction: &Transaction,
+            error: &ProcessingError,
+            state: &ClientState,
+        ) {
+            self.record(error.code(), transaction, state);
+        }
+
+        fn on_transaction_ignored(&mut self, transaction: &Transaction, state: &ClientState) {
+            self.record("ignored", transaction, state);
+        }
+
+        fn on_dispute_opened(
+            &mut self,
+            transaction: &Transaction,
+            _before: &ClientState,
+            after: &ClientState,
+        ) {
+            self.record("dispute", transaction, after);
+        }
+
+        fn on_chargeback(
+            &mut self,
+            transaction: &Transaction,
+            _before: &ClientState,
+            after: &ClientState,
+        ) {
+            self.record("chargeback", transaction, after);
+        }
+
+        fn on_account_locked(
+            &mut self,
+            transaction: &Transaction,
+            _before: &ClientState,
+            after: &ClientState,
+        ) {
+            self.record("locked", transaction, after);
+        }
+    }
+
+    #[test]
+    fn should_notify_observers_about_lifecycle_events() {
+        let csv = "type,client,tx,amount
+deposit,1,1,2
+withdrawal,1,2,5
+dispute,1,1,
+resolve,1,3,
+chargeback,1,1,
+";
+
+        let observer = RecordingObserver::default();
+        let importer = TransactionCsvImporter::from_reader(csv.as_bytes());
+        TransactionProcessor::new(importer, csv::Writer::from_writer(vec![]))
+            .with_observer(Box::new(observer.clone()))
+            .process_transactions()
+            .unwrap();
+
+        assert_eq!(
+            *observer.0.borrow(),
+            vec![
+                "applied 1 0",
+                "applied 1 2",
+                "E_INSUFFICIENT_FUNDS 2 2",
+                "applied 1 2",
+                "applied 1 0",
+                "dispute 1 0",
+                "ignored 3 0",
+                "applied 1 0",
+                "applied 1 0",
+                "chargeback 1 0",
+                "locked 1 0",
+            ]
+        );
+    }
+}
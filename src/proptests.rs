@@ -0,0 +1,270 @@
+//! Property-based tests, checking [`TransactionProcessor`] against a simple reference model for
+//! arbitrary transaction streams.
+
+use fxhash::FxHashMap;
+use proptest::prelude::*;
+use proptest::sample::Index;
+use rust_decimal::Decimal;
+use std::cell::RefCell;
+use std::rc::Rc;
+
+use crate::exporter::{ClientStateExporter, TransactionResultExporter};
+use crate::importer::TransactionImporter;
+use crate::model::{ClientId, ClientState, Transaction, TransactionId, TransactionType};
+use crate::service::{TransactionProcessor, TransactionResult, TransactionStatus};
+
+const CLIENTS: u8 = 4;
+
+/// Amounts with up to 4 decimal places, small enough to never overflow.
+fn valid_amount() -> impl Strategy<Value = Decimal> {
+    (1i64..10_000_000).prop_map(|value| Decimal::new(value, 4))
+}
+
+/// Amounts including zero, negative and excessively precise ones.
+fn any_amount() -> impl Strategy<Value = Decimal> {
+    (-10_000i64..10_000_000, 0u32..=6).prop_map(|(value, scale)| Decimal::new(value, scale))
+}
+
+fn client_id() -> impl Strategy<Value = ClientId> {
+    (0..CLIENTS).prop_map(|id| ClientId::new(id.into()))
+}
+
+fn transaction_type() -> impl Strategy<Value = TransactionType> {
+    prop_oneof![
+        Just(TransactionType::Deposit),
+        Just(TransactionType::Withdrawal),
+        Just(TransactionType::Dispute),
+        Just(TransactionType::Resolve),
+        Just(TransactionType::Chargeback),
+    ]
+}
+
+// single step of a valid stream; references pick one of previous deposits or withdrawals
+#[derive(Debug, Clone)]
+enum Step {
+    Deposit(ClientId, Decimal),
+    Withdrawal(ClientId, Decimal),
+    Reference(TransactionType, Index),
+}
+
+fn step() -> impl Strategy<Value = Step> {
+    prop_oneof![
+        3 => (client_id(), valid_amount())
+            .prop_map(|(client, amount)| Step::Deposit(client, amount)),
+        2 => (client_id(), valid_amount())
+            .prop_map(|(client, amount)| Step::Withdrawal(client, amount)),
+        1 => any::<Index>().prop_map(|index| Step::Reference(TransactionType::Dispute, index)),
+        1 => any::<Index>().prop_map(|index| Step::Reference(TransactionType::Resolve, index)),
+        1 => any::<Index>().prop_map(|index| Step::Reference(TransactionType::Chargeback, index)),
+    ]
+}
+
+/// Well-formed streams: unique transaction IDs, amounts where needed, and disputes, resolves and
+/// chargebacks referring to previous transactions of the same client.
+fn valid_transactions() -> impl Strategy<Value = Vec<Transaction>> {
+    prop::collection::vec(step(), 0..200).prop_map(|steps| {
+        let mut transactions: Vec<Transaction> = Vec::with_capacity(steps.len());
+        let mut amounts = Vec::new();
+
+        for step in steps {
+            let transaction_id = TransactionId::new(transactions.len() as u32);
+            let transaction = match step {
+                Step::Deposit(client_id, amount) | Step::Withdrawal(client_id, amount) => {
+                    amounts.push((client_id, transaction_id));
+                    let r#type = match step {
+                        Step::Deposit(..) => TransactionType::Deposit,
+                        _ => TransactionType::Withdrawal,
+                    };
+                    Transaction {
+                        r#type,
+                        client_id,
+                        transaction_id,
+                        amount: Some(amount),
+                    }
+                }
+                Step::Reference(r#type, index) if !amounts.is_empty() => {
+                    let (client_id, transaction_id) = *index.get(&amounts);
+                    Transaction {
+                        r#type,
+                        client_id,
+                        transaction_id,
+                        amount: None,
+                    }
+                }
+                Step::Reference(..) => continue,
+            };
+
+            transactions.push(transaction);
+        }
+
+        transactions
+    })
+}
+
+/// Arbitrary streams: colliding IDs, missing, zero or negative amounts, references to unknown
+/// transactions or ones of other clients, and repeated disputes, resolves and chargebacks.
+fn adversarial_transactions() -> impl Strategy<Value = Vec<Transaction>> {
+    let transaction = (
+        transaction_type(),
+        client_id(),
+        0u32..16,
+        prop::option::weighted(0.8, any_amount()),
+    )
+        .prop_map(|(r#type, client_id, transaction_id, amount)| Transaction {
+            r#type,
+            client_id,
+            transaction_id: TransactionId::new(transaction_id),
+            amount,
+        });
+
+    prop::collection::vec(transaction, 0..200)
+}
+
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+enum ModelState {
+    Applied,
+    Disputed,
+    ChargedBack,
+}
+
+#[derive(Debug, Default)]
+struct ModelClient {
+    available: Decimal,
+    held: Decimal,
+    locked: bool,
+    transactions: FxHashMap<TransactionId, (TransactionType, Decimal, ModelState)>,
+}
+
+/// Reference model of the ledger, written for clarity rather than speed.
+#[derive(Debug, Default)]
+struct Model {
+    clients: FxHashMap<ClientId, ModelClient>,
+}
+
+impl Model {
+    fn apply(&mut self, transaction: &Transaction) -> TransactionStatus {
+        use TransactionStatus::*;
+
+        let client = self.clients.entry(transaction.client_id).or_default();
+        let id = transaction.transaction_id;
+
+        match transaction.r#type {
+            TransactionType::Deposit | TransactionType::Withdrawal => {
+                let deposit = transaction.r#type == TransactionType::Deposit;
+                let Some(amount) = transaction.amount.filter(|amount| *amount > Decimal::ZERO)
+                else {
+                    return Rejected;
+                };
+                if !deposit && (client.locked || client.available < amount) {
+                    return Rejected;
+                }
+                // replacing a disputed transaction would orphan its held funds
+                if let Some((_, _, ModelState::Disputed)) = client.transactions.get(&id) {
+                    return Rejected;
+                }
+
+                client.available += if deposit { amount } else { -amount };
+                client
+                    .transactions
+                    .insert(id, (transaction.r#type, amount, ModelState::Applied));
+                Applied
+            }
+            r#type => {
+                let Some((original_type, amount, state)) = client.transactions.get_mut(&id) else {
+                    return Ignored;
+                };
+
+                match (r#type, *original_type, *state) {
+                    (TransactionType::Dispute, TransactionType::Deposit, ModelState::Applied) => {
+                        client.available -= *amount;
+                        client.held += *amount;
+                        *state = ModelState::Disputed;
+                    }
+                    (TransactionType::Resolve, _, ModelState::Disputed) => {
+                        client.available += *amount;
+                        client.held -= *amount;
+                        *state = ModelState::Applied;
+                    }
+                    (TransactionType::Chargeback, _, ModelState::Disputed) => {
+                        client.held -= *amount;
+                        client.locked = true;
+                        *state = ModelState::ChargedBack;
+                    }
+                    _ => return Rejected,
+                }
+
+                Applied
+            }
+        }
+    }
+}
+
+struct VecImporter(Vec<Transaction>);
+
+impl TransactionImporter for VecImporter {
+    fn deserialize(&mut self) -> Box<dyn Iterator<Item = anyhow::Result<Transaction>> + '_> {
+        Box::new(self.0.iter().copied().map(Ok))
+    }
+}
+
+#[derive(Default)]
+struct CachingExporter {
+    client_states: Vec<ClientState>,
+}
+
+impl ClientStateExporter for &mut CachingExporter {
+    fn serialize(&mut self, client_state: &ClientState) -> anyhow::Result<()> {
+        self.client_states.push(*client_state);
+        Ok(())
+    }
+}
+
+#[derive(Clone, Default)]
+struct StatusExporter(Rc<RefCell<Vec<TransactionStatus>>>);
+
+impl TransactionResultExporter for StatusExporter {
+    fn serialize(&mut self, result: &TransactionResult) -> anyhow::Result<()> {
+        self.0.borrow_mut().push(result.status);
+        Ok(())
+    }
+}
+
+// processes given transactions with invariant checks, comparing outcomes against the model
+fn check_against_model(transactions: Vec<Transaction>) -> Result<(), TestCaseError> {
+    let mut model = Model::default();
+    let expected_statuses: Vec<_> = transactions.iter().map(|tx| model.apply(tx)).collect();
+
+    let statuses = StatusExporter::default();
+    let mut exporter = CachingExporter::default();
+    let result = TransactionProcessor::new(VecImporter(transactions), &mut exporter)
+        .with_result_exporter(Box::new(statuses.clone()))
+        .with_invariant_checks()
+        .process_transactions();
+    prop_assert!(result.is_ok(), "processing failed: {}", result.unwrap_err());
+
+    prop_assert_eq!(&*statuses.0.borrow(), &expected_statuses);
+    prop_assert_eq!(exporter.client_states.len(), model.clients.len());
+
+    for state in &exporter.client_states {
+        let client = &model.clients[&state.client_id()];
+        prop_assert_eq!(state.available(), client.available);
+        prop_assert_eq!(state.held(), client.held);
+        prop_assert_eq!(state.total(), client.available + client.held);
+        prop_assert_eq!(state.locked(), client.locked);
+        prop_assert!(!state.held().is_sign_negative());
+    }
+
+    Ok(())
+}
+
+proptest! {
+    #[test]
+    fn should_match_model_for_valid_streams(transactions in valid_transactions()) {
+        check_against_model(transactions)?;
+    }
+
+    #[test]
+    fn should_match_model_for_adversarial_streams(transactions in adversarial_transactions()) {
+        check_against_model(transactions)?;
+    }
+}
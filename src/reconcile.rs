@@ -0,0 +1,443 @@
+use anyhow::{bail, Context, Result};
+use csv::{Writer, WriterBuilder};
+use fxhash::{FxHashMap, FxHashSet};
+use rust_decimal::Decimal;
+use serde::{Deserialize, Serialize};
+use std::io::Write;
+
+use crate::exporter::{ClientStateExporter, ExporterConfig};
+use crate::model::{format_amount, ClientId, ClientState, RoundingMode};
+
+/// Maximum absolute differences between expected and actual amounts, which are still considered
+/// a match. Defaults to exact matches.
+#[derive(Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Tolerance {
+    pub available: Decimal,
+    pub held: Decimal,
+    pub total: Decimal,
+}
+
+impl Tolerance {
+    /// Creates a tolerance with the same value for all amounts.
+    pub fn uniform(value: Decimal) -> Self {
+        Self {
+            available: value,
+            held: value,
+            total: value,
+        }
+    }
+}
+
+/// Client state amount, which can differ between expected and actual states.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum AmountField {
+    Available,
+    Held,
+    Total,
+}
+
+impl AmountField {
+    pub fn as_str(self) -> &'static str {
+        match self {
+            AmountField::Available => "available",
+            AmountField::Held => "held",
+            AmountField::Total => "total",
+        }
+    }
+}
+
+/// Single difference between expected and actual client states.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum Discrepancy {
+    /// Expected client is missing from actual states.
+    MissingClient(ClientId),
+    /// Actual client wasn't expected.
+    UnexpectedClient(ClientId),
+    /// Amounts differ by more than the tolerance.
+    Amount {
+        client_id: ClientId,
+        field: AmountField,
+        expected: Decimal,
+        actual: Decimal,
+    },
+    /// Locked flags differ.
+    Locked {
+        client_id: ClientId,
+        expected: bool,
+        actual: bool,
+    },
+}
+
+impl Discrepancy {
+    pub fn client_id(&self) -> ClientId {
+        match *self {
+            Discrepancy::MissingClient(client_id)
+            | Discrepancy::UnexpectedClient(client_id)
+            | Discrepancy::Amount { client_id, .. }
+            | Discrepancy::Locked { client_id, .. } => client_id,
+        }
+    }
+}
+
+/// Compares actual client states against expected ones. Discrepancies are reported in order of
+/// expected states, followed by unexpected clients in order of actual states.
+pub fn reconcile(
+    expected: &[ClientState],
+    actual: &[ClientState],
+    tolerance: &Tolerance,
+) -> Vec<Discrepancy> {
+    let actual_by_id: FxHashMap<_, _> = actual
+        .iter()
+        .map(|state| (state.client_id(), state))
+        .collect();
+
+    let mut discrepancies = Vec::new();
+    for expected in expected {
+        let client_id = expected.client_id();
+        let Some(actual) = actual_by_id.get(&client_id) else {
+            discrepancies.push(Discrepancy::MissingClient(client_id));
+            continue;
+        };
+
+        for (field, expected, actual, tolerance) in [
+            (
+                AmountField::Available,
+                expected.available(),
+                actual.available(),
+                tolerance.available,
+            ),
+            (
+                AmountField::Held,
+                expected.held(),
+                actual.held(),
+                tolerance.held,
+            ),
+            (
+                AmountField::Total,
+                expected.total(),
+                actual.total(),
+                tolerance.total,
+            ),
+        ] {
+            // differences too large to be represented are beyond any tolerance
+            let beyond_tolerance = actual
+                .checked_sub(expected)
+                .is_none_or(|difference| difference.abs() > tolerance);
+            if beyond_tolerance {
+                discrepancies.push(Discrepancy::Amount {
+                    client_id,
+                    field,
+                    expected,
+                    actual,
+                });
+            }
+        }
+
+        if expected.locked() != actual.locked() {
+            discrepancies.push(Discrepancy::Locked {
+                client_id,
+                expected: expected.locked(),
+                actual: actual.locked(),
+            });
+        }
+    }
+
+    let expected_ids: FxHashSet<_> = expected.iter().map(ClientState::client_id).collect();
+    discrepancies.extend(
+        actual
+            .iter()
+            .filter(|state| !expected_ids.contains(&state.client_id()))
+            .map(|state| Discrepancy::UnexpectedClient(state.client_id())),
+    );
+
+    discrepancies
+}
+
+/// Discrepancy as seen in the report, with amounts formatted like exported ones. Amount
+/// differences too large to be represented are left empty.
+#[derive(Serialize, Debug, Clone)]
+struct DiscrepancyRecord {
+    client: ClientId,
+    mismatch: &'static str,
+    expected: Option<String>,
+    actual: Option<String>,
+    difference: Option<String>,
+}
+
+impl DiscrepancyRecord {
+    fn new(discrepancy: Discrepancy, scale: u32) -> Self {
+        let (mismatch, expected, actual, difference) = match discrepancy {
+            Discrepancy::MissingClient(_) => ("missing", None, None, None),
+            Discrepancy::UnexpectedClient(_) => ("unexpected", None, None, None),
+            Discrepancy::Amount {
+                field,
+                expected,
+                actual,
+                ..
+            } => (
+                field.as_str(),
+                Some(format_amount(expected, scale)),
+                Some(format_amount(actual, scale)),
+                actual
+                    .checked_sub(expected)
+                    .map(|difference| format_amount(difference, scale)),
+            ),
+            Discrepancy::Locked {
+                expected, actual, ..
+            } => (
+                "locked",
+                Some(expected.to_string()),
+                Some(actual.to_string()),
+                None,
+            ),
+        };
+
+        Self {
+            client: discrepancy.client_id(),
+            mismatch,
+            expected,
+            actual,
+            difference,
+        }
+    }
+}
+
+/// Client state exporter, which reconciles exported states against expected ones and writes a
+/// CSV report of discrepancies once all states are exported. Actual amounts are rounded the same
+/// way as on CSV export, so previously exported states can be compared exactly.
+///
+/// Finishing fails if any discrepancies were found, after the report is written.
+pub struct ReconcilingExporter<W: Write> {
+    expected: Vec<ClientState>,
+    actual: Vec<ClientState>,
+    tolerance: Tolerance,
+    amount_scale: u32,
+    rounding: RoundingMode,
+    // taken when finishing, to finish the underlying writer
+    report: Option<Writer<W>>,
+    finish_report: fn(&mut W) -> std::io::Result<()>,
+}
+
+impl<W: Write> ReconcilingExporter<W> {
+    /// Creates a new exporter, writing the report to given `Writer` in the CSV dialect of given
+    /// configuration.
+    pub fn new(expected: Vec<ClientState>, report: W, config: ExporterConfig) -> Self {
+        let report = WriterBuilder::new()
+            .delimiter(config.delimiter)
+            .quote(config.quote)
+            .from_writer(report);
+
+        Self {
+            expected,
+            actual: Vec::new(),
+            tolerance: Default::default(),
+            amount_scale: config.amount_scale,
+            rounding: config.rounding,
+            report: Some(report),
+            finish_report: |_| Ok(()),
+        }
+    }
+
+    /// Sets tolerance of amount differences.
+    pub fn with_tolerance(mut self, tolerance: Tolerance) -> Self {
+        self.tolerance = tolerance;
+        self
+    }
+
+    /// Finishes the report writer with given function after flushing it, e.g. to complete a
+    /// compressed stream with [`Encoder::finish`](crate::compression::Encoder::finish).
+    pub fn with_finish(mut self, finish_report: fn(&mut W) -> std::io::Result<()>) -> Self {
+        self.finish_report = finish_report;
+        self
+    }
+}
+
+impl<W: Write> ClientStateExporter for ReconcilingExporter<W> {
+    fn serialize(&mut self, client_state: &ClientState) -> Result<()> {
+        self.actual
+            .push(client_state.round(self.amount_scale, self.rounding));
+        Ok(())
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        let mut report = self
+            .report
+            .take()
+            .context("Reconciliation already finished")?;
+        let discrepancies = reconcile(&self.expected, &self.actual, &self.tolerance);
+        for discrepancy in &discrepancies {
+            report
+                .serialize(DiscrepancyRecord::new(*discrepancy, self.amount_scale))
+                .with_context(|| {
+                    format!(
+                        "Error serializing discrepancy for client: {}",
+                        discrepancy.client_id()
+                    )
+                })?;
+        }
+        report
+            .flush()
+            .context("Error flushing reconciliation report")?;
+        // already flushed, so taking the writer doesn't fail
+        let mut report = report
+            .into_inner()
+            .ok()
+            .context("Error flushing reconciliation report")?;
+        (self.finish_report)(&mut report).context("Error finishing reconciliation report")?;
+
+        if !discrepancies.is_empty() {
+            bail!(
+                "Reconciliation failed: {} discrepancies found",
+                discrepancies.len()
+            );
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+
+    use crate::exporter::ClientStateExporter;
+    use crate::importer::TransactionCsvImporter;
+    use crate::model::{ClientId, ClientIdRepr, ClientState};
+    use crate::reconcile::{reconcile, AmountField, Discrepancy, ReconcilingExporter, Tolerance};
+    use crate::service::{ProcessingError, TransactionProcessor};
+
+    fn state(client_id: ClientIdRepr, available: i64, held: i64, locked: bool) -> ClientState {
+        let available = Decimal::new(available, 4);
+        let held = Decimal::new(held, 4);
+        ClientState::from_parts(
+            ClientId::new(client_id),
+            available,
+            held,
+            available + held,
+            locked,
+        )
+    }
+
+    #[test]
+    fn should_report_differences_beyond_tolerance() {
+        let expected = [
+            state(1, 10000, 0, false),
+            state(2, 5000, 2000, false),
+            state(3, 0, 0, false),
+        ];
+        let actual = [
+            state(4, 0, 0, false),
+            state(2, 5001, 2100, true),
+            state(1, 10000, 0, false),
+        ];
+
+        let discrepancies = reconcile(&expected, &actual, &Tolerance::uniform(Decimal::new(1, 3)));
+
+        let client_id = ClientId::new(2);
+        assert_eq!(
+            discrepancies,
+            vec![
+                Discrepancy::Amount {
+                    client_id,
+                    field: AmountField::Held,
+                    expected: Decimal::new(2000, 4),
+                    actual: Decimal::new(2100, 4),
+                },
+                Discrepancy::Amount {
+                    client_id,
+                    field: AmountField::Total,
+                    expected: Decimal::new(7000, 4),
+                    actual: Decimal::new(7101, 4),
+                },
+                Discrepancy::Locked {
+                    client_id,
+                    expected: false,
+                    actual: true,
+                },
+                Discrepancy::MissingClient(ClientId::new(3)),
+                Discrepancy::UnexpectedClient(ClientId::new(4)),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_write_report_and_fail_on_mismatch() {
+        let csv = "type,client,tx,amount
+deposit,1,1,1
+deposit,2,2,2
+";
+
+        let mut report = vec![];
+        let exporter = ReconcilingExporter::new(
+            vec![state(1, 10000, 0, false), state(2, 10000, 0, false)],
+            &mut report,
+            Default::default(),
+        );
+
+        let error = TransactionProcessor::new(
+            TransactionCsvImporter::from_reader(csv.as_bytes()),
+            exporter,
+        )
+        .process_transactions()
+        .unwrap_err();
+
+        let ProcessingError::ExportError(error) = error else {
+            panic!("Unexpected error: {}", error);
+        };
+        assert_eq!(
+            error.to_string(),
+            "Reconciliation failed: 2 discrepancies found"
+        );
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,mismatch,expected,actual,difference
+2,available,1.0000,2.0000,1.0000
+2,total,1.0000,2.0000,1.0000
+"
+        );
+    }
+
+    #[test]
+    fn should_pass_on_matching_states() {
+        let mut report = vec![];
+        let mut exporter = ReconcilingExporter::new(
+            vec![state(1, 10000, 0, true)],
+            &mut report,
+            Default::default(),
+        )
+        .with_tolerance(Tolerance::uniform(Decimal::ONE));
+
+        exporter.serialize(&state(1, 10100, 0, true)).unwrap();
+        exporter.finish().unwrap();
+        drop(exporter);
+
+        assert!(report.is_empty());
+    }
+
+    #[test]
+    fn should_report_differences_of_large_amounts() {
+        let large_state = |amount: Decimal| {
+            ClientState::from_parts(ClientId::new(1), amount, Decimal::ZERO, amount, false)
+        };
+
+        let mut report = vec![];
+        let mut exporter = ReconcilingExporter::new(
+            vec![large_state(Decimal::MIN)],
+            &mut report,
+            Default::default(),
+        );
+
+        exporter.serialize(&large_state(Decimal::MAX)).unwrap();
+        exporter.finish().unwrap_err();
+        drop(exporter);
+
+        assert_eq!(
+            String::from_utf8(report).unwrap(),
+            "client,mismatch,expected,actual,difference
+1,available,-79228162514264337593543950335,79228162514264337593543950335,
+1,total,-79228162514264337593543950335,79228162514264337593543950335,
+"
+        );
+    }
+}
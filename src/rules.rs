@@ -0,0 +1,477 @@
+use anyhow::{Context, Result};
+use fxhash::{FxHashMap, FxHashSet};
+use rust_decimal::Decimal;
+use serde::Deserialize;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::fraud::FraudConfig;
+use crate::model::{ClientId, ClientState, Transaction, TransactionId, TransactionType};
+use crate::service::ProcessingError;
+
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+
+/// Validation rule evaluated before a transaction is applied. Rules see the transaction along with
+/// current state of its client, and can reject the transaction with a custom reason, which leaves
+/// client state intact. Stateful rules can track applied transactions via [`record`].
+///
+/// [`record`]: TransactionRule::record
+pub trait TransactionRule: Send {
+    /// Returns the name of this rule, as reported in rejections.
+    fn name(&self) -> &str;
+
+    /// Checks given transaction against current client state, returning the reason for rejecting
+    /// it, if any.
+    fn check(&self, transaction: &Transaction, state: &ClientState) -> Result<(), String>;
+
+    /// Called after a transaction which passed all rules has been applied.
+    fn record(&mut self, _transaction: &Transaction) {}
+}
+
+/// Ordered list of rules, all of which must pass for a transaction to be applied.
+#[derive(Default)]
+pub struct RuleSet {
+    rules: Vec<Box<dyn TransactionRule>>,
+}
+
+impl RuleSet {
+    /// Appends given rule. Rules are evaluated in insertion order, and evaluation stops at the
+    /// first rejection.
+    pub fn push(&mut self, rule: Box<dyn TransactionRule>) {
+        self.rules.push(rule);
+    }
+
+    /// Returns whether there are any rules to evaluate.
+    pub fn is_empty(&self) -> bool {
+        self.rules.is_empty()
+    }
+
+    /// Checks given transaction against all rules.
+    pub fn check(
+        &self,
+        transaction: &Transaction,
+        state: &ClientState,
+    ) -> Result<(), ProcessingError> {
+        for rule in &self.rules {
+            rule.check(transaction, state)
+                .map_err(|reason| ProcessingError::RuleViolation {
+                    transaction_id: transaction.transaction_id,
+                    rule: rule.name().to_owned(),
+                    reason,
+                })?;
+        }
+
+        Ok(())
+    }
+
+    /// Lets all rules record given applied transaction.
+    pub fn record(&mut self, transaction: &Transaction) {
+        for rule in &mut self.rules {
+            rule.record(transaction);
+        }
+    }
+}
+
+impl From<Vec<Box<dyn TransactionRule>>> for RuleSet {
+    fn from(rules: Vec<Box<dyn TransactionRule>>) -> Self {
+        Self { rules }
+    }
+}
+
+/// Rejects deposits and withdrawals above given amount.
+pub struct MaxAmount(pub Decimal);
+
+impl TransactionRule for MaxAmount {
+    fn name(&self) -> &str {
+        "max_amount"
+    }
+
+    fn check(&self, transaction: &Transaction, _state: &ClientState) -> Result<(), String> {
+        match transaction.amount {
+            Some(amount) if amount > self.0 => {
+                Err(format!("Amount {} exceeds maximum of {}", amount, self.0))
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Rejects all transactions of given clients.
+pub struct BlockedClients(pub FxHashSet<ClientId>);
+
+impl TransactionRule for BlockedClients {
+    fn name(&self) -> &str {
+        "blocked_clients"
+    }
+
+    fn check(&self, transaction: &Transaction, _state: &ClientState) -> Result<(), String> {
+        if self.0.contains(&transaction.client_id) {
+            Err(format!("Client {} is blocked", transaction.client_id))
+        } else {
+            Ok(())
+        }
+    }
+}
+
+/// Source of the current time, as a number of seconds since the Unix epoch (UTC).
+pub trait Clock: Send {
+    fn now(&self) -> u64;
+}
+
+/// Wall clock of the system.
+pub struct SystemClock;
+
+impl Clock for SystemClock {
+    fn now(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+/// Limits the total amount a client can withdraw per day. Transactions carry no timestamps, so
+/// days are measured by given clock at the time of processing.
+pub struct DailyWithdrawalLimit {
+    limit: Decimal,
+    clock: Box<dyn Clock>,
+    // day and total amount of applied withdrawals per client
+    withdrawn: FxHashMap<ClientId, (u64, Decimal)>,
+}
+
+impl DailyWithdrawalLimit {
+    /// Creates a new rule with given limit, using the system clock.
+    pub fn new(limit: Decimal) -> Self {
+        Self {
+            limit,
+            clock: Box::new(SystemClock),
+            withdrawn: Default::default(),
+        }
+    }
+
+    /// Uses given clock to determine the current day.
+    pub fn with_clock(mut self, clock: Box<dyn Clock>) -> Self {
+        self.clock = clock;
+        self
+    }
+
+    fn today(&self) -> u64 {
+        self.clock.now() / SECONDS_PER_DAY
+    }
+
+    fn withdrawn_today(&self, client_id: ClientId, today: u64) -> Decimal {
+        match self.withdrawn.get(&client_id) {
+            Some((day, withdrawn)) if *day == today => *withdrawn,
+            _ => Decimal::ZERO,
+        }
+    }
+}
+
+impl TransactionRule for DailyWithdrawalLimit {
+    fn name(&self) -> &str {
+        "daily_withdrawal_limit"
+    }
+
+    fn check(&self, transaction: &Transaction, _state: &ClientState) -> Result<(), String> {
+        let amount = match (transaction.r#type, transaction.amount) {
+            (TransactionType::Withdrawal, Some(amount)) => amount,
+            _ => return Ok(()),
+        };
+
+        let withdrawn = self.withdrawn_today(transaction.client_id, self.today());
+        // an overflowing sum exceeds any limit
+        let exceeded = match withdrawn.checked_add(amount) {
+            Some(total) => total > self.limit,
+            None => true,
+        };
+        if exceeded {
+            Err(format!(
+                "Withdrawal of {} exceeds daily limit of {} ({} already withdrawn)",
+                amount, self.limit, withdrawn
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn record(&mut self, transaction: &Transaction) {
+        if let (TransactionType::Withdrawal, Some(amount)) =
+            (transaction.r#type, transaction.amount)
+        {
+            let today = self.today();
+            let withdrawn = self
+                .withdrawn_today(transaction.client_id, today)
+                .saturating_add(amount);
+            self.withdrawn
+                .insert(transaction.client_id, (today, withdrawn));
+        }
+    }
+}
+
+/// Rejects deposits and withdrawals reusing the ID of an already applied transaction. Rejected
+/// transactions are not recorded, so they can be retried with the same ID.
+#[derive(Default)]
+pub struct DuplicateTransactions {
+    seen: FxHashSet<TransactionId>,
+}
+
+impl TransactionRule for DuplicateTransactions {
+    fn name(&self) -> &str {
+        "duplicate_transactions"
+    }
+
+    fn check(&self, transaction: &Transaction, _state: &ClientState) -> Result<(), String> {
+        if is_new_funds_movement(transaction) && self.seen.contains(&transaction.transaction_id) {
+            Err(format!(
+                "Transaction {} has already been applied",
+                transaction.transaction_id
+            ))
+        } else {
+            Ok(())
+        }
+    }
+
+    fn record(&mut self, transaction: &Transaction) {
+        if is_new_funds_movement(transaction) {
+            self.seen.insert(transaction.transaction_id);
+        }
+    }
+}
+
+#[inline]
+fn is_new_funds_movement(transaction: &Transaction) -> bool {
+    // disputes, resolves and chargebacks reuse IDs of the transactions they refer to
+    matches!(
+        transaction.r#type,
+        TransactionType::Deposit | TransactionType::Withdrawal
+    )
+}
+
+/// Configuration of built-in rules, loadable from a TOML rules file. All rules are disabled by
+/// default.
+#[derive(Deserialize, Debug, Default, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct RulesConfig {
+    pub max_amount: Option<Decimal>,
+    pub blocked_clients: Vec<ClientId>,
+    pub daily_withdrawal_limit: Option<Decimal>,
+    pub reject_duplicates: bool,
+    pub fraud: FraudConfig,
+}
+
+impl RulesConfig {
+    /// Loads rules configuration from given TOML file.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let data = fs::read_to_string(path)
+            .with_context(|| format!("Error reading rules file: {}", path.display()))?;
+
+        toml::from_str(&data)
+            .with_context(|| format!("Error parsing rules file: {}", path.display()))
+    }
+
+    /// Creates enabled rules. Cheap, stateless rules are evaluated first.
+    pub fn build(&self) -> RuleSet {
+        let mut rules = RuleSet::default();
+
+        if !self.blocked_clients.is_empty() {
+            rules.push(Box::new(BlockedClients(
+                self.blocked_clients.iter().copied().collect(),
+            )));
+        }
+
+        if let Some(max_amount) = self.max_amount {
+            rules.push(Box::new(MaxAmount(max_amount)));
+        }
+
+        if self.reject_duplicates {
+            rules.push(Box::new(DuplicateTransactions::default()));
+        }
+
+        if let Some(limit) = self.daily_withdrawal_limit {
+            rules.push(Box::new(DailyWithdrawalLimit::new(limit)));
+        }
+
+        rules
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use std::sync::Arc;
+
+    use rust_decimal::Decimal;
+
+    use crate::importer::{TransactionCsvImporter, TransactionImporter};
+    use crate::model::{ClientId, ClientState, Transaction, TransactionId, TransactionType};
+    use crate::rules::{
+        Clock, DailyWithdrawalLimit, RuleSet, RulesConfig, TransactionRule, SECONDS_PER_DAY,
+    };
+    use crate::service::{ProcessingContext, ProcessingError};
+
+    struct TestClock(Arc<AtomicU64>);
+
+    impl Clock for TestClock {
+        fn now(&self) -> u64 {
+            self.0.load(Ordering::Relaxed)
+        }
+    }
+
+    fn apply_csv(
+        context: &mut ProcessingContext,
+        rules: &mut RuleSet,
+        csv: &str,
+    ) -> Vec<Option<String>> {
+        let mut importer = TransactionCsvImporter::from_reader(csv.as_bytes());
+        importer
+            .deserialize()
+            .map(|transaction| {
+                context
+                    .apply_with_rules(&transaction.unwrap(), &Default::default(), rules)
+                    .err()
+                    .map(|error| match error {
+                        ProcessingError::RuleViolation { rule, .. } => rule,
+                        error => error.code().to_owned(),
+                    })
+            })
+            .collect()
+    }
+
+    #[test]
+    fn should_parse_rules_config() {
+        let config: RulesConfig = toml::from_str(
+            r#"
+max_amount = "1000"
+blocked_clients = [3, 7]
+daily_withdrawal_limit = 500
+reject_duplicates = true
+"#,
+        )
+        .unwrap();
+
+        assert_eq!(config.max_amount, Some(1000.into()));
+        assert_eq!(
+            config.blocked_clients,
+            vec![ClientId::new(3), ClientId::new(7)]
+        );
+        assert_eq!(config.daily_withdrawal_limit, Some(500.into()));
+        assert!(config.reject_duplicates);
+    }
+
+    #[test]
+    fn should_reject_transactions_violating_built_in_rules() {
+        let mut rules = RulesConfig {
+            max_amount: Some(100.into()),
+            blocked_clients: vec![ClientId::new(2)],
+            daily_withdrawal_limit: None,
+            reject_duplicates: true,
+            ..Default::default()
+        }
+        .build();
+        let mut context = ProcessingContext::default();
+
+        let csv = "type,client,tx,amount
+deposit,1,1,50
+deposit,1,2,150
+deposit,2,3,10
+deposit,1,1,10
+withdrawal,1,4,100
+withdrawal,1,4,10
+dispute,1,1,
+";
+
+        assert_eq!(
+            apply_csv(&mut context, &mut rules, csv),
+            vec![
+                None,
+                Some("max_amount".into()),
+                Some("blocked_clients".into()),
+                Some("duplicate_transactions".into()),
+                Some("E_INSUFFICIENT_FUNDS".into()),
+                None,
+                None,
+            ]
+        );
+
+        // rejected clients still show up, so their results can be reported
+        let state = context.client_state(ClientId::new(2)).unwrap();
+        assert_eq!(state.total(), 0.into());
+    }
+
+    #[test]
+    fn should_limit_withdrawals_per_day() {
+        let now = Arc::new(AtomicU64::new(SECONDS_PER_DAY));
+        let mut rules = RuleSet::default();
+        rules.push(Box::new(
+            DailyWithdrawalLimit::new(100.into()).with_clock(Box::new(TestClock(now.clone()))),
+        ));
+        let mut context = ProcessingContext::default();
+
+        let csv = "type,client,tx,amount
+deposit,1,1,1000
+deposit,2,2,1000
+withdrawal,1,3,60
+withdrawal,1,4,60
+withdrawal,2,5,100
+withdrawal,1,6,40
+";
+        assert_eq!(
+            apply_csv(&mut context, &mut rules, csv),
+            vec![
+                None,
+                None,
+                None,
+                Some("daily_withdrawal_limit".into()),
+                None,
+                None
+            ]
+        );
+
+        now.store(2 * SECONDS_PER_DAY, Ordering::Relaxed);
+        let withdrawal = Transaction {
+            r#type: TransactionType::Withdrawal,
+            client_id: ClientId::new(1),
+            transaction_id: TransactionId::new(7),
+            amount: Some(100.into()),
+        };
+        assert!(rules
+            .check(&withdrawal, &ClientState::new(ClientId::new(1)))
+            .is_ok());
+    }
+
+    #[test]
+    fn should_reject_withdrawals_overflowing_daily_limit() {
+        let mut rules = RuleSet::default();
+        rules.push(Box::new(DailyWithdrawalLimit::new(1000.into())));
+        let mut context = ProcessingContext::default();
+
+        let csv = format!(
+            "type,client,tx,amount
+deposit,1,1,100
+withdrawal,1,2,10
+withdrawal,1,3,{}
+",
+            Decimal::MAX
+        );
+        assert_eq!(
+            apply_csv(&mut context, &mut rules, &csv),
+            vec![None, None, Some("daily_withdrawal_limit".into())]
+        );
+
+        // recorded withdrawals saturate instead of overflowing
+        let mut limit = DailyWithdrawalLimit::new(Decimal::MAX);
+        let withdrawal = Transaction {
+            r#type: TransactionType::Withdrawal,
+            client_id: ClientId::new(1),
+            transaction_id: TransactionId::new(4),
+            amount: Some(Decimal::MAX),
+        };
+        limit.record(&withdrawal);
+        limit.record(&withdrawal);
+        assert!(limit
+            .check(&withdrawal, &ClientState::new(ClientId::new(1)))
+            .is_err());
+    }
+}
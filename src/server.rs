@@ -0,0 +1,477 @@
+use axum::body::Bytes;
+use axum::extract::{Path, State};
+use axum::http::{header, HeaderMap, StatusCode};
+use axum::response::{IntoResponse, Response};
+use axum::routing::{get, post};
+use axum::{Json, Router};
+use serde::Serialize;
+use std::future::Future;
+use std::mem;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+#[cfg(feature = "metrics")]
+use std::time::Instant;
+use tokio::net::TcpListener;
+
+use crate::exporter::{ClientStateRecord, ExporterConfig};
+use crate::importer::{ImporterConfig, RecordError, TransactionCsvImporter, TransactionImporter};
+#[cfg(feature = "metrics")]
+use crate::metrics::{self, Metrics};
+use crate::model::{ClientId, ClientIdRepr, ClientState, Transaction, TransactionId};
+use crate::rules::RuleSet;
+#[cfg(feature = "metrics")]
+use crate::service::TransactionStatus;
+use crate::service::{
+    log_result, transaction_span, AmountLimits, ProcessingContext, ProcessingError,
+};
+
+/// Long-running HTTP server, which keeps processing context in memory and applies transactions as
+/// they arrive:
+///
+/// * `POST /transactions` accepts a single JSON transaction, or a CSV batch with `text/csv`
+///   content type,
+/// * `GET /clients/{id}` returns the state of a single client,
+/// * `GET /clients` returns states of all clients,
+/// * `GET /metrics` returns processing metrics in Prometheus text format (with the `metrics`
+///   feature).
+///
+/// Transactions are applied one request at a time, in request order, and a batch is applied as a
+/// whole, so transactions of any client are never reordered or interleaved.
+pub struct TransactionServer {
+    context: ProcessingContext,
+    limits: AmountLimits,
+    importer_config: ImporterConfig,
+    exporter_config: ExporterConfig,
+    rules: RuleSet,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+}
+
+// state shared between request handlers; only the context is mutable
+struct ServerState {
+    context: Mutex<ProcessingContext>,
+    // only locked while holding the context lock
+    rules: Mutex<RuleSet>,
+    limits: AmountLimits,
+    importer_config: ImporterConfig,
+    exporter_config: ExporterConfig,
+    #[cfg(feature = "metrics")]
+    metrics: Metrics,
+}
+
+type SharedState = Arc<ServerState>;
+
+#[derive(Serialize)]
+struct BatchResult {
+    processed: usize,
+    rejected: Vec<RejectedTransaction>,
+}
+
+#[derive(Serialize)]
+struct RejectedTransaction {
+    tx: TransactionId,
+    error: ProcessingError,
+}
+
+#[derive(Serialize)]
+struct ErrorResponse {
+    code: &'static str,
+    error: String,
+}
+
+impl TransactionServer {
+    /// Creates a new server, which continues processing from given context.
+    pub fn new(context: ProcessingContext) -> Self {
+        Self {
+            context,
+            limits: Default::default(),
+            importer_config: Default::default(),
+            exporter_config: Default::default(),
+            rules: Default::default(),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+        }
+    }
+
+    /// Sets amount limits for incoming transactions. Transactions outside of limits are rejected.
+    pub fn with_limits(mut self, limits: AmountLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Sets CSV dialect, columns and amount precision handling of incoming transactions.
+    pub fn with_importer_config(mut self, config: ImporterConfig) -> Self {
+        self.importer_config = config;
+        self
+    }
+
+    /// Sets amount scale and rounding of returned client states, like those of exported ones.
+    pub fn with_exporter_config(mut self, config: ExporterConfig) -> Self {
+        self.exporter_config = config;
+        self
+    }
+
+    /// Validates incoming transactions with given rules before applying them.
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Records processing metrics to given, possibly shared, metrics.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = metrics;
+        self
+    }
+
+    /// Serves requests from given listener until `shutdown` completes. In-flight requests are
+    /// allowed to finish, after which the final processing context is returned, e.g. for writing
+    /// a snapshot.
+    pub async fn serve<F>(
+        self,
+        listener: TcpListener,
+        shutdown: F,
+    ) -> anyhow::Result<ProcessingContext>
+    where
+        F: Future<Output = ()> + Send + 'static,
+    {
+        let state = Arc::new(ServerState {
+            context: Mutex::new(self.context),
+            rules: Mutex::new(self.rules),
+            limits: self.limits,
+            importer_config: self.importer_config,
+            exporter_config: self.exporter_config,
+            #[cfg(feature = "metrics")]
+            metrics: self.metrics,
+        });
+
+        axum::serve(listener, router(state.clone()))
+            .with_graceful_shutdown(shutdown)
+            .await?;
+
+        let context = mem::take(&mut *lock_context(&state));
+        Ok(context)
+    }
+}
+
+fn router(state: SharedState) -> Router {
+    let router = Router::new()
+        .route("/transactions", post(post_transactions))
+        .route("/clients", get(get_clients))
+        .route("/clients/:id", get(get_client));
+
+    #[cfg(feature = "metrics")]
+    let router = router.route("/metrics", get(get_metrics));
+
+    router.with_state(state)
+}
+
+async fn post_transactions(
+    State(state): State<SharedState>,
+    headers: HeaderMap,
+    body: Bytes,
+) -> Response {
+    let content_type = headers
+        .get(header::CONTENT_TYPE)
+        .and_then(|value| value.to_str().ok())
+        .unwrap_or("application/json");
+
+    // the whole body is parsed upfront, so malformed batches are not applied partially
+    let transactions = if content_type.starts_with("text/csv") {
+        parse_csv_batch(&body, &state.importer_config)
+    } else if content_type.starts_with("application/json") {
+        parse_json_transaction(&body, &state.importer_config).map(|transaction| vec![transaction])
+    } else {
+        return error_response(
+            StatusCode::UNSUPPORTED_MEDIA_TYPE,
+            "E_UNSUPPORTED_CONTENT_TYPE",
+            format!("Unsupported content type: {}", content_type),
+        );
+    };
+
+    let transactions = match transactions {
+        Ok(transactions) => transactions,
+        // serialized like rejected transactions, so code and severity match all other outputs
+        Err(error) => {
+            let error = ProcessingError::from_import_error(error);
+            return (StatusCode::BAD_REQUEST, Json(error)).into_response();
+        }
+    };
+
+    let mut context = lock_context(&state);
+    let mut rules = lock_rules(&state);
+    let rejected = transactions
+        .iter()
+        .filter_map(|transaction| {
+            let _span = transaction_span(transaction).entered();
+            #[cfg(feature = "metrics")]
+            let started = Instant::now();
+
+            let result = context.apply_with_rules(transaction, &state.limits, &mut rules);
+            log_result(&result);
+
+            #[cfg(feature = "metrics")]
+            state.metrics.record(
+                transaction,
+                *result.as_ref().unwrap_or(&TransactionStatus::Rejected),
+                started.elapsed(),
+            );
+
+            result.err().map(|error| RejectedTransaction {
+                tx: transaction.transaction_id,
+                error,
+            })
+        })
+        .collect();
+
+    Json(BatchResult {
+        processed: transactions.len(),
+        rejected,
+    })
+    .into_response()
+}
+
+async fn get_clients(State(state): State<SharedState>) -> Json<Vec<ClientStateRecord<'static>>> {
+    let mut states: Vec<_> = lock_context(&state).client_states().copied().collect();
+    states.sort_by_key(|state| ClientIdRepr::from(state.client_id()));
+
+    Json(
+        states
+            .iter()
+            .map(|client_state| encode_client_state(client_state, &state.exporter_config))
+            .collect(),
+    )
+}
+
+async fn get_client(State(state): State<SharedState>, Path(client_id): Path<ClientId>) -> Response {
+    match lock_context(&state).client_state(client_id) {
+        Some(client_state) => {
+            Json(encode_client_state(client_state, &state.exporter_config)).into_response()
+        }
+        None => error_response(
+            StatusCode::NOT_FOUND,
+            "E_UNKNOWN_CLIENT",
+            format!("Unknown client: {}", client_id),
+        ),
+    }
+}
+
+#[cfg(feature = "metrics")]
+async fn get_metrics(State(state): State<SharedState>) -> Response {
+    state.metrics.update_gauges(&lock_context(&state));
+    (
+        [(header::CONTENT_TYPE, metrics::CONTENT_TYPE)],
+        state.metrics.encode(),
+    )
+        .into_response()
+}
+
+fn parse_csv_batch(body: &[u8], config: &ImporterConfig) -> anyhow::Result<Vec<Transaction>> {
+    TransactionCsvImporter::from_reader_with_config(body, config.clone())
+        .deserialize()
+        .collect()
+}
+
+// a single transaction is a single record, so its errors are classified like CSV record ones
+fn parse_json_transaction(body: &[u8], config: &ImporterConfig) -> anyhow::Result<Transaction> {
+    let parse = || -> anyhow::Result<Transaction> {
+        let transaction = serde_json::from_slice(body)?;
+        Ok(config
+            .precision_policy
+            .apply(transaction, config.amount_scale)?)
+    };
+
+    parse().map_err(|error| RecordError { line: None, error }.into())
+}
+
+fn lock_context(state: &ServerState) -> MutexGuard<'_, ProcessingContext> {
+    // transactions are applied atomically, so a panicking handler cannot leave a client state
+    // half-updated
+    state.context.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn lock_rules(state: &ServerState) -> MutexGuard<'_, RuleSet> {
+    state.rules.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// amounts are rounded and formatted like exported ones
+fn encode_client_state(
+    client_state: &ClientState,
+    config: &ExporterConfig,
+) -> ClientStateRecord<'static> {
+    ClientStateRecord::new(client_state, config.amount_scale, config.rounding)
+}
+
+fn error_response(status: StatusCode, code: &'static str, error: String) -> Response {
+    (status, Json(ErrorResponse { code, error })).into_response()
+}
+
+#[cfg(test)]
+mod tests {
+    use axum::body::{to_bytes, Body};
+    use axum::http::{header, Request, StatusCode};
+    use axum::Router;
+    use std::sync::{Arc, Mutex};
+    use tower::ServiceExt;
+
+    use crate::exporter::ExporterConfig;
+    use crate::model::RoundingMode;
+    use crate::server::{router, ServerState};
+
+    fn create_test_router() -> Router {
+        create_test_router_with_exporter_config(Default::default())
+    }
+
+    fn create_test_router_with_exporter_config(exporter_config: ExporterConfig) -> Router {
+        router(Arc::new(ServerState {
+            context: Mutex::new(Default::default()),
+            rules: Mutex::new(Default::default()),
+            #[cfg(feature = "metrics")]
+            metrics: Default::default(),
+            limits: Default::default(),
+            importer_config: Default::default(),
+            exporter_config,
+        }))
+    }
+
+    async fn send(router: &Router, request: Request<Body>) -> (StatusCode, String) {
+        let response = router.clone().oneshot(request).await.unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), usize::MAX).await.unwrap();
+
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    fn post(content_type: &str, body: &'static str) -> Request<Body> {
+        Request::post("/transactions")
+            .header(header::CONTENT_TYPE, content_type)
+            .body(Body::from(body))
+            .unwrap()
+    }
+
+    fn get(uri: &str) -> Request<Body> {
+        Request::get(uri).body(Body::empty()).unwrap()
+    }
+
+    #[tokio::test]
+    async fn should_process_json_and_csv_transactions() {
+        let router = create_test_router();
+
+        let (status, body) = send(
+            &router,
+            post(
+                "application/json",
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "2.5"}"#,
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, r#"{"processed":1,"rejected":[]}"#);
+
+        let (status, body) = send(
+            &router,
+            post(
+                "text/csv",
+                "type,client,tx,amount
+withdrawal,1,2,1
+withdrawal,1,3,5
+deposit,2,4,1
+",
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            r#"{"processed":3,"rejected":[{"tx":3,"error":{"code":"E_INSUFFICIENT_FUNDS","severity":"warning","message":"Error for transaction 3: Insufficient funds to perform the operation!","tx":3}}]}"#
+        );
+
+        let (status, body) = send(&router, get("/clients/1")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            r#"{"client":1,"available":"1.5000","held":"0.0000","total":"1.5000","locked":false}"#
+        );
+
+        let (status, body) = send(&router, get("/clients")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(body.starts_with(r#"[{"client":1,"#));
+        assert!(body.contains(r#"{"client":2,"#));
+    }
+
+    #[tokio::test]
+    async fn should_round_client_states_like_exported_ones() {
+        let router = create_test_router_with_exporter_config(ExporterConfig {
+            amount_scale: 2,
+            rounding: RoundingMode::HalfUp,
+            ..Default::default()
+        });
+
+        send(
+            &router,
+            post(
+                "application/json",
+                r#"{"type": "deposit", "client": 1, "tx": 1, "amount": "1.005"}"#,
+            ),
+        )
+        .await;
+
+        let (status, body) = send(&router, get("/clients")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(
+            body,
+            r#"[{"client":1,"available":"1.01","held":"0.00","total":"1.01","locked":false}]"#
+        );
+    }
+
+    #[tokio::test]
+    async fn should_reject_malformed_batches() {
+        let router = create_test_router();
+
+        let (status, body) = send(
+            &router,
+            post(
+                "text/csv",
+                "type,client,tx,amount
+deposit,1,1,1
+deposit,x,2,1
+",
+            ),
+        )
+        .await;
+        assert_eq!(status, StatusCode::BAD_REQUEST);
+        assert!(body.starts_with(r#"{"code":"E_INVALID_RECORD","severity":"error","#));
+
+        // nothing from a malformed batch should be applied
+        let (status, _) = send(&router, get("/clients/1")).await;
+        assert_eq!(status, StatusCode::NOT_FOUND);
+
+        let (status, _) = send(&router, post("text/plain", "")).await;
+        assert_eq!(status, StatusCode::UNSUPPORTED_MEDIA_TYPE);
+    }
+
+    #[cfg(feature = "metrics")]
+    #[tokio::test]
+    async fn should_expose_metrics() {
+        let router = create_test_router();
+
+        send(
+            &router,
+            post(
+                "text/csv",
+                "type,client,tx,amount
+deposit,1,1,1
+withdrawal,1,2,5
+dispute,1,1,
+",
+            ),
+        )
+        .await;
+
+        let (status, body) = send(&router, get("/metrics")).await;
+        assert_eq!(status, StatusCode::OK);
+        assert!(
+            body.contains(r#"engine_transactions_total{outcome="rejected",type="withdrawal"} 1"#)
+        );
+        assert!(body.contains("engine_open_disputes 1"));
+    }
+}
@@ -1,33 +1,252 @@
-use fxhash::FxHashMap;
+use fxhash::{FxHashMap, FxHashSet};
 use rust_decimal::Decimal;
+use serde::ser::SerializeMap;
+use serde::{Deserialize, Serialize, Serializer};
 use std::io::{stderr, BufWriter, Write};
+#[cfg(feature = "metrics")]
+use std::time::Instant;
 use thiserror::Error;
+use tracing::{debug, info, info_span, Span};
 
-use crate::exporter::ClientStateExporter;
-use crate::importer::TransactionImporter;
+use crate::exporter::{ClientStateExporter, TransactionResultExporter};
+use crate::fraud::{FraudAction, FraudDetector};
+use crate::importer::{InputFormatError, RecordError, TransactionImporter};
+use crate::interner::{InternerError, SharedInterner};
+use crate::invariants::{check_client, ViolatingTransaction};
+#[cfg(feature = "metrics")]
+use crate::metrics::Metrics;
 use crate::model::{
-    ClientId, ClientState, Transaction, TransactionError, TransactionId, TransactionType,
+    ClientId, ClientState, Severity, Transaction, TransactionError, TransactionId, TransactionType,
 };
+use crate::observer::{notify_observers, ProcessingObserver};
+use crate::rules::RuleSet;
 
 /// Possible processing errors.
 #[derive(Error, Debug)]
 pub enum ProcessingError {
     #[error("Transaction import error: {0}")]
     ImportError(#[source] anyhow::Error),
+    #[error("{0:#}")]
+    InvalidRecord(#[source] anyhow::Error),
     #[error("Transaction export error: {0}")]
     ExportError(#[source] anyhow::Error),
+    #[error("Processing context store error: {0}")]
+    StoreError(#[source] anyhow::Error),
     #[error("Missing amount for transaction: {0}")]
     MissingAmount(TransactionId),
     #[error("Transaction cannot be disputed again: {0}")]
     CannotDispute(TransactionId),
     #[error("Transaction cannot be resolved or charged back: {0}")]
     CannotResolveOrChargeBack(TransactionId),
+    #[error("Transaction ID is in use by a disputed transaction: {0}")]
+    TransactionUnderDispute(TransactionId),
     #[error("Error for transaction {transaction_id}: {error}")]
     TransactionError {
         transaction_id: TransactionId,
         #[source]
         error: TransactionError,
     },
+    #[error("Withdrawal from an account on hold: {0}")]
+    AccountHeld(TransactionId),
+    #[error("Transaction {transaction_id} rejected by rule {rule}: {reason}")]
+    RuleViolation {
+        transaction_id: TransactionId,
+        rule: String,
+        reason: String,
+    },
+    #[error("Ledger invariant violated: {0}")]
+    InvariantViolation(#[source] Box<ViolatingTransaction>),
+}
+
+impl ProcessingError {
+    /// Classifies an error returned by an importer. Errors of single records are rejected like
+    /// invalid transactions, while any other stops importing.
+    pub fn from_import_error(error: anyhow::Error) -> Self {
+        if error.is::<RecordError>() {
+            ProcessingError::InvalidRecord(error)
+        } else {
+            ProcessingError::ImportError(error)
+        }
+    }
+
+    /// Returns the ID of the transaction which caused this error, if any.
+    pub fn transaction_id(&self) -> Option<TransactionId> {
+        match self {
+            ProcessingError::ImportError(_)
+            | ProcessingError::InvalidRecord(_)
+            | ProcessingError::ExportError(_)
+            | ProcessingError::StoreError(_) => None,
+            ProcessingError::MissingAmount(transaction_id)
+            | ProcessingError::CannotDispute(transaction_id)
+            | ProcessingError::CannotResolveOrChargeBack(transaction_id)
+            | ProcessingError::TransactionUnderDispute(transaction_id)
+            | ProcessingError::AccountHeld(transaction_id)
+            | ProcessingError::TransactionError { transaction_id, .. }
+            | ProcessingError::RuleViolation { transaction_id, .. } => Some(*transaction_id),
+            ProcessingError::InvariantViolation(violation) => {
+                Some(violation.transaction.transaction_id)
+            }
+        }
+    }
+
+    /// Returns a stable, machine-readable code of this error. Transaction errors, as well as
+    /// import errors caused by malformed input structure or identifiers, report the code of the
+    /// underlying error.
+    pub fn code(&self) -> &'static str {
+        match self {
+            ProcessingError::ImportError(error) => import_error_code(error).unwrap_or("E_IMPORT"),
+            ProcessingError::InvalidRecord(error) => {
+                import_error_code(error).unwrap_or("E_INVALID_RECORD")
+            }
+            ProcessingError::ExportError(_) => "E_EXPORT",
+            ProcessingError::StoreError(_) => "E_STORE",
+            ProcessingError::MissingAmount(_) => "E_MISSING_AMOUNT",
+            ProcessingError::CannotDispute(_) => "E_CANNOT_DISPUTE",
+            ProcessingError::CannotResolveOrChargeBack(_) => "E_CANNOT_RESOLVE_OR_CHARGE_BACK",
+            ProcessingError::TransactionUnderDispute(_) => "E_TRANSACTION_UNDER_DISPUTE",
+            ProcessingError::TransactionError { error, .. } => error.code(),
+            ProcessingError::AccountHeld(_) => "E_ACCOUNT_HELD",
+            ProcessingError::RuleViolation { .. } => "E_RULE_VIOLATION",
+            ProcessingError::InvariantViolation(_) => "E_INVARIANT_VIOLATION",
+        }
+    }
+
+    /// Returns the severity of this error. Import, export and store errors, as well as invariant
+    /// violations, stop processing, so they are fatal.
+    pub fn severity(&self) -> Severity {
+        match self {
+            ProcessingError::ImportError(_)
+            | ProcessingError::ExportError(_)
+            | ProcessingError::StoreError(_)
+            | ProcessingError::InvariantViolation(_) => Severity::Fatal,
+            ProcessingError::InvalidRecord(_) | ProcessingError::MissingAmount(_) => {
+                Severity::Error
+            }
+            ProcessingError::CannotDispute(_)
+            | ProcessingError::CannotResolveOrChargeBack(_)
+            | ProcessingError::TransactionUnderDispute(_) => Severity::Warning,
+            ProcessingError::TransactionError { error, .. } => error.severity(),
+            ProcessingError::AccountHeld(_) | ProcessingError::RuleViolation { .. } => {
+                Severity::Warning
+            }
+        }
+    }
+}
+
+fn import_error_code(error: &anyhow::Error) -> Option<&'static str> {
+    // records keep their underlying error aside, so it's not part of the source chain
+    let error = error
+        .downcast_ref::<RecordError>()
+        .map_or(error, |record| &record.error);
+
+    error.chain().find_map(|cause| {
+        cause
+            .downcast_ref::<InputFormatError>()
+            .map(InputFormatError::code)
+            .or_else(|| {
+                cause
+                    .downcast_ref::<InternerError>()
+                    .map(InternerError::code)
+            })
+    })
+}
+
+/// Serializes as a map of `code`, `severity`, `message`, `tx` (if related to a transaction) and
+/// any structured fields of the underlying error.
+impl Serialize for ProcessingError {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("code", self.code())?;
+        map.serialize_entry("severity", &self.severity())?;
+        map.serialize_entry("message", &self.to_string())?;
+        if let Some(transaction_id) = self.transaction_id() {
+            map.serialize_entry("tx", &transaction_id)?;
+        }
+
+        match self {
+            ProcessingError::TransactionError { error, .. } => error.serialize_fields(&mut map)?,
+            ProcessingError::RuleViolation { rule, reason, .. } => {
+                map.serialize_entry("rule", rule)?;
+                map.serialize_entry("reason", reason)?;
+            }
+            _ => {}
+        }
+
+        map.end()
+    }
+}
+
+/// Outcome of processing a single transaction.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TransactionStatus {
+    /// Transaction changed client state.
+    Applied,
+    /// Transaction was invalid and left client state intact.
+    Rejected,
+    /// Transaction referred to an unknown transaction, so there was nothing to apply.
+    Ignored,
+}
+
+impl TransactionStatus {
+    /// Returns the canonical (output data) name of this status.
+    pub fn as_str(self) -> &'static str {
+        match self {
+            TransactionStatus::Applied => "applied",
+            TransactionStatus::Rejected => "rejected",
+            TransactionStatus::Ignored => "ignored",
+        }
+    }
+}
+
+/// Result of processing a single transaction, along with the resulting client state.
+#[derive(Debug, Copy, Clone)]
+pub struct TransactionResult<'a> {
+    pub transaction: &'a Transaction,
+    pub status: TransactionStatus,
+    pub error: Option<&'a ProcessingError>,
+    pub client_state: &'a ClientState,
+}
+
+/// Inclusive amount range allowed for a single transaction.
+#[derive(Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountRange {
+    pub min: Option<Decimal>,
+    pub max: Option<Decimal>,
+}
+
+impl AmountRange {
+    fn check(&self, amount: Decimal) -> Result<(), TransactionError> {
+        match (self.min, self.max) {
+            (Some(min), _) if amount < min => {
+                Err(TransactionError::AmountBelowMinimum { amount, min })
+            }
+            (_, Some(max)) if amount > max => {
+                Err(TransactionError::AmountAboveMaximum { amount, max })
+            }
+            _ => Ok(()),
+        }
+    }
+}
+
+/// Amount limits per transaction type. Disputes, resolves and chargebacks refer to amounts of
+/// already applied transactions, so they are not limited separately.
+#[derive(Deserialize, Debug, Default, Copy, Clone, Eq, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct AmountLimits {
+    pub deposit: AmountRange,
+    pub withdrawal: AmountRange,
+}
+
+/// Abstract persistent storage of processing context, which allows resuming processing across
+/// runs.
+pub trait ContextStore {
+    /// Loads previously saved context, or returns an empty one if there is none.
+    fn load(&mut self) -> anyhow::Result<ProcessingContext>;
+
+    /// Saves given context, previously loaded from this store. Only clients touched since loading
+    /// need to be written.
+    fn save(&mut self, context: &ProcessingContext) -> anyhow::Result<()>;
 }
 
 /// Transaction processing service. Gathers transactions from a data source, computes resulting
@@ -38,6 +257,17 @@ pub struct TransactionProcessor<I: TransactionImporter, E: ClientStateExporter>
     importer: I,
     exporter: E,
     context: ProcessingContext,
+    limits: AmountLimits,
+    interner: Option<SharedInterner>,
+    store: Option<Box<dyn ContextStore>>,
+    result_exporter: Option<Box<dyn TransactionResultExporter>>,
+    observers: Vec<Box<dyn ProcessingObserver>>,
+    rules: RuleSet,
+    fraud_detector: Option<FraudDetector>,
+    check_invariants: bool,
+    error_report: Box<dyn Write>,
+    #[cfg(feature = "metrics")]
+    metrics: Option<Metrics>,
 }
 
 impl<I: TransactionImporter, E: ClientStateExporter> TransactionProcessor<I, E> {
@@ -47,71 +277,519 @@ impl<I: TransactionImporter, E: ClientStateExporter> TransactionProcessor<I, E>
             importer,
             exporter,
             context: Default::default(),
+            limits: Default::default(),
+            interner: None,
+            store: None,
+            result_exporter: None,
+            observers: Vec::new(),
+            rules: Default::default(),
+            fraud_detector: None,
+            check_invariants: false,
+            error_report: Box::new(stderr()),
+            #[cfg(feature = "metrics")]
+            metrics: None,
         }
     }
 
+    /// Persists processing context in given store. Context is loaded from the store before
+    /// processing and saved back afterwards, so subsequent runs continue where previous ones
+    /// finished.
+    pub fn with_store(mut self, store: Box<dyn ContextStore>) -> Self {
+        self.store = Some(store);
+        self
+    }
+
+    /// Emits a result for every processed transaction to given exporter, so each transaction can
+    /// be acknowledged individually.
+    pub fn with_result_exporter(mut self, exporter: Box<dyn TransactionResultExporter>) -> Self {
+        self.result_exporter = Some(exporter);
+        self
+    }
+
+    /// Registers an observer of transaction lifecycle events. Observers are notified in
+    /// registration order.
+    pub fn with_observer(mut self, observer: Box<dyn ProcessingObserver>) -> Self {
+        self.observers.push(observer);
+        self
+    }
+
+    /// Validates transactions with given rules before applying them. Transactions violating any
+    /// rule are rejected.
+    pub fn with_rules(mut self, rules: RuleSet) -> Self {
+        self.rules = rules;
+        self
+    }
+
+    /// Runs fraud heuristics on applied transactions, flagging, holding or locking accounts which
+    /// trigger them.
+    pub fn with_fraud_detector(mut self, detector: FraudDetector) -> Self {
+        self.fraud_detector = Some(detector);
+        self
+    }
+
+    /// Checks ledger invariants of the affected client after every transaction, stopping with
+    /// [`ProcessingError::InvariantViolation`] at the first violating one. Checks go through all
+    /// transactions of the client, so they're meant for debugging and testing.
+    pub fn with_invariant_checks(mut self) -> Self {
+        self.check_invariants = true;
+        self
+    }
+
+    /// Records processing metrics: transaction counts and latency during processing, and context
+    /// size gauges once it's finished.
+    #[cfg(feature = "metrics")]
+    pub fn with_metrics(mut self, metrics: Metrics) -> Self {
+        self.metrics = Some(metrics);
+        self
+    }
+
+    /// Uses given interner to report errors with opaque string transaction identifiers, as seen in
+    /// the input data.
+    pub fn with_interner(mut self, interner: SharedInterner) -> Self {
+        self.interner = Some(interner);
+        self
+    }
+
+    /// Writes the report of rejected transactions, printed after processing, to given writer
+    /// instead of stderr.
+    pub fn with_error_report(mut self, writer: Box<dyn Write>) -> Self {
+        self.error_report = writer;
+        self
+    }
+
+    /// Sets amount limits for incoming transactions. Transactions outside of limits are rejected.
+    pub fn with_limits(mut self, limits: AmountLimits) -> Self {
+        self.limits = limits;
+        self
+    }
+
     /// Processes a list of transactions and computes final client states.
     pub fn process_transactions(mut self) -> Result<(), ProcessingError> {
+        if let Some(store) = &mut self.store {
+            let _span = info_span!("load_context").entered();
+            self.context = store.load().map_err(ProcessingError::StoreError)?;
+        }
+
         self.import_and_process_transactions()?;
-        self.export_client_states()
-    }
 
-    fn export_client_states(&mut self) -> Result<(), ProcessingError> {
-        for client in self.context.clients.values() {
-            self.exporter
-                .serialize(&client.state)
-                .map_err(ProcessingError::ExportError)?;
+        #[cfg(feature = "metrics")]
+        if let Some(metrics) = &self.metrics {
+            metrics.update_gauges(&self.context);
         }
 
+        self.export_client_states()?;
+
+        match &mut self.store {
+            Some(store) => {
+                let _span = info_span!("save_context").entered();
+                store
+                    .save(&self.context)
+                    .map_err(ProcessingError::StoreError)
+            }
+            None => Ok(()),
+        }
+    }
+
+    fn export_client_states(&mut self) -> Result<(), ProcessingError> {
+        let _span = info_span!("export").entered();
+        self.context.export_client_states(&mut self.exporter)?;
+        info!(
+            clients = self.context.clients.len(),
+            "Exported client states"
+        );
         Ok(())
     }
 
     fn import_and_process_transactions(&mut self) -> Result<(), ProcessingError> {
+        // transactions are imported lazily, so processing happens within the import span
+        let _span = info_span!("import").entered();
+        let mut processed = 0usize;
+
         for transaction in self.importer.deserialize() {
-            let transaction = transaction.map_err(ProcessingError::ImportError)?;
+            processed += 1;
+            let transaction = match transaction {
+                Ok(transaction) => transaction,
+                Err(error) => match ProcessingError::from_import_error(error) {
+                    // a single invalid record is rejected like an invalid transaction
+                    error @ ProcessingError::InvalidRecord(_) => {
+                        let result = Err(error);
+                        log_result(&result);
+                        if let Err(error) = result {
+                            if let Some(result_exporter) = &mut self.result_exporter {
+                                result_exporter
+                                    .serialize_invalid_record(&error)
+                                    .map_err(ProcessingError::ExportError)?;
+                            }
+
+                            self.context.transaction_errors.push(error);
+                        }
+                        continue;
+                    }
+                    error => return Err(error),
+                },
+            };
+            let _span = transaction_span(&transaction).entered();
 
-            // get current client state or create a new one
-            let client = self
+            // observers and invariant checks need client state from before the transaction, so
+            // only copy it for them
+            let before = (!self.observers.is_empty() || self.check_invariants).then(|| {
+                self.context
+                    .client_state(transaction.client_id)
+                    .copied()
+                    .unwrap_or_else(|| ClientState::new(transaction.client_id))
+            });
+
+            #[cfg(feature = "metrics")]
+            let started = Instant::now();
+
+            let result = Self::apply_transaction(
+                &mut self.context,
+                &self.limits,
+                &mut self.rules,
+                self.fraud_detector.as_mut(),
+                &transaction,
+            )?;
+            let status = *result.as_ref().unwrap_or(&TransactionStatus::Rejected);
+            log_result(&result);
+
+            #[cfg(feature = "metrics")]
+            if let Some(metrics) = &self.metrics {
+                metrics.record(&transaction, status, started.elapsed());
+            }
+
+            let client_state = self
                 .context
-                .clients
-                .entry(transaction.client_id)
-                .or_insert_with(|| ClientInfo::new(ClientState::new(transaction.client_id)));
+                .client_state(transaction.client_id)
+                .expect("Client state should exist after applying a transaction");
+
+            if let Some(before) = before.filter(|_| self.check_invariants) {
+                check_client(&self.context, transaction.client_id).map_err(|violation| {
+                    ProcessingError::InvariantViolation(Box::new(ViolatingTransaction {
+                        transaction,
+                        before,
+                        after: *client_state,
+                        violation,
+                    }))
+                })?;
+            }
+
+            if let Some(before) = &before {
+                notify_observers(
+                    &mut self.observers,
+                    &transaction,
+                    &result,
+                    before,
+                    client_state,
+                );
+            }
+
+            if let Some(result_exporter) = &mut self.result_exporter {
+                result_exporter
+                    .serialize(&TransactionResult {
+                        transaction: &transaction,
+                        status,
+                        error: result.as_ref().err(),
+                        client_state,
+                    })
+                    .map_err(ProcessingError::ExportError)?;
+            }
 
-            let result = Self::process_transaction(client, &transaction);
             if let Err(error) = result {
                 // a single invalid transaction should not cause all processing to stop
                 // the requirements are unclear how to report the error, so simply aggregate the
-                // errors and print a report to stderr
+                // errors and print a report, to stderr by default
                 self.context.transaction_errors.push(error);
             }
         }
 
+        info!(
+            processed,
+            rejected = self.context.transaction_errors.len(),
+            "Processed transactions"
+        );
         self.report_transaction_errors();
 
-        Ok(())
+        if let Some(detector) = &mut self.fraud_detector {
+            detector.finish().map_err(ProcessingError::ExportError)?;
+        }
+
+        match &mut self.result_exporter {
+            Some(result_exporter) => result_exporter
+                .finish()
+                .map_err(ProcessingError::ExportError),
+            None => Ok(()),
+        }
     }
 
-    fn report_transaction_errors(&self) {
-        // print any tx errors encountered; use a lock to avoid locking on every write
-        let stderr_lock = stderr().lock();
-        let mut writer = BufWriter::new(stderr_lock);
+    // the outer error stops processing, while the inner one only rejects the transaction
+    fn apply_transaction(
+        context: &mut ProcessingContext,
+        limits: &AmountLimits,
+        rules: &mut RuleSet,
+        fraud_detector: Option<&mut FraudDetector>,
+        transaction: &Transaction,
+    ) -> Result<Result<TransactionStatus, ProcessingError>, ProcessingError> {
+        let detector = match fraud_detector {
+            Some(detector) => detector,
+            None => return Ok(context.apply_with_rules(transaction, limits, rules)),
+        };
+
+        let result = detector
+            .check(transaction)
+            .and_then(|_| context.apply_with_rules(transaction, limits, rules));
+
+        if let Ok(TransactionStatus::Applied) = result {
+            let action = detector
+                .record(transaction)
+                .map_err(ProcessingError::ExportError)?;
+
+            if action == Some(FraudAction::Lock) {
+                context.lock_client(transaction.client_id);
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn report_transaction_errors(&mut self) {
+        // print any tx errors encountered; buffer them to avoid locking stderr on every write
+        let mut writer = BufWriter::new(&mut self.error_report);
+        let interner = self.interner.as_ref().map(|interner| interner.borrow());
 
         for error in &self.context.transaction_errors {
+            let external_id = interner
+                .as_ref()
+                .zip(error.transaction_id())
+                .and_then(|(interner, transaction_id)| interner.transaction_name(transaction_id));
+
             // handling errors during error reporting is quite tricky, so for the sake of simplicity
             // in this example, we simply ignore it
-            let _ = writeln!(&mut writer, "{}", error);
+            let severity = error.severity().as_str();
+            let code = error.code();
+            let _ = match external_id {
+                Some(external_id) => writeln!(
+                    &mut writer,
+                    "{} {}: {} (tx: {})",
+                    severity, code, error, external_id
+                ),
+                None => writeln!(&mut writer, "{} {}: {}", severity, code, error),
+            };
+        }
+    }
+}
+
+/// State of an applied transaction.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub enum TransactionState {
+    Applied,
+    Disputed,
+    ChargedBack,
+}
+
+impl TransactionState {
+    #[inline]
+    fn can_dispute(self) -> bool {
+        // we can only dispute applied transactions, not ones already disputed/charged back
+        self == TransactionState::Applied
+    }
+
+    #[inline]
+    fn can_resolve_or_charge_back(self) -> bool {
+        self == TransactionState::Disputed
+    }
+}
+
+/// Applied transaction, which can be referenced by disputes, resolves and chargebacks.
+#[derive(Debug, Copy, Clone, Eq, PartialEq)]
+pub struct TransactionInfo {
+    pub amount: Decimal,
+    pub state: TransactionState,
+    pub r#type: TransactionType,
+}
+
+impl TransactionInfo {
+    #[inline]
+    fn new(amount: Decimal, r#type: TransactionType) -> Self {
+        Self {
+            amount,
+            state: TransactionState::Applied,
+            r#type,
+        }
+    }
+
+    #[inline]
+    fn can_dispute(&self) -> bool {
+        // the requirements suggest we handle only deposit disputes - see the README for details
+        self.r#type == TransactionType::Deposit && self.state.can_dispute()
+    }
+
+    #[inline]
+    fn can_resolve_or_charge_back(&self) -> bool {
+        self.state.can_resolve_or_charge_back()
+    }
+}
+
+// client state with all referenced transactions
+struct ClientInfo {
+    state: ClientState,
+    transactions: FxHashMap<TransactionId, TransactionInfo>,
+}
+
+impl ClientInfo {
+    #[inline]
+    fn new(state: ClientState) -> Self {
+        Self {
+            state,
+            transactions: Default::default(),
         }
     }
+}
+
+/// Current state of processing: all client states along with transactions they reference.
+#[derive(Default)]
+pub struct ProcessingContext {
+    clients: FxHashMap<ClientId, ClientInfo>,
+    // clients changed by processing, as opposed to being inserted when loading
+    touched_clients: FxHashSet<ClientId>,
+    transaction_errors: Vec<ProcessingError>,
+}
+
+impl ProcessingContext {
+    /// Returns an iterator over all client states.
+    pub fn client_states(&self) -> impl Iterator<Item = &ClientState> {
+        self.clients.values().map(|client| &client.state)
+    }
+
+    /// Returns an iterator over all transactions which can be referenced, along with their
+    /// client IDs.
+    pub fn transactions(
+        &self,
+    ) -> impl Iterator<Item = (ClientId, TransactionId, &TransactionInfo)> {
+        self.clients.iter().flat_map(|(client_id, client)| {
+            client
+                .transactions
+                .iter()
+                .map(move |(transaction_id, info)| (*client_id, *transaction_id, info))
+        })
+    }
+
+    /// Returns an iterator over IDs of clients touched by processing, i.e. the ones whose state or
+    /// transactions might have changed since the context has been loaded.
+    pub fn touched_clients(&self) -> impl Iterator<Item = ClientId> + '_ {
+        self.touched_clients.iter().copied()
+    }
+
+    /// Returns an iterator over all transactions of given client which can be referenced.
+    pub fn client_transactions(
+        &self,
+        client_id: ClientId,
+    ) -> impl Iterator<Item = (TransactionId, &TransactionInfo)> {
+        self.clients.get(&client_id).into_iter().flat_map(|client| {
+            client
+                .transactions
+                .iter()
+                .map(|(transaction_id, info)| (*transaction_id, info))
+        })
+    }
+
+    /// Applies a single transaction to the state of its client, creating the client if needed.
+    /// Returns whether the transaction has been applied or ignored; invalid transactions leave the
+    /// state intact and are returned as errors.
+    pub fn apply(
+        &mut self,
+        transaction: &Transaction,
+        limits: &AmountLimits,
+    ) -> Result<TransactionStatus, ProcessingError> {
+        self.apply_with_rules(transaction, limits, &mut RuleSet::default())
+    }
+
+    /// Applies a single transaction like [`apply`](Self::apply), provided it passes all given
+    /// rules. Rules record the transaction once it has been applied.
+    pub fn apply_with_rules(
+        &mut self,
+        transaction: &Transaction,
+        limits: &AmountLimits,
+        rules: &mut RuleSet,
+    ) -> Result<TransactionStatus, ProcessingError> {
+        self.touched_clients.insert(transaction.client_id);
+
+        // get current client state or create a new one
+        let client = self
+            .clients
+            .entry(transaction.client_id)
+            .or_insert_with(|| ClientInfo::new(ClientState::new(transaction.client_id)));
+
+        rules.check(transaction, &client.state)?;
+
+        let status = Self::process_transaction(client, transaction, limits)?;
+        if status == TransactionStatus::Applied {
+            rules.record(transaction);
+        }
+
+        Ok(status)
+    }
+
+    /// Writes all client states to given exporter and finishes the export.
+    pub fn export_client_states<E: ClientStateExporter>(
+        &self,
+        exporter: &mut E,
+    ) -> Result<(), ProcessingError> {
+        for client in self.clients.values() {
+            exporter
+                .serialize(&client.state)
+                .map_err(ProcessingError::ExportError)?;
+        }
+
+        exporter.finish().map_err(ProcessingError::ExportError)
+    }
+
+    /// Returns the state of given client, if any of its transactions have been processed.
+    pub fn client_state(&self, client_id: ClientId) -> Option<&ClientState> {
+        self.clients.get(&client_id).map(|client| &client.state)
+    }
+
+    /// Locks the account of given client, if it exists.
+    pub fn lock_client(&mut self, client_id: ClientId) {
+        if let Some(client) = self.clients.get_mut(&client_id) {
+            client.state.lock();
+            self.touched_clients.insert(client_id);
+        }
+    }
+
+    /// Inserts or replaces given client state, keeping its transactions intact.
+    pub fn insert_client_state(&mut self, state: ClientState) {
+        self.clients
+            .entry(state.client_id())
+            .and_modify(|client| client.state = state)
+            .or_insert_with(|| ClientInfo::new(state));
+    }
+
+    /// Inserts or replaces given transaction of a client. Creates an empty client state if needed.
+    pub fn insert_transaction(
+        &mut self,
+        client_id: ClientId,
+        transaction_id: TransactionId,
+        info: TransactionInfo,
+    ) {
+        self.clients
+            .entry(client_id)
+            .or_insert_with(|| ClientInfo::new(ClientState::new(client_id)))
+            .transactions
+            .insert(transaction_id, info);
+    }
 
     fn process_transaction(
         client: &mut ClientInfo,
         transaction: &Transaction,
-    ) -> Result<(), ProcessingError> {
+        limits: &AmountLimits,
+    ) -> Result<TransactionStatus, ProcessingError> {
         match transaction.r#type {
             TransactionType::Deposit => {
                 let amount = extract_amount(transaction)?;
+                check_not_disputed(client, transaction.transaction_id)?;
 
                 map_from_transaction_error(transaction.transaction_id, || {
+                    limits.deposit.check(amount)?;
                     client.state.deposit(amount)
                 })?;
 
@@ -122,8 +800,10 @@ impl<I: TransactionImporter, E: ClientStateExporter> TransactionProcessor<I, E>
             }
             TransactionType::Withdrawal => {
                 let amount = extract_amount(transaction)?;
+                check_not_disputed(client, transaction.transaction_id)?;
 
                 map_from_transaction_error(transaction.transaction_id, || {
+                    limits.withdrawal.check(amount)?;
                     client.state.withdraw(amount)
                 })?;
 
@@ -146,6 +826,8 @@ impl<I: TransactionImporter, E: ClientStateExporter> TransactionProcessor<I, E>
                     })?;
 
                     original_transaction.state = TransactionState::Disputed;
+                } else {
+                    return Ok(TransactionStatus::Ignored);
                 }
             }
             TransactionType::Resolve => {
@@ -165,6 +847,8 @@ impl<I: TransactionImporter, E: ClientStateExporter> TransactionProcessor<I, E>
 
                     // switch back to applied - can be disputed again
                     original_transaction.state = TransactionState::Applied;
+                } else {
+                    return Ok(TransactionStatus::Ignored);
                 }
             }
             TransactionType::Chargeback => {
@@ -183,85 +867,40 @@ impl<I: TransactionImporter, E: ClientStateExporter> TransactionProcessor<I, E>
                     })?;
 
                     original_transaction.state = TransactionState::ChargedBack;
+                } else {
+                    return Ok(TransactionStatus::Ignored);
                 }
             }
         };
 
-        Ok(())
-    }
-}
-
-#[derive(Copy, Clone, Eq, PartialEq)]
-enum TransactionState {
-    Applied,
-    Disputed,
-    ChargedBack,
-}
-
-impl TransactionState {
-    #[inline]
-    fn can_dispute(self) -> bool {
-        // we can only dispute applied transactions, not ones already disputed/charged back
-        self == TransactionState::Applied
-    }
-
-    #[inline]
-    fn can_resolve_or_charge_back(self) -> bool {
-        self == TransactionState::Disputed
-    }
-}
-
-#[derive(Copy, Clone)]
-struct TransactionInfo {
-    amount: Decimal,
-    state: TransactionState,
-    r#type: TransactionType,
-}
-
-impl TransactionInfo {
-    #[inline]
-    fn new(amount: Decimal, r#type: TransactionType) -> Self {
-        Self {
-            amount,
-            state: TransactionState::Applied,
-            r#type,
-        }
-    }
-
-    #[inline]
-    fn can_dispute(&self) -> bool {
-        // the requirements suggest we handle only deposit disputes - see the README for details
-        self.r#type == TransactionType::Deposit && self.state.can_dispute()
-    }
-
-    #[inline]
-    fn can_resolve_or_charge_back(&self) -> bool {
-        self.state.can_resolve_or_charge_back()
+        Ok(TransactionStatus::Applied)
     }
 }
 
-// client state with all referenced transactions
-struct ClientInfo {
-    state: ClientState,
-    transactions: FxHashMap<TransactionId, TransactionInfo>,
+/// Creates a span identifying a single transaction, so all its events can be correlated. Disabled
+/// spans cost next to nothing, so there's no need to check the log level upfront.
+pub(crate) fn transaction_span(transaction: &Transaction) -> Span {
+    info_span!(
+        "transaction",
+        client_id = %transaction.client_id,
+        transaction_id = %transaction.transaction_id,
+        "type" = transaction.r#type.as_str(),
+    )
 }
 
-impl ClientInfo {
-    #[inline]
-    fn new(state: ClientState) -> Self {
-        Self {
-            state,
-            transactions: Default::default(),
-        }
+/// Logs the outcome of processing a single transaction, within its span.
+pub(crate) fn log_result(result: &Result<TransactionStatus, ProcessingError>) {
+    match result {
+        Ok(status) => debug!(status = status.as_str(), "Processed transaction"),
+        // rejections are reported along with all other errors, so only their code is traced here
+        Err(error) => debug!(
+            status = TransactionStatus::Rejected.as_str(),
+            code = error.code(),
+            "Processed transaction"
+        ),
     }
 }
 
-#[derive(Default)]
-struct ProcessingContext {
-    clients: FxHashMap<ClientId, ClientInfo>,
-    transaction_errors: Vec<ProcessingError>,
-}
-
 #[inline]
 fn extract_amount(transaction: &Transaction) -> Result<Decimal, ProcessingError> {
     transaction
@@ -269,6 +908,20 @@ fn extract_amount(transaction: &Transaction) -> Result<Decimal, ProcessingError>
         .ok_or(ProcessingError::MissingAmount(transaction.transaction_id))
 }
 
+// reused IDs replace previous transactions, which must not orphan funds held by a dispute
+#[inline]
+fn check_not_disputed(
+    client: &ClientInfo,
+    transaction_id: TransactionId,
+) -> Result<(), ProcessingError> {
+    match client.transactions.get(&transaction_id) {
+        Some(info) if info.state == TransactionState::Disputed => {
+            Err(ProcessingError::TransactionUnderDispute(transaction_id))
+        }
+        _ => Ok(()),
+    }
+}
+
 #[inline]
 fn map_from_transaction_error<F: FnOnce() -> Result<(), TransactionError>>(
     transaction_id: TransactionId,
@@ -284,12 +937,20 @@ fn map_from_transaction_error<F: FnOnce() -> Result<(), TransactionError>>(
 #[cfg(test)]
 mod tests {
     use rust_decimal::Decimal;
-    use std::io::Read;
+    use std::cell::RefCell;
+    use std::io::{Read, Write};
+    use std::rc::Rc;
+    use std::sync::{Arc, Mutex};
 
-    use crate::exporter::ClientStateExporter;
-    use crate::importer::TransactionCsvImporter;
-    use crate::model::{ClientId, ClientState};
-    use crate::service::TransactionProcessor;
+    use crate::exporter::{ClientStateExporter, TransactionResultExporter};
+    use crate::importer::{InputFormatError, RecordError, TransactionCsvImporter};
+    use crate::interner::InternerError;
+    use crate::invariants::InvariantViolation;
+    use crate::model::{ClientId, ClientState, Severity, TransactionError, TransactionId};
+    use crate::service::{
+        AmountLimits, AmountRange, ContextStore, ProcessingContext, ProcessingError,
+        TransactionProcessor, TransactionResult, TransactionStatus,
+    };
 
     #[derive(Clone, Default)]
     struct CachingExporter {
@@ -312,6 +973,19 @@ mod tests {
         )
     }
 
+    // loads a given context once, e.g. a corrupted one
+    struct SnapshotStore(Option<ProcessingContext>);
+
+    impl ContextStore for SnapshotStore {
+        fn load(&mut self) -> anyhow::Result<ProcessingContext> {
+            Ok(self.0.take().unwrap_or_default())
+        }
+
+        fn save(&mut self, _context: &ProcessingContext) -> anyhow::Result<()> {
+            Ok(())
+        }
+    }
+
     #[test]
     fn should_apply_transactions_for_single_client() {
         let csv = "type,client,tx,amount
@@ -406,4 +1080,332 @@ dispute,1,2,
         assert!(exporter.client_states[0].held().is_zero());
         assert!(!exporter.client_states[0].locked());
     }
+
+    #[test]
+    fn should_not_replace_disputed_transaction() {
+        let csv = "type,client,tx,amount
+deposit,1,1,2
+dispute,1,1,
+deposit,1,1,3
+resolve,1,1,
+";
+
+        let (importer, mut exporter) = create_importer_and_exporter(csv.as_bytes());
+        let processor = TransactionProcessor::new(importer, &mut exporter);
+        processor.process_transactions().unwrap();
+
+        assert_eq!(exporter.client_states.len(), 1);
+        assert_eq!(exporter.client_states[0].available(), Decimal::from(2));
+        assert!(exporter.client_states[0].held().is_zero());
+        assert_eq!(exporter.client_states[0].total(), Decimal::from(2));
+    }
+
+    #[test]
+    fn should_reject_amounts_outside_of_limits() {
+        let csv = "type,client,tx,amount
+deposit,1,1,0.5
+deposit,1,2,2000
+deposit,1,3,10
+withdrawal,1,4,0
+withdrawal,1,5,6
+withdrawal,1,6,4
+";
+
+        let limits = AmountLimits {
+            deposit: AmountRange {
+                min: Some(Decimal::from(1)),
+                max: Some(Decimal::from(1000)),
+            },
+            withdrawal: AmountRange {
+                min: None,
+                max: Some(Decimal::from(5)),
+            },
+        };
+
+        let (importer, mut exporter) = create_importer_and_exporter(csv.as_bytes());
+        let processor = TransactionProcessor::new(importer, &mut exporter).with_limits(limits);
+        processor.process_transactions().unwrap();
+
+        assert_eq!(exporter.client_states.len(), 1);
+        assert_eq!(exporter.client_states[0].total(), Decimal::from(6));
+    }
+
+    #[test]
+    fn should_reject_invalid_records_and_continue() {
+        let csv = "type,client,tx,amount
+deposit,1,1,2
+deposit,1,2,0.00005
+deposit,1,3,abc
+deposit,1
+deposit,2,4,1.5
+";
+
+        let (importer, mut exporter) = create_importer_and_exporter(csv.as_bytes());
+        let processor = TransactionProcessor::new(importer, &mut exporter);
+        processor.process_transactions().unwrap();
+
+        assert_eq!(exporter.client_states.len(), 2);
+        assert_eq!(exporter.client_states[0].total(), Decimal::from(2));
+        assert_eq!(exporter.client_states[1].total(), Decimal::new(15, 1));
+    }
+
+    #[test]
+    fn should_emit_result_for_every_transaction() {
+        let csv = "type,client,tx,amount
+deposit,1,1,2
+withdrawal,1,2,5
+deposit,1,3,abc
+dispute,1,1,
+resolve,1,9,
+";
+
+        type ResultRow = (
+            Option<u32>,
+            TransactionStatus,
+            Option<&'static str>,
+            Decimal,
+        );
+
+        #[derive(Clone, Default)]
+        struct CachingResultExporter(Rc<RefCell<Vec<ResultRow>>>);
+
+        impl TransactionResultExporter for CachingResultExporter {
+            fn serialize(&mut self, result: &TransactionResult) -> anyhow::Result<()> {
+                self.0.borrow_mut().push((
+                    Some(result.transaction.transaction_id.into()),
+                    result.status,
+                    result.error.map(ProcessingError::code),
+                    result.client_state.available(),
+                ));
+                Ok(())
+            }
+
+            fn serialize_invalid_record(&mut self, error: &ProcessingError) -> anyhow::Result<()> {
+                self.0.borrow_mut().push((
+                    None,
+                    TransactionStatus::Rejected,
+                    Some(error.code()),
+                    Decimal::ZERO,
+                ));
+                Ok(())
+            }
+        }
+
+        let results = CachingResultExporter::default();
+        let (importer, mut exporter) = create_importer_and_exporter(csv.as_bytes());
+        let processor = TransactionProcessor::new(importer, &mut exporter)
+            .with_result_exporter(Box::new(results.clone()));
+        processor.process_transactions().unwrap();
+
+        assert_eq!(
+            *results.0.borrow(),
+            vec![
+                (Some(1), TransactionStatus::Applied, None, Decimal::from(2)),
+                (
+                    Some(2),
+                    TransactionStatus::Rejected,
+                    Some("E_INSUFFICIENT_FUNDS"),
+                    Decimal::from(2)
+                ),
+                (
+                    None,
+                    TransactionStatus::Rejected,
+                    Some("E_INVALID_RECORD"),
+                    Decimal::ZERO
+                ),
+                (Some(1), TransactionStatus::Applied, None, Decimal::ZERO),
+                (Some(9), TransactionStatus::Ignored, None, Decimal::ZERO),
+            ]
+        );
+    }
+
+    #[test]
+    fn should_serialize_errors_with_stable_codes() {
+        let error = ProcessingError::TransactionError {
+            transaction_id: TransactionId::new(4),
+            error: TransactionError::AmountAboveMaximum {
+                amount: Decimal::from(6),
+                max: Decimal::from(5),
+            },
+        };
+
+        assert_eq!(error.code(), "E_AMOUNT_ABOVE_MAXIMUM");
+        assert_eq!(error.severity(), Severity::Warning);
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"code":"E_AMOUNT_ABOVE_MAXIMUM","severity":"warning","message":"Error for transaction 4: Amount 6 exceeds the maximum of 5","tx":4,"amount":"6","max":"5"}"#
+        );
+
+        let error = ProcessingError::ImportError(anyhow::anyhow!("Broken input"));
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"code":"E_IMPORT","severity":"fatal","message":"Transaction import error: Broken input"}"#
+        );
+
+        let error = ProcessingError::RuleViolation {
+            transaction_id: TransactionId::new(2),
+            rule: "blocked_clients".into(),
+            reason: "Client 1 is blocked".into(),
+        };
+        assert_eq!(
+            serde_json::to_string(&error).unwrap(),
+            r#"{"code":"E_RULE_VIOLATION","severity":"warning","message":"Transaction 2 rejected by rule blocked_clients: Client 1 is blocked","tx":2,"rule":"blocked_clients","reason":"Client 1 is blocked"}"#
+        );
+    }
+
+    #[test]
+    fn should_report_codes_of_underlying_import_errors() {
+        let error = anyhow::Error::from(InputFormatError::MissingColumn("amount".into()))
+            .context("Error reading input.csv");
+        let error = ProcessingError::from_import_error(error);
+        assert_eq!(error.code(), "E_MISSING_COLUMN");
+        assert_eq!(error.severity(), Severity::Fatal);
+
+        let error = ProcessingError::from_import_error(
+            RecordError {
+                line: Some(2),
+                error: InternerError::ClientIdsExhausted("a".into()).into(),
+            }
+            .into(),
+        );
+        assert_eq!(error.code(), "E_CLIENT_IDS_EXHAUSTED");
+        assert_eq!(error.severity(), Severity::Error);
+
+        let error = ProcessingError::from_import_error(
+            RecordError {
+                line: Some(2),
+                error: anyhow::anyhow!("Invalid amount"),
+            }
+            .into(),
+        );
+        assert_eq!(error.code(), "E_INVALID_RECORD");
+    }
+
+    #[derive(Clone, Default)]
+    struct SharedBuffer(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for SharedBuffer {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn should_log_rejections_within_transaction_spans() {
+        let buffer = SharedBuffer::default();
+        let writer = buffer.clone();
+        let subscriber = tracing_subscriber::fmt()
+            .with_max_level(tracing::Level::DEBUG)
+            .json()
+            .with_current_span(true)
+            .with_writer(move || writer.clone())
+            .finish();
+
+        let (importer, mut exporter) = create_importer_and_exporter(
+            "type,client,tx,amount
+deposit,1,1,2
+withdrawal,1,2,5
+"
+            .as_bytes(),
+        );
+        tracing::subscriber::with_default(subscriber, || {
+            TransactionProcessor::new(importer, &mut exporter)
+                .process_transactions()
+                .unwrap();
+        });
+
+        let logs = String::from_utf8(buffer.0.lock().unwrap().clone()).unwrap();
+        let rejection: serde_json::Value = logs
+            .lines()
+            .map(|line| serde_json::from_str(line).unwrap())
+            .find(|event: &serde_json::Value| event["fields"]["status"] == "rejected")
+            .unwrap();
+
+        assert_eq!(rejection["fields"]["code"], "E_INSUFFICIENT_FUNDS");
+        assert_eq!(rejection["span"]["name"], "transaction");
+        assert_eq!(rejection["span"]["client_id"], "1");
+        assert_eq!(rejection["span"]["transaction_id"], "2");
+        assert_eq!(rejection["span"]["type"], "withdrawal");
+    }
+
+    #[test]
+    fn should_report_rejections_to_given_writer() {
+        let report = SharedBuffer::default();
+        let (importer, mut exporter) = create_importer_and_exporter(
+            "type,client,tx,amount
+deposit,1,1,2
+withdrawal,1,2,5
+deposit,1,3,abc
+"
+            .as_bytes(),
+        );
+        TransactionProcessor::new(importer, &mut exporter)
+            .with_error_report(Box::new(report.clone()))
+            .process_transactions()
+            .unwrap();
+
+        let report = String::from_utf8(report.0.lock().unwrap().clone()).unwrap();
+        let codes: Vec<_> = report
+            .lines()
+            .map(|line| line.split(':').next().unwrap())
+            .collect();
+        assert_eq!(
+            codes,
+            vec!["warning E_INSUFFICIENT_FUNDS", "error E_INVALID_RECORD"]
+        );
+    }
+
+    #[test]
+    fn should_stop_at_first_invariant_violation() {
+        let csv = "type,client,tx,amount
+deposit,2,1,1.0
+deposit,1,2,1.0
+deposit,1,3,1.0
+";
+
+        // held funds without a matching dispute
+        let mut snapshot = ProcessingContext::default();
+        snapshot.insert_client_state(ClientState::from_parts(
+            ClientId::new(1),
+            Decimal::ZERO,
+            Decimal::ONE,
+            Decimal::ONE,
+            false,
+        ));
+
+        let (importer, mut exporter) = create_importer_and_exporter(csv.as_bytes());
+        let error = TransactionProcessor::new(importer, &mut exporter)
+            .with_store(Box::new(SnapshotStore(Some(snapshot))))
+            .with_invariant_checks()
+            .process_transactions()
+            .unwrap_err();
+
+        assert_eq!(error.code(), "E_INVARIANT_VIOLATION");
+        assert_eq!(error.transaction_id(), Some(TransactionId::new(2)));
+
+        let ProcessingError::InvariantViolation(violation) = &error else {
+            panic!("Unexpected error: {}", error);
+        };
+        assert_eq!(violation.before.available(), Decimal::ZERO);
+        assert_eq!(violation.after.available(), Decimal::ONE);
+        assert_eq!(
+            violation.violation,
+            InvariantViolation::Held {
+                client_id: ClientId::new(1),
+                held: Decimal::ONE,
+                disputed: Decimal::ZERO,
+            }
+        );
+        assert_eq!(
+            error.to_string(),
+            "Ledger invariant violated: Held 1 of client 1 doesn't equal disputed amount 0; after \
+             deposit 2 with amount 1.0, state changed from (available 0, held 1, total 1, locked \
+             false) to (available 1.0, held 1, total 2.0, locked false)"
+        );
+        assert!(exporter.client_states.is_empty());
+    }
 }
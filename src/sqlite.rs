@@ -0,0 +1,473 @@
+use anyhow::{anyhow, Context, Result};
+use rusqlite::{params, Connection};
+use rust_decimal::Decimal;
+use std::path::Path;
+use std::str::FromStr;
+
+use crate::exporter::ClientStateExporter;
+use crate::model::{ClientId, ClientIdRepr, ClientState, TransactionId};
+use crate::service::{ContextStore, ProcessingContext, TransactionInfo, TransactionState};
+
+// amounts are stored as text to keep them exact; SQLite can still cast them when querying
+const CLIENT_STATES_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS client_states (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL
+    );
+";
+
+const CONTEXT_SCHEMA: &str = "
+    CREATE TABLE IF NOT EXISTS context_clients (
+        client INTEGER PRIMARY KEY,
+        available TEXT NOT NULL,
+        held TEXT NOT NULL,
+        total TEXT NOT NULL,
+        locked INTEGER NOT NULL
+    );
+    CREATE TABLE IF NOT EXISTS context_transactions (
+        client INTEGER NOT NULL,
+        tx INTEGER NOT NULL,
+        type TEXT NOT NULL,
+        amount TEXT NOT NULL,
+        state TEXT NOT NULL,
+        PRIMARY KEY (client, tx)
+    );
+";
+
+const UPSERT_CLIENT_STATE: &str = "
+    INSERT INTO client_states (client, available, held, total, locked)
+    VALUES (?1, ?2, ?3, ?4, ?5)
+    ON CONFLICT (client) DO UPDATE SET
+        available = excluded.available,
+        held = excluded.held,
+        total = excluded.total,
+        locked = excluded.locked
+";
+
+const UPSERT_CONTEXT_CLIENT: &str = "
+    INSERT INTO context_clients (client, available, held, total, locked)
+    VALUES (?1, ?2, ?3, ?4, ?5)
+    ON CONFLICT (client) DO UPDATE SET
+        available = excluded.available,
+        held = excluded.held,
+        total = excluded.total,
+        locked = excluded.locked
+";
+
+const UPSERT_CONTEXT_TRANSACTION: &str = "
+    INSERT INTO context_transactions (client, tx, type, amount, state)
+    VALUES (?1, ?2, ?3, ?4, ?5)
+    ON CONFLICT (client, tx) DO UPDATE SET
+        type = excluded.type,
+        amount = excluded.amount,
+        state = excluded.state
+";
+
+/// Client state exporter upserting states into a `client_states` SQLite table. All states of a
+/// single run are written in one database transaction.
+pub struct SqliteClientStateExporter {
+    connection: Connection,
+    in_transaction: bool,
+}
+
+impl SqliteClientStateExporter {
+    /// Creates a new exporter writing to given database file, creating the table if needed.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(open_connection(path)?)
+    }
+
+    /// Creates a new exporter writing to given database connection.
+    pub fn new(connection: Connection) -> Result<Self> {
+        connection
+            .execute_batch(CLIENT_STATES_SCHEMA)
+            .context("Error creating client state table")?;
+
+        Ok(Self {
+            connection,
+            in_transaction: false,
+        })
+    }
+}
+
+impl SqliteClientStateExporter {
+    fn upsert(&mut self, client_state: &ClientState) -> Result<()> {
+        self.connection
+            .prepare_cached(UPSERT_CLIENT_STATE)?
+            .execute(params![
+                client_id_to_sql(client_state.client_id())?,
+                client_state.available().to_string(),
+                client_state.held().to_string(),
+                client_state.total().to_string(),
+                client_state.locked(),
+            ])
+            .with_context(|| {
+                format!(
+                    "Error serializing state for client: {}",
+                    client_state.client_id()
+                )
+            })?;
+
+        Ok(())
+    }
+
+    // discards partially exported states, so the connection is left without an open transaction
+    fn roll_back(&mut self, error: anyhow::Error) -> anyhow::Error {
+        self.in_transaction = false;
+        match self.connection.execute_batch("ROLLBACK") {
+            Ok(()) => error,
+            Err(rollback_error) => error.context(format!(
+                "Error rolling back client states: {}",
+                rollback_error
+            )),
+        }
+    }
+}
+
+impl ClientStateExporter for SqliteClientStateExporter {
+    fn serialize(&mut self, client_state: &ClientState) -> Result<()> {
+        if !self.in_transaction {
+            self.connection.execute_batch("BEGIN")?;
+            self.in_transaction = true;
+        }
+
+        self.upsert(client_state)
+            .map_err(|error| self.roll_back(error))
+    }
+
+    fn finish(&mut self) -> Result<()> {
+        if self.in_transaction {
+            self.connection
+                .execute_batch("COMMIT")
+                .context("Error committing client states")
+                .map_err(|error| self.roll_back(error))?;
+            self.in_transaction = false;
+        }
+
+        Ok(())
+    }
+}
+
+/// Processing context store persisting client states and transaction history in
+/// `context_clients` and `context_transactions` SQLite tables.
+pub struct SqliteContextStore {
+    connection: Connection,
+}
+
+impl SqliteContextStore {
+    /// Creates a new store in given database file, creating tables if needed.
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        Self::new(open_connection(path)?)
+    }
+
+    /// Creates a new store in given database connection.
+    pub fn new(connection: Connection) -> Result<Self> {
+        connection
+            .execute_batch(CONTEXT_SCHEMA)
+            .context("Error creating processing context tables")?;
+
+        Ok(Self { connection })
+    }
+}
+
+impl ContextStore for SqliteContextStore {
+    fn load(&mut self) -> Result<ProcessingContext> {
+        let mut context = ProcessingContext::default();
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT client, available, held, total, locked FROM context_clients")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            context.insert_client_state(ClientState::from_parts(
+                client_id_from_sql(row.get(0)?)?,
+                decimal_from_sql(row.get(1)?)?,
+                decimal_from_sql(row.get(2)?)?,
+                decimal_from_sql(row.get(3)?)?,
+                row.get(4)?,
+            ));
+        }
+
+        let mut statement = self
+            .connection
+            .prepare("SELECT client, tx, type, amount, state FROM context_transactions")?;
+        let mut rows = statement.query([])?;
+        while let Some(row) = rows.next()? {
+            let r#type: String = row.get(2)?;
+            let state: String = row.get(4)?;
+
+            context.insert_transaction(
+                client_id_from_sql(row.get(0)?)?,
+                TransactionId::new(row.get(1)?),
+                TransactionInfo {
+                    amount: decimal_from_sql(row.get(3)?)?,
+                    state: transaction_state_from_sql(&state)?,
+                    r#type: r#type.parse().map_err(|error: String| anyhow!(error))?,
+                },
+            );
+        }
+
+        Ok(context)
+    }
+
+    fn save(&mut self, context: &ProcessingContext) -> Result<()> {
+        // dropping an uncommitted transaction rolls it back, so failures leave the store intact
+        let transaction = self.connection.transaction()?;
+
+        {
+            let mut client_statement = transaction.prepare(UPSERT_CONTEXT_CLIENT)?;
+            let mut transaction_statement = transaction.prepare(UPSERT_CONTEXT_TRANSACTION)?;
+
+            for client_id in context.touched_clients() {
+                let client = client_id_to_sql(client_id)?;
+
+                if let Some(state) = context.client_state(client_id) {
+                    client_statement.execute(params![
+                        client,
+                        state.available().to_string(),
+                        state.held().to_string(),
+                        state.total().to_string(),
+                        state.locked(),
+                    ])?;
+                }
+
+                for (transaction_id, info) in context.client_transactions(client_id) {
+                    transaction_statement.execute(params![
+                        client,
+                        u32::from(transaction_id),
+                        info.r#type.as_str(),
+                        info.amount.to_string(),
+                        transaction_state_to_sql(info.state),
+                    ])?;
+                }
+            }
+        }
+
+        transaction
+            .commit()
+            .context("Error committing processing context")
+    }
+}
+
+fn open_connection<P: AsRef<Path>>(path: P) -> Result<Connection> {
+    let path = path.as_ref();
+    Connection::open(path).with_context(|| format!("Error opening database: {}", path.display()))
+}
+
+// SQLite integers are signed 64-bit, which might not fit the widest client IDs
+#[cfg(feature = "client-id-u64")]
+fn client_id_to_sql(client_id: ClientId) -> Result<i64> {
+    i64::try_from(ClientIdRepr::from(client_id))
+        .with_context(|| format!("Client ID out of database range: {}", client_id))
+}
+
+#[cfg(not(feature = "client-id-u64"))]
+fn client_id_to_sql(client_id: ClientId) -> Result<i64> {
+    Ok(i64::from(ClientIdRepr::from(client_id)))
+}
+
+fn client_id_from_sql(value: i64) -> Result<ClientId> {
+    ClientIdRepr::try_from(value)
+        .map(ClientId::new)
+        .with_context(|| format!("Invalid client ID in database: {}", value))
+}
+
+fn decimal_from_sql(value: String) -> Result<Decimal> {
+    Decimal::from_str(&value).with_context(|| format!("Invalid amount in database: {}", value))
+}
+
+fn transaction_state_to_sql(state: TransactionState) -> &'static str {
+    match state {
+        TransactionState::Applied => "applied",
+        TransactionState::Disputed => "disputed",
+        TransactionState::ChargedBack => "charged_back",
+    }
+}
+
+fn transaction_state_from_sql(value: &str) -> Result<TransactionState> {
+    match value {
+        "applied" => Ok(TransactionState::Applied),
+        "disputed" => Ok(TransactionState::Disputed),
+        "charged_back" => Ok(TransactionState::ChargedBack),
+        _ => Err(anyhow!("Invalid transaction state in database: {}", value)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rust_decimal::Decimal;
+    use std::io::sink;
+    use tempfile::NamedTempFile;
+
+    use crate::exporter::{ClientStateCsvExporter, ClientStateExporter};
+    use crate::importer::TransactionCsvImporter;
+    use crate::model::{ClientId, ClientIdRepr, ClientState};
+    use crate::service::{ContextStore, TransactionProcessor};
+    use crate::sqlite::{SqliteClientStateExporter, SqliteContextStore};
+
+    #[test]
+    fn should_upsert_client_states() {
+        let database = NamedTempFile::new().unwrap();
+
+        let mut state = ClientState::new(ClientId::new(2));
+        state.deposit(Decimal::new(15, 1)).unwrap();
+
+        let mut exporter = SqliteClientStateExporter::from_path(database.path()).unwrap();
+        exporter.serialize(&state).unwrap();
+        exporter.finish().unwrap();
+
+        state.deposit(Decimal::from(1)).unwrap();
+        exporter.serialize(&state).unwrap();
+        exporter.finish().unwrap();
+
+        let (count, total): (i64, String) = exporter
+            .connection
+            .query_row(
+                "SELECT COUNT(*), MAX(total) FROM client_states",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .unwrap();
+        assert_eq!(count, 1);
+        assert_eq!(total, "2.5");
+    }
+
+    #[test]
+    fn should_resume_processing_from_stored_context() {
+        let database = NamedTempFile::new().unwrap();
+
+        let process = |csv: &str| {
+            let importer = TransactionCsvImporter::from_reader(csv.as_bytes());
+            let exporter = SqliteClientStateExporter::from_path(database.path()).unwrap();
+            let store = SqliteContextStore::from_path(database.path()).unwrap();
+
+            TransactionProcessor::new(importer, exporter)
+                .with_store(Box::new(store))
+                .process_transactions()
+                .unwrap();
+        };
+
+        process(
+            "type,client,tx,amount
+deposit,1,1,2
+deposit,2,2,3
+dispute,2,2,
+",
+        );
+
+        // disputes from the previous run need to be remembered
+        process(
+            "type,client,tx,amount
+withdrawal,1,3,1
+chargeback,2,2,
+",
+        );
+
+        let context = SqliteContextStore::from_path(database.path())
+            .unwrap()
+            .load()
+            .unwrap();
+
+        let mut states: Vec<_> = context.client_states().copied().collect();
+        states.sort_by_key(|state| ClientIdRepr::from(state.client_id()));
+
+        assert_eq!(states.len(), 2);
+        assert_eq!(states[0].total(), Decimal::from(1));
+        assert!(!states[0].locked());
+        assert!(states[1].total().is_zero());
+        assert!(states[1].held().is_zero());
+        assert!(states[1].locked());
+        assert_eq!(context.transactions().count(), 3);
+    }
+
+    #[test]
+    fn should_roll_back_failed_exports() {
+        let database = NamedTempFile::new().unwrap();
+
+        let mut exporter = SqliteClientStateExporter::from_path(database.path()).unwrap();
+        exporter
+            .connection
+            .execute_batch(
+                "CREATE TRIGGER reject_client BEFORE INSERT ON client_states WHEN NEW.client = 3
+                 BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+            )
+            .unwrap();
+
+        exporter
+            .serialize(&ClientState::new(ClientId::new(2)))
+            .unwrap();
+        assert!(exporter
+            .serialize(&ClientState::new(ClientId::new(3)))
+            .is_err());
+
+        assert!(exporter.connection.is_autocommit());
+        let count: i64 = exporter
+            .connection
+            .query_row("SELECT COUNT(*) FROM client_states", [], |row| row.get(0))
+            .unwrap();
+        assert_eq!(count, 0);
+    }
+
+    #[test]
+    fn should_save_only_touched_clients() {
+        let database = NamedTempFile::new().unwrap();
+
+        let process = |csv: &str| {
+            let importer = TransactionCsvImporter::from_reader(csv.as_bytes());
+            let store = SqliteContextStore::from_path(database.path()).unwrap();
+
+            TransactionProcessor::new(
+                importer,
+                ClientStateCsvExporter::new(sink(), Default::default()),
+            )
+            .with_store(Box::new(store))
+            .process_transactions()
+        };
+
+        process(
+            "type,client,tx,amount
+deposit,1,1,2
+deposit,2,2,3
+",
+        )
+        .unwrap();
+
+        // untouched clients must not be rewritten, and failed saves must not be partially applied
+        let store = SqliteContextStore::from_path(database.path()).unwrap();
+        store
+            .connection
+            .execute_batch(
+                "UPDATE context_clients SET available = '4', total = '4' WHERE client = 2;
+                 CREATE TRIGGER reject_transaction BEFORE INSERT ON context_transactions
+                 WHEN NEW.tx = 4 BEGIN SELECT RAISE(ABORT, 'rejected'); END;",
+            )
+            .unwrap();
+
+        process(
+            "type,client,tx,amount
+deposit,1,3,1
+",
+        )
+        .unwrap();
+        assert!(process(
+            "type,client,tx,amount
+deposit,1,4,1
+",
+        )
+        .is_err());
+
+        let context = SqliteContextStore::from_path(database.path())
+            .unwrap()
+            .load()
+            .unwrap();
+
+        let mut states: Vec<_> = context.client_states().copied().collect();
+        states.sort_by_key(|state| ClientIdRepr::from(state.client_id()));
+
+        assert_eq!(states[0].total(), Decimal::from(3));
+        assert_eq!(states[1].total(), Decimal::from(4));
+        assert_eq!(context.transactions().count(), 3);
+    }
+}